@@ -0,0 +1,217 @@
+//! Benchmarks of rendering steps, each compared with the simpler approach it replaced.
+//!
+//! Run with `cargo bench`, which needs libvips and pango installed, as the crate itself does.
+
+use cartomata::image::{BlendMode, Color, ImageMap, ImgBackend, Origin, TextResources};
+use cartomata::layer::{Layer, LayerStack, RenderContext, RenderOptions};
+use cartomata::text::{FontMap, Markup};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use libvips::{ops, VipsImage};
+use pango::prelude::FontMapExt;
+use std::num::NonZero;
+use std::path::PathBuf;
+
+const CARD_SIZE: (i32, i32) = (750, 1050);
+
+/// Forces libvips to compute an image, as images are otherwise evaluated lazily.
+fn compute(img: &VipsImage) -> f64 {
+    ops::avg(img).unwrap()
+}
+
+/// Small translucent squares spread over the card, like the icons and frames of a template.
+fn decorations(ib: &ImgBackend, n: usize) -> Vec<(VipsImage, i32, i32, BlendMode)> {
+    let (w, h) = CARD_SIZE;
+    (0..n)
+        .map(|i| {
+            let color = Color::from_rgba(i as f64 / n as f64, 0.5, 0.5, 0.8);
+            let img = ib.new_canvas(&color, 64, 64).unwrap();
+            let (x, y) = (i as i32 * 97 % (w - 64), i as i32 * 53 % (h - 64));
+            (img, x, y, BlendMode::Over)
+        })
+        .collect()
+}
+
+fn composite(c: &mut Criterion) {
+    let ib = ImgBackend::shared().unwrap();
+    let (w, h) = CARD_SIZE;
+    let layers = decorations(ib, 20);
+    let mut group = c.benchmark_group("composite 20 layers");
+    group.bench_function("overlay each", |b| {
+        b.iter(|| {
+            let mut img = ib.new_canvas(&Color::WHITE, w, h).unwrap();
+            for (src, x, y, mode) in layers.iter() {
+                let o = Origin::default();
+                img = ib.overlay(&img, src, *x, *y, o, o, *mode).unwrap();
+            }
+            compute(&img)
+        })
+    });
+    group.bench_function("composite_all", |b| {
+        b.iter(|| {
+            let img = ib.new_canvas(&Color::WHITE, w, h).unwrap();
+            compute(&ib.composite_all(img, layers.clone()).unwrap())
+        })
+    });
+    group.finish();
+}
+
+/// Casts and copies an image to 8-bit sRGB, as every operation of the backend did before
+/// skipping images already in that format.
+fn cast_to_srgb(img: &VipsImage) -> VipsImage {
+    let img = ops::cast(img, ops::BandFormat::Uchar).unwrap();
+    let opts = ops::CopyOptions {
+        interpretation: ops::Interpretation::Srgb,
+        width: img.get_width(),
+        height: img.get_height(),
+        bands: img.get_bands(),
+        format: ops::BandFormat::Uchar,
+        ..Default::default()
+    };
+    ops::copy_with_opts(&img, &opts).unwrap()
+}
+
+fn reinterpret(c: &mut Criterion) {
+    let ib = ImgBackend::shared().unwrap();
+    let (w, h) = CARD_SIZE;
+    let layers = decorations(ib, 10);
+    let recolor = |img: &VipsImage, cast: bool| {
+        let img = ib.set_color(img, Color::from_rgb(0.2, 0.4, 0.6)).unwrap();
+        let img = if cast { cast_to_srgb(&img) } else { img };
+        let img = ib.set_opacity(&img, 0.5).unwrap();
+        if cast {
+            cast_to_srgb(&img)
+        } else {
+            img
+        }
+    };
+    let mut group = c.benchmark_group("recolor 10 layers");
+    for (name, cast) in [("cast every step", true), ("skip normalized", false)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let img = ib.new_canvas(&Color::WHITE, w, h).unwrap();
+                let layers = layers
+                    .iter()
+                    .map(|(src, x, y, mode)| (recolor(src, cast), *x, *y, *mode))
+                    .collect();
+                compute(&ib.composite_all(img, layers).unwrap())
+            })
+        });
+    }
+    group.finish();
+}
+
+/// A line of text, which pango lays out and draws as soon as its overlay is rendered, unlike
+/// libvips operations, which are only evaluated once the card is composited.
+#[derive(Debug)]
+struct Line(usize);
+
+impl Layer for Line {
+    fn render(&self, img: VipsImage, ctx: &RenderContext) -> cartomata::Result<VipsImage> {
+        let (text, x, y, mode) = self.overlay(ctx)?.unwrap();
+        let o = Origin::default();
+        ctx.backend.overlay(&img, &text, x, y, o, o, mode)
+    }
+
+    fn overlay(
+        &self,
+        ctx: &RenderContext,
+    ) -> cartomata::Result<Option<(VipsImage, i32, i32, BlendMode)>> {
+        let markup = Markup::plain(format!("Line {} of the rules text of this card.", self.0));
+        let res = ctx.text_resources();
+        let (img, _, _) = ctx.backend.print(markup, res, "sans", 24.0, Color::BLACK, &[])?;
+        Ok(Some((img, 40, 40 + self.0 as i32 * 40, BlendMode::Over)))
+    }
+}
+
+/// A font map with any sans-serif font installed, and an image map of the card size.
+fn resources() -> (FontMap, ImageMap) {
+    let mut font_map = FontMap::new().unwrap();
+    font_map.load_font_from_name(String::from("sans"), "sans-serif", None).unwrap();
+    let img_map = ImageMap {
+        assets_folder: PathBuf::from("assets"),
+        artwork_folder: PathBuf::from("artwork"),
+        extensions: vec![String::from("png")],
+        placeholder: None,
+        card_size: CARD_SIZE,
+        background: Color::WHITE,
+    };
+    (font_map, img_map)
+}
+
+fn layer_threads(c: &mut Criterion) {
+    let (font_map, img_map) = resources();
+    let mut group = c.benchmark_group("render 20 text layers");
+    for threads in [None, NonZero::new(2), NonZero::new(4)] {
+        let options = RenderOptions { layer_threads: threads, ..Default::default() };
+        let ctx = RenderContext {
+            backend: ImgBackend::shared().unwrap(),
+            font_map: &font_map,
+            img_map: &img_map,
+            options: &options,
+            warnings: Default::default(),
+            layer_times: Default::default(),
+            outputs: Default::default(),
+        };
+        let name = match threads {
+            Some(n) => format!("{n} layer threads"),
+            None => String::from("one thread"),
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let layers = (0..20).map(|i| Box::new(Line(i)) as Box<dyn Layer>).collect();
+                compute(&LayerStack::new(layers).render(&ctx).unwrap())
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Draws text with a font map and context created for it, as `print` did before reusing the
+/// text context of each thread.
+fn print_with_new_context(text: &str, font: &pango::FontDescription) -> cairo::ImageSurface {
+    let ctx = pangocairo::FontMap::new().create_context();
+    let layout = pango::Layout::new(&ctx);
+    layout.set_font_description(Some(font));
+    layout.set_text(text);
+    let (_, rect) = layout.pixel_extents();
+    let surface =
+        cairo::ImageSurface::create(cairo::Format::ARgb32, rect.width(), rect.height()).unwrap();
+    let cr = cairo::Context::new(&surface).unwrap();
+    pangocairo::functions::show_layout(&cr, &layout);
+    drop(cr);
+    surface
+}
+
+/// Compares drawing text with a new text context each time, which loads fonts again, with
+/// `print`, which reuses the context. `print` also converts the text to a libvips image, so
+/// it does more work than the other.
+fn text_context(c: &mut Criterion) {
+    let ib = ImgBackend::shared().unwrap();
+    let (font_map, img_map) = resources();
+    let font = font_map.get_desc_pt("sans", 24.0).unwrap();
+    let lines: Vec<_> =
+        (0..20).map(|i| format!("Line {i} of the rules text of this card.")).collect();
+    let mut group = c.benchmark_group("print 20 lines");
+    group.bench_function("new context each", |b| {
+        b.iter(|| {
+            for line in &lines {
+                black_box(print_with_new_context(line, &font));
+            }
+        })
+    });
+    let res = TextResources { img_map: &img_map, font_map: &font_map, premultiplied_alpha: false };
+    group.bench_function("reused context", |b| {
+        b.iter(|| {
+            for line in &lines {
+                let markup = Markup::plain(line.as_str());
+                let (img, _, _) = ib.print(markup, res, "sans", 24.0, Color::BLACK, &[]).unwrap();
+                compute(&img);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, composite, reinterpret, layer_threads, text_context);
+criterion_main!(benches);
@@ -1,42 +1,247 @@
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::{spanned::Spanned, Data, DataStruct, DeriveInput, Fields};
+use syn::ext::IdentExt;
+use syn::punctuated::Punctuated;
+use syn::{
+    spanned::Spanned, Data, DataStruct, DeriveInput, Expr, Field, Fields, Ident, Lit, Meta, Token,
+};
 
 pub fn derive_card(ast: &DeriveInput) -> syn::Result<TokenStream> {
-    let get_method = derive_card_get_value(ast)?;
+    let rename_all = rename_all(ast)?;
+    let fields = named_fields(ast)?
+        .iter()
+        .map(|field| CardField::parse(field, rename_all))
+        .collect::<syn::Result<Vec<_>>>()?;
+    let get_method = derive_card_get_value(&fields);
+    let id_method = derive_card_id(&fields)?;
     let name = &ast.ident;
     let gen = quote! {
         impl ::cartomata::data::Card for #name {
             #get_method
+            #id_method
         }
     };
     Ok(gen)
 }
 
-pub fn derive_card_get_value(ast: &DeriveInput) -> syn::Result<TokenStream> {
-    let idents = match &ast.data {
-        Data::Struct(DataStruct {
-            fields: Fields::Named(fields),
-            ..
-        }) => Ok(fields
-            .named
-            .iter()
-            .map(|field| field.ident.as_ref().unwrap())),
+fn named_fields(ast: &DeriveInput) -> syn::Result<&Punctuated<Field, Token![,]>> {
+    match &ast.data {
+        Data::Struct(DataStruct { fields: Fields::Named(fields), .. }) => Ok(&fields.named),
         _ => Err(syn::Error::new(
             ast.span(),
             "expected struct with named fields",
         )),
-    }?;
-    let arms = idents.map(|ident| quote!( stringify!(#ident) => self.#ident.clone().into(), ));
-    let gen = quote! {
+    }
+}
+
+/// Case convention given to the fields of a struct with `#[serde(rename_all = "...")]`, or
+/// with `#[serde(rename_all(deserialize = "..."))]`, as fields are looked up by the names they
+/// are deserialized from.
+#[derive(Clone, Copy)]
+enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn from_lit(expr: &Expr) -> syn::Result<Self> {
+        let rule = match lit_str(expr)?.as_str() {
+            "lowercase" => Self::Lower,
+            "UPPERCASE" => Self::Upper,
+            "PascalCase" => Self::Pascal,
+            "camelCase" => Self::Camel,
+            "snake_case" => Self::Snake,
+            "SCREAMING_SNAKE_CASE" => Self::ScreamingSnake,
+            "kebab-case" => Self::Kebab,
+            "SCREAMING-KEBAB-CASE" => Self::ScreamingKebab,
+            _ => return Err(syn::Error::new(expr.span(), "unknown `rename_all` case")),
+        };
+        Ok(rule)
+    }
+
+    /// Renames a field, whose name is expected in snake case, as serde does.
+    fn apply(self, field: &str) -> String {
+        let pascal = || {
+            field
+                .split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(c) => c.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<String>()
+        };
+        match self {
+            Self::Lower | Self::Snake => field.to_string(),
+            Self::Upper | Self::ScreamingSnake => field.to_ascii_uppercase(),
+            Self::Pascal => pascal(),
+            Self::Camel => {
+                let pascal = pascal();
+                let mut chars = pascal.chars();
+                match chars.next() {
+                    Some(c) => c.to_lowercase().chain(chars).collect(),
+                    None => pascal,
+                }
+            }
+            Self::Kebab => field.replace('_', "-"),
+            Self::ScreamingKebab => field.replace('_', "-").to_ascii_uppercase(),
+        }
+    }
+}
+
+/// Reads the case convention given to every field of the struct, if any.
+fn rename_all(ast: &DeriveInput) -> syn::Result<Option<RenameRule>> {
+    let mut rule = None;
+    for attr in ast.attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        let metas = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+        for meta in metas {
+            match meta {
+                Meta::NameValue(nv) if nv.path.is_ident("rename_all") => {
+                    rule = Some(RenameRule::from_lit(&nv.value)?);
+                }
+                Meta::List(list) if list.path.is_ident("rename_all") => {
+                    list.parse_nested_meta(|meta| {
+                        let value = meta.value()?.parse::<Expr>()?;
+                        if meta.path.is_ident("deserialize") {
+                            rule = Some(RenameRule::from_lit(&value)?);
+                        }
+                        Ok(())
+                    })?;
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(rule)
+}
+
+fn derive_card_get_value(fields: &[CardField]) -> TokenStream {
+    let arms = fields.iter().filter(|field| !field.skip).map(|field| {
+        let CardField { ident, name, aliases, .. } = field;
+        quote!( #name #(| #aliases)* => self.#ident.clone().into(), )
+    });
+    quote! {
         fn get(&self, field: &str) -> ::cartomata::data::Value {
             match field {
                 #(#arms)*
                 _ => ::cartomata::data::Value::Nil
             }
         }
+    }
+}
+
+/// Implements `id` with the field marked with `#[card(id)]`, or else the field named `id`.
+/// Without either, the default implementation is kept.
+fn derive_card_id(fields: &[CardField]) -> syn::Result<TokenStream> {
+    let mut marked = fields.iter().filter(|field| field.id);
+    let field = match (marked.next(), marked.next()) {
+        (Some(_), Some(other)) => {
+            return Err(syn::Error::new(
+                other.ident.span(),
+                "only one field can be marked with `#[card(id)]`",
+            ))
+        }
+        (Some(field), None) => field,
+        (None, _) => match fields.iter().find(|field| field.ident == "id") {
+            Some(field) => field,
+            None => return Ok(TokenStream::new()),
+        },
     };
-    Ok(gen)
+    let ident = &field.ident;
+    Ok(quote! {
+        fn id(&self) -> String {
+            ::cartomata::data::Value::from(self.#ident.clone()).to_string()
+        }
+    })
+}
+
+/// A struct field along with how it is accessed as a card field.
+struct CardField {
+    ident: Ident,
+    /// Name by which the field is accessed with `get`.
+    name: String,
+    aliases: Vec<String>,
+    skip: bool,
+    id: bool,
+}
+
+impl CardField {
+    /// Reads a field, following `#[serde(rename, alias)]` and the `rename_all` rule of the
+    /// struct, so that fields are found by the names they are deserialized from.
+    /// `#[card(rename, alias)]` take precedence, `#[card(skip)]` hides the field from `get`
+    /// and `#[card(id)]` marks it as the card id.
+    fn parse(field: &Field, rename_all: Option<RenameRule>) -> syn::Result<Self> {
+        let ident = field.ident.clone().unwrap();
+        let mut serde_name = None;
+        let mut card_name = None;
+        let mut aliases = Vec::new();
+        let mut skip = false;
+        let mut id = false;
+        for attr in &field.attrs {
+            if attr.path().is_ident("serde") {
+                let metas =
+                    attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+                for meta in metas {
+                    match meta {
+                        Meta::NameValue(nv) if nv.path.is_ident("rename") => {
+                            serde_name = Some(lit_str(&nv.value)?);
+                        }
+                        Meta::NameValue(nv) if nv.path.is_ident("alias") => {
+                            aliases.push(lit_str(&nv.value)?);
+                        }
+                        Meta::List(list) if list.path.is_ident("rename") => {
+                            list.parse_nested_meta(|meta| {
+                                let value = meta.value()?.parse::<Expr>()?;
+                                if meta.path.is_ident("deserialize") {
+                                    serde_name = Some(lit_str(&value)?);
+                                }
+                                Ok(())
+                            })?;
+                        }
+                        _ => {}
+                    }
+                }
+            } else if attr.path().is_ident("card") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("skip") {
+                        skip = true;
+                    } else if meta.path.is_ident("id") {
+                        id = true;
+                    } else if meta.path.is_ident("rename") {
+                        card_name = Some(lit_str(&meta.value()?.parse()?)?);
+                    } else if meta.path.is_ident("alias") {
+                        aliases.push(lit_str(&meta.value()?.parse()?)?);
+                    } else {
+                        return Err(meta.error("expected `skip`, `id`, `rename` or `alias`"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        let name = card_name.or(serde_name).unwrap_or_else(|| {
+            let name = ident.unraw().to_string();
+            match rename_all {
+                Some(rule) => rule.apply(&name),
+                None => name,
+            }
+        });
+        Ok(Self { ident, name, aliases, skip, id })
+    }
+}
+
+fn lit_str(expr: &Expr) -> syn::Result<String> {
+    match expr {
+        Expr::Lit(syn::ExprLit { lit: Lit::Str(s), .. }) => Ok(s.value()),
+        _ => Err(syn::Error::new(expr.span(), "expected string literal")),
+    }
 }
 
 pub fn derive_lua_layer(ast: &DeriveInput) -> syn::Result<TokenStream> {
@@ -5,7 +5,7 @@ mod expand;
 use proc_macro::TokenStream;
 use syn::{parse_macro_input, DeriveInput};
 
-#[proc_macro_derive(Card)]
+#[proc_macro_derive(Card, attributes(card))]
 pub fn card(input: TokenStream) -> TokenStream {
     let ast: DeriveInput = parse_macro_input!(input);
     expand::derive_card(&ast)
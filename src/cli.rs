@@ -2,21 +2,29 @@
 mod card;
 mod config;
 mod decode;
+mod derived;
+mod mapped;
 mod output;
+mod overrides;
 mod template;
 
-pub use crate::cli::card::DynCard;
-use crate::cli::config::Config;
-use crate::cli::output::Resize;
-use crate::cli::template::{DynTemplate, SourceType};
-use crate::data::Predicate;
-use crate::pipeline::{Pipeline, LogVisitor, ParallelismOptions};
+pub use crate::cli::card::{CaseInsensitiveCard, DynCard};
+pub use crate::cli::config::Config;
+pub use crate::cli::decode::{LuaDecoder, LuaDecoderFactory};
+pub use crate::cli::template::{DynTemplate, SourceType};
+use crate::cli::config::FontConfig;
+use crate::cli::output::{OutputFormat, Resize};
+use crate::data::{Predicate, Value};
+use crate::pipeline::{Pipeline, LogVisitor, ParallelismOptions, ReportVisitor};
 use crate::logs;
-use crate::Error;
+use crate::text::{FontMap, FontPath};
+use crate::{Error, Result};
 
 use clap::Parser;
+use std::fs::{self, File};
 use std::num::NonZero;
 use std::path::PathBuf;
+use std::thread::JoinHandle;
 
 /// Render card images automatically from code defined templates.
 #[derive(Debug, Parser)]
@@ -36,19 +44,27 @@ pub struct Cli {
     #[arg(short, long, value_enum)]
     pub source: Option<SourceType>,
 
-    /// Input data path
-    #[arg(short, long)]
-    pub input: PathBuf,
+    /// Input data path. With the `remote` feature, it can also be an `http://` or `https://`
+    /// URL to a CSV file, such as a sheet published online, which may need `--source csv`
+    #[arg(short, long, required_unless_present = "list_fonts")]
+    pub input: Option<PathBuf>,
 
     /// Output images path, defaults to the current directory.
     #[arg(short, long)]
     pub output: Option<PathBuf>,
 
-    /// Optionally filters input data
+    /// Optionally filters input data. With `@PATH`, the filter is read from that file, where
+    /// it can span several lines, with comments starting at `--`
     #[arg(short, long)]
     pub filter: Option<String>,
 
-    /// Optionally resizes output
+    /// Gives a value to a parameter of the filter, written there as `:NAME`. Values are read
+    /// as integers, numbers or booleans when possible, and as strings otherwise
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, Value)>,
+
+    /// Optionally resizes output: `WxH` stretches to that size, `WxH>` only shrinks
+    /// keeping the aspect ratio, and `N%` scales by a percentage
     #[arg(long)]
     pub resize: Option<Resize>,
 
@@ -57,23 +73,165 @@ pub struct Cli {
     #[arg(long)]
     pub ext: Option<String>,
 
-    /// Number of worker threads
-    #[arg(short, long, default_value_t = NonZero::new(4).unwrap())]
-    pub workers: NonZero<usize>,
+    /// Text added before the file name of every output, e.g. `draft_`
+    #[arg(long)]
+    pub name_prefix: Option<String>,
+
+    /// Text added after the file name of every output, before its extension, e.g. `_draft`
+    #[arg(long)]
+    pub name_suffix: Option<String>,
+
+    /// Resolution written to output images, in dots per inch, e.g. 300 for print
+    #[arg(long)]
+    pub dpi: Option<f64>,
+
+    /// Opacity of the whole card, between 0 and 1, e.g. to export faded previews
+    #[arg(long)]
+    pub opacity: Option<f64>,
+
+    /// Text stamped diagonally over every card, e.g. `SAMPLE`
+    #[arg(long)]
+    pub watermark: Option<String>,
+
+    /// Print bleed in pixels, filled by extending the card edges
+    #[arg(long)]
+    pub bleed: Option<i32>,
+
+    /// Surrounds each card with crop marks
+    #[arg(long)]
+    pub crop_marks: bool,
+
+    /// Output format
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Number of worker threads, where `0` or `auto` uses every available core
+    #[arg(short, long, default_value_t = 4, value_parser = parse_workers)]
+    pub workers: usize,
+
+    /// Number of threads rendering the layers of each card concurrently, which may help
+    /// with cards made of many heavy layers
+    #[arg(long)]
+    pub layer_threads: Option<NonZero<usize>>,
 
     /// Maximum number of cards to be read at a time
     #[arg(long)]
-    pub batch: Option<NonZero<usize>>
+    pub batch: Option<NonZero<usize>>,
+
+    /// Adjusts the number of cards read at a time while running, between `MIN` and `MAX`,
+    /// keeping workers fed without reading more cards than needed
+    #[arg(long, value_name = "MIN:MAX", value_parser = parse_batch_bounds)]
+    pub adaptive_batch: Option<(NonZero<usize>, NonZero<usize>)>,
+
+    /// Maximum number of cards held in memory at a time, including the ones being rendered
+    #[arg(long)]
+    pub max_in_flight: Option<NonZero<usize>>,
+
+    /// Skips cards whose output is newer than the input data, decode scripts and template
+    /// config, rendering only the outdated ones
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Writes each card after every layer to `DIR/{card}/{n}-{kind}.png`, to debug templates
+    #[arg(long, value_name = "DIR")]
+    pub dump_layers: Option<PathBuf>,
+
+    /// Seed of the random generator available to decode scripts, replacing the one in the
+    /// template config. Each card is decoded with a generator seeded by this and its identity,
+    /// so a run gives the same images whatever the number of workers, while messages about
+    /// cards are logged in the order cards were read
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Stops at the first card that fails to render, exiting with an error, instead of going
+    /// on with the other cards
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Reads the decode scripts again before each card, so that edits take effect without
+    /// restarting. Implies a single worker.
+    #[arg(long)]
+    pub reload: bool,
+
+    /// Logs plain lines, without colors nor an animated progress bar, as is also done when
+    /// `NO_COLOR` is set or stderr is not a terminal
+    #[arg(long, visible_alias = "no-progress")]
+    pub no_color: bool,
+
+    /// Writes the time spent on each card and each of its layers to a CSV file, to find which
+    /// are slow
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// Prints the name each font of the template resolved to on this machine, or why it
+    /// failed to load, and exits without rendering
+    #[arg(long)]
+    pub list_fonts: bool,
+
+    #[cfg(unix)]
+    /// File descriptor to write `progress <done> <total>` lines to, other than stdin, stdout
+    /// and stderr
+    #[arg(long, value_name = "FD", value_parser = parse_progress_fd)]
+    pub progress_fd: Option<i32>,
+}
+
+/// Parses a file descriptor for progress lines, which can't be one of the standard streams.
+#[cfg(unix)]
+fn parse_progress_fd(s: &str) -> std::result::Result<i32, String> {
+    let fd: i32 = s.parse().map_err(|e| format!("`{s}` is not a file descriptor: {e}"))?;
+    if fd < 3 {
+        return Err(format!("{fd} is a standard stream, not a file descriptor of its own"));
+    }
+    Ok(fd)
+}
+
+/// Parses the number of workers, with `auto` standing for `0`.
+fn parse_workers(s: &str) -> std::result::Result<usize, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(0);
+    }
+    s.parse().map_err(|_| format!("`{s}` is not a number of workers nor `auto`"))
+}
+
+/// Parses a filter parameter, as `NAME=VALUE`.
+fn parse_param(s: &str) -> std::result::Result<(String, Value), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("`{s}` is not in the form NAME=VALUE"))?;
+    let name = name.trim().trim_start_matches(':').to_string();
+    let value = if let Ok(v) = value.parse::<i64>() {
+        Value::Int(v)
+    } else if let Ok(v) = value.parse::<f64>() {
+        if !v.is_finite() {
+            return Err(format!("`{value}` is not a finite number"));
+        }
+        Value::Float(v)
+    } else if let Ok(v) = value.to_lowercase().parse::<bool>() {
+        Value::Bool(v)
+    } else {
+        Value::Str(value.to_string())
+    };
+    Ok((name, value))
+}
+
+/// Parses the bounds of an adaptive batch size, as `MIN:MAX`.
+fn parse_batch_bounds(
+    s: &str,
+) -> std::result::Result<(NonZero<usize>, NonZero<usize>), String> {
+    let expected = || format!("`{s}` is not in the form MIN:MAX, with positive integers");
+    let (min, max) = s.split_once(':').ok_or_else(expected)?;
+    let min: NonZero<usize> = min.trim().parse().map_err(|_| expected())?;
+    let max: NonZero<usize> = max.trim().parse().map_err(|_| expected())?;
+    if min > max {
+        return Err(format!("`{s}` has a minimum greater than its maximum"));
+    }
+    Ok((min, max))
 }
 
 macro_rules! unwrap {
     ($res:expr) => {
         $res.unwrap_or_else(|e| {
-            panic!(
-                "{}[ERROR]{} {e}",
-                logs::ERR_COLOR.fg_str(),
-                termion::style::Reset
-            )
+            panic!("{} {e}", logs::paint(logs::ERR_COLOR, "[ERROR]"))
         })
     };
 }
@@ -89,29 +247,149 @@ impl Cli {
         }));
 
         let cli = Self::parse();
+        logs::set_plain(cli.no_color);
         let (folder, config) = unwrap!(Config::find(cli.template.as_ref()));
+        if cli.list_fonts {
+            unwrap!(Self::list_fonts(config.font));
+            return;
+        }
+        let input = cli.input.expect("input is required unless listing fonts");
+        let list_delimiter = config.source.list_delimiter.clone();
 
-        let mut template = unwrap!(DynTemplate::from_config(config, folder));
+        let mut template = unwrap!(DynTemplate::from_config(config, folder.clone()));
         template.configure_output(cli.output, cli.resize, cli.ext);
+        template.set_name_affixes(cli.name_prefix, cli.name_suffix);
+        template.set_format(cli.format);
+        template.set_dpi(cli.dpi);
+        template.set_opacity(cli.opacity);
+        template.set_watermark(cli.watermark);
+        template.set_print_margins(cli.bleed, cli.crop_marks);
+        template.set_reload(cli.reload);
+        template.set_seed(cli.seed);
+        template.set_layer_threads(cli.layer_threads);
+        template.set_dump_layers(cli.dump_layers);
+        if cli.incremental {
+            unwrap!(template.set_incremental(&folder, &input));
+        }
+
+        let filter = cli.filter.as_ref().map(|f| {
+            let filter = match f.strip_prefix('@') {
+                Some(path) => unwrap!(fs::read_to_string(path)
+                    .map_err(|e| Error::filter_open(path, e))),
+                None => f.clone(),
+            };
+            let filter = unwrap!(Predicate::from_string(&filter)).with_delimiter(&list_delimiter);
+            let filter = cli
+                .params
+                .iter()
+                .fold(filter, |filter, (name, value)| filter.bind(name, value.clone()));
+            unwrap!(filter.ensure_bound());
+            filter
+        });
 
-        let filter = cli
-            .filter
-            .as_ref()
-            .map(|f| unwrap!(Predicate::from_string(f)));
-
-        let source_key = (cli.source, cli.input);
-        let v_handle = if cli.workers.get() > 1 {
-            let opt = ParallelismOptions::new(cli.workers).with_batch_size(cli.batch);
-            let (visitor, handle) = LogVisitor::new(opt.n_workers());
-            let pipeline = Pipeline::new(template, visitor);
-            unwrap!(unwrap!(pipeline.run_parallel(source_key, filter, opt)).join());
-            handle
+        #[cfg(unix)]
+        let progress_sink = cli.progress_fd.map(|fd| {
+            // opened again instead of taken over, so that a descriptor that isn't open fails
+            // here, and the descriptor itself is never closed by this process
+            let file = fs::OpenOptions::new().append(true).open(format!("/dev/fd/{fd}"));
+            unwrap!(file.map_err(Error::io_error))
+        });
+        #[cfg(not(unix))]
+        let progress_sink = None;
+
+        let source_key = (cli.source, input);
+        let workers =
+            NonZero::new(cli.workers).unwrap_or_else(ParallelismOptions::available_workers);
+        // the report is shared with the pipeline, so that what was collected is still written
+        // if the pipeline fails
+        let report = cli.report.as_ref().map(|_| ReportVisitor::new());
+        let (v_handle, result) = if workers.get() > 1 && !cli.reload {
+            let opt = ParallelismOptions::new(workers)
+                .with_batch_size(cli.batch)
+                .with_adaptive_batch(cli.adaptive_batch)
+                .with_max_in_flight(cli.max_in_flight);
+            let (visitor, handle) = Self::log_visitor(opt.n_workers(), progress_sink);
+            let pipeline = Pipeline::new(template, (visitor, report.clone()))
+                .with_fail_fast(cli.fail_fast);
+            let result = pipeline
+                .run_parallel(source_key, filter, opt)
+                .and_then(|handle| handle.join())
+                .map(|_| ());
+            (handle, result)
         } else {
-            let (visitor, handle) = LogVisitor::new(0);
-            let pipeline = Pipeline::new(template, visitor);
-            pipeline.run(source_key, filter);
-            handle
+            let (visitor, handle) = Self::log_visitor(0, progress_sink);
+            let pipeline = Pipeline::new(template, (visitor, report.clone()))
+                .with_fail_fast(cli.fail_fast);
+            let result = if cli.reload {
+                pipeline.try_run_reloading(source_key, filter)
+            } else {
+                pipeline.try_run(source_key, filter)
+            };
+            (handle, result.map(|_| ()))
         };
-        unwrap!(unwrap!(v_handle.join().map_err(|_| Error::thread_join(0))));
+        if let (Some(path), Some(report)) = (&cli.report, &report) {
+            unwrap!(report.write_csv(path));
+        }
+        // the log is finished before failing, so that every message about the cards rendered
+        // so far is shown
+        let logged = v_handle.join().map_err(|e| Error::thread_join(0, e));
+        unwrap!(result);
+        unwrap!(unwrap!(logged));
+    }
+
+    /// Loads each font on its own, so that every one is listed even if some fail, printing
+    /// its key, what the config asks for and the name fontconfig resolved it to. Families
+    /// resolved to a name that doesn't start with the family are marked as substituted, as
+    /// fontconfig falls back to another font when a family is not installed.
+    fn list_fonts(config: FontConfig) -> Result<()> {
+        let mut font_map = FontMap::new()?;
+        let mut fonts: Vec<_> = config.fonts.into_iter().collect();
+        fonts.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (key, font) in fonts {
+            let (requested, result) = match font {
+                FontPath::Path(path) => (
+                    format!("file {}", path.display()),
+                    font_map.load_font_from_file(key.clone(), &path).map(|_| None),
+                ),
+                FontPath::Desc { name, style } => {
+                    let requested = match &style {
+                        Some(style) => format!("family {name:?}, style {style:?}"),
+                        None => format!("family {name:?}"),
+                    };
+                    let result = font_map
+                        .load_font_from_name(key.clone(), &name, style.as_deref())
+                        .map(|_| Some(name));
+                    (requested, result)
+                }
+            };
+            let resolved = match result {
+                Ok(family) => {
+                    let resolved = &font_map.loaded()[&key];
+                    match family {
+                        Some(family)
+                            if !resolved.to_lowercase().starts_with(&family.to_lowercase()) =>
+                        {
+                            format!("{resolved} (substituted)")
+                        }
+                        _ => resolved.clone(),
+                    }
+                }
+                Err(e) => format!("error: {e}"),
+            };
+            println!("{key}: {requested} -> {resolved}");
+        }
+        let default = config.default_key.as_deref().unwrap_or(font_map.default_key());
+        println!("default: {default}");
+        Ok(())
+    }
+
+    fn log_visitor(
+        n_workers: usize,
+        progress_sink: Option<File>,
+    ) -> (LogVisitor, JoinHandle<Result<()>>) {
+        match progress_sink {
+            Some(sink) => LogVisitor::with_sink(n_workers, sink),
+            None => LogVisitor::new(n_workers),
+        }
     }
 }
@@ -2,11 +2,15 @@
 
 use crate::data::Value;
 use crate::data::Card;
-use mlua::{IntoLua, Lua, Result as LuaResult, Value as LuaValue};
+use mlua::{
+    Error as LuaError, IntoLua, Lua, MetaMethod, Result as LuaResult, UserData, UserDataMethods,
+    Value as LuaValue,
+};
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::OnceLock;
 
 
 impl<'lua> IntoLua<'lua> for Value {
@@ -21,12 +25,228 @@ impl<'lua> IntoLua<'lua> for Value {
     }
 }
 
+/// A card whose fields are only known at runtime.
+///
+/// By default fields are matched exactly. A card made case-insensitive with
+/// [`DynCard::ignore_case`] keeps a lowercased index of its fields, so that looking up
+/// `power` finds a field named `Power` when there is no exact match.
 #[derive(Debug, Clone)]
-pub struct DynCard(pub HashMap<String, Value>);
+pub struct DynCard {
+    fields: HashMap<String, Value>,
+    index: Option<HashMap<String, String>>,
+    /// Field names by their name in snake case, built the first time a field is looked up
+    /// by it.
+    aliases: OnceLock<HashMap<String, String>>,
+}
+
+impl DynCard {
+    pub fn new(fields: HashMap<String, Value>) -> Self {
+        Self { fields, index: None, aliases: OnceLock::new() }
+    }
+
+    /// Makes field lookups fall back to ignoring case.
+    pub fn ignore_case(mut self) -> Self {
+        // fields differing only in case are found by the first one in order of their names
+        let mut index = HashMap::new();
+        for (k, _) in self.fields() {
+            index.entry(k.to_lowercase()).or_insert_with(|| k.to_string());
+        }
+        self.index = Some(index);
+        self
+    }
+
+    /// Returns a reference to the value of a field, if present.
+    pub fn field(&self, field: &str) -> Option<&Value> {
+        self.fields.get(field).or_else(|| {
+            let key = self.index.as_ref()?.get(&field.to_lowercase())?;
+            self.fields.get(key)
+        })
+    }
+
+    /// Sets the value of a field, adding it if missing.
+    pub fn set(&mut self, field: String, value: Value) {
+        if let Some(index) = &mut self.index {
+            index.entry(field.to_lowercase()).or_insert_with(|| field.clone());
+        }
+        if !self.fields.contains_key(&field) {
+            self.aliases.take();
+        }
+        self.fields.insert(field, value);
+    }
+
+    /// Iterates over every field name and value, in order of their names, so that anything
+    /// built from them is the same between runs.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &Value)> {
+        let mut fields: Vec<_> = self.fields.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        fields.sort_unstable_by_key(|(k, _)| *k);
+        fields.into_iter()
+    }
+
+    /// Like `field`, but also finds fields by their name in snake case, e.g. `my_field` finds
+    /// a field named `MyField` or `My Field`, when there is no field with that exact name.
+    /// When several fields share that name, the first one in order of their names is found.
+    pub fn field_or_alias(&self, field: &str) -> Option<&Value> {
+        self.field(field).or_else(|| self.fields.get(self.aliases().get(field)?))
+    }
+
+    fn aliases(&self) -> &HashMap<String, String> {
+        self.aliases.get_or_init(|| {
+            let mut aliases = HashMap::new();
+            for (k, _) in self.fields() {
+                aliases.entry(snake_case(k)).or_insert_with(|| k.to_string());
+            }
+            aliases
+        })
+    }
+
+    /// Returns a field as a string slice, if it is a string.
+    pub fn get_str(&self, field: &str) -> Option<&str> {
+        self.field(field)?.as_str()
+    }
+
+    /// Returns a field as an integer, converting floats and strings when possible.
+    pub fn get_i64(&self, field: &str) -> Option<i64> {
+        self.field(field)?.as_i64()
+    }
+
+    /// Returns a field as a float, converting integers and strings when possible.
+    pub fn get_f64(&self, field: &str) -> Option<f64> {
+        self.field(field)?.as_f64()
+    }
+
+    /// Returns a field as a boolean, converting strings when possible.
+    pub fn get_bool(&self, field: &str) -> Option<bool> {
+        self.field(field)?.as_bool()
+    }
+}
 
 impl Card for DynCard {
     fn get(&self, field: &str) -> Value {
-        self.0.get(field).cloned().unwrap_or_default()
+        self.field(field).cloned().unwrap_or_default()
+    }
+}
+
+/// Cards are exposed to Lua as userdata. Fields are read by indexing, e.g. `card.power`, and
+/// can be converted with the methods `card:number(field)`, `card:int(field)`,
+/// `card:str(field)` and `card:bool(field)`, which return `nil` if the conversion fails.
+/// Fields named like those methods can still be read with `card:get(field)`.
+///
+/// Fields can also be read by their name in snake case, so that a column named
+/// `Attack Power` or `AttackPower` is found with `card.attack_power`, and iterating over a
+/// card with `pairs` gives both names.
+///
+/// Fields can be assigned as with a table, e.g. `card.power = card:int("power") + 1`, which
+/// adds the field if missing. Only booleans, numbers, strings and `nil` can be stored, and
+/// the change is only seen by the script decoding that card.
+impl UserData for DynCard {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("get", |_, card, field: String| {
+            Ok(card.field_or_alias(&field).cloned().unwrap_or_default())
+        });
+        methods.add_method("number", |_, card, field: String| {
+            Ok(card.field_or_alias(&field).and_then(Value::as_f64))
+        });
+        methods.add_method("int", |_, card, field: String| {
+            Ok(card.field_or_alias(&field).and_then(Value::as_i64))
+        });
+        methods.add_method("bool", |_, card, field: String| {
+            Ok(card.field_or_alias(&field).and_then(Value::as_bool))
+        });
+        methods.add_method("str", |_, card, field: String| {
+            Ok(card.field_or_alias(&field).and_then(|v| match v {
+                Value::Nil => None,
+                v => Some(v.to_string()),
+            }))
+        });
+        methods.add_meta_method(MetaMethod::Index, |_, card, key: LuaValue| {
+            Ok(key
+                .as_str()
+                .and_then(|k| card.field_or_alias(k).cloned())
+                .unwrap_or_default())
+        });
+        methods.add_meta_method_mut(
+            MetaMethod::NewIndex,
+            |_, card, (key, value): (String, LuaValue)| {
+                let value = match value {
+                    LuaValue::Nil => Value::Nil,
+                    LuaValue::Boolean(v) => Value::Bool(v),
+                    LuaValue::Integer(v) => Value::Int(v),
+                    LuaValue::Number(v) => Value::Float(v),
+                    LuaValue::String(v) => Value::Str(v.to_str()?.to_string()),
+                    v => {
+                        return Err(LuaError::FromLuaConversionError {
+                            from: v.type_name(),
+                            to: "card field",
+                            message: Some(format!("can't assign it to field `{key}`")),
+                        })
+                    }
+                };
+                card.set(key, value);
+                Ok(())
+            },
+        );
+        // fields are given in order of their names, each followed by its alias, as the order
+        // `next` gives would change between runs
+        methods.add_meta_method(MetaMethod::Pairs, |lua, card, ()| {
+            let mut entries = Vec::with_capacity(card.fields.len() * 2);
+            for (k, v) in card.fields() {
+                entries.push((k.to_string(), v.clone()));
+                let alias = snake_case(k);
+                if !card.fields.contains_key(&alias) && !entries.iter().any(|(e, _)| *e == alias) {
+                    entries.push((alias, v.clone()));
+                }
+            }
+            let mut entries = entries.into_iter();
+            let iter = lua.create_function_mut(move |_, ()| Ok(entries.next().unzip()))?;
+            Ok((iter, LuaValue::Nil, LuaValue::Nil))
+        });
+    }
+}
+
+/// Converts a field name to snake case, splitting words at spaces, punctuation and changes
+/// of case, e.g. `Attack Power`, `attack-power` and `AttackPower` all become `attack_power`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len());
+    let mut split = false;
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            split = !snake.is_empty();
+            continue;
+        }
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower) {
+                split = true;
+            }
+        }
+        if split && !snake.is_empty() {
+            snake.push('_');
+        }
+        split = false;
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+/// A card that is deserialized already ignoring case, so that sources filtering cards
+/// while reading them also match fields regardless of case.
+#[derive(Debug, Clone)]
+pub struct CaseInsensitiveCard(pub DynCard);
+
+impl Card for CaseInsensitiveCard {
+    fn get(&self, field: &str) -> Value {
+        self.0.get(field)
+    }
+}
+
+impl<'de> Deserialize<'de> for CaseInsensitiveCard {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        DynCard::deserialize(deserializer).map(|card| CaseInsensitiveCard(card.ignore_case()))
     }
 }
 
@@ -47,7 +267,7 @@ impl<'de> Visitor<'de> for DynCardVisitor {
         while let Some((k, v)) = map.next_entry::<String, Value>()? {
             items.insert(k, v);
         }
-        Ok(DynCard(items))
+        Ok(DynCard::new(items))
     }
 }
 
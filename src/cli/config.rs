@@ -4,8 +4,14 @@
 use crate::data::source::CsvSourceConfig;
 #[cfg(feature = "sqlite")]
 use crate::data::source::SqliteSourceConfig;
+#[cfg(feature = "xlsx")]
+use crate::data::source::XlsxSourceConfig;
+use crate::cli::output::OutputProfile;
+use crate::cli::template::SourceType;
+use crate::data::DEFAULT_LIST_DELIMITER;
 use crate::error::{Error, Result};
-use crate::image::Color;
+use crate::image::{BleedMode, Color, IccProfile};
+use crate::layer::Watermark;
 use crate::text::FontPath;
 
 use serde::{
@@ -24,8 +30,34 @@ pub struct Config {
     pub base: Base,
     pub assets: Option<AssetsConfig>,
     pub artwork: Option<ArtworkConfig>,
-    pub font: HashMap<String, FontPath>,
+    pub font: FontConfig,
     pub source: DataSourceConfig,
+    /// Additional decode scripts, each used for the cards matching its filter.
+    #[serde(default)]
+    pub decoder: Vec<DecoderConfig>,
+    pub watermark: Option<Watermark>,
+    /// ICC profile output images are converted to, relative to the template folder, such
+    /// as a CMYK profile for print.
+    pub cmyk: Option<IccProfile>,
+    /// Fields added to each card, computed from the other fields by an expression.
+    #[serde(default)]
+    pub derived: HashMap<String, String>,
+    /// Named colors and tables of colors, available to decoders in the `cartomata.palette`
+    /// module, e.g. `palette.primary`, or `palette.element[card.element]` to pick a color by
+    /// a card field. Layer color fields also accept them as `@primary` or `@element.fire`.
+    #[serde(default)]
+    pub palette: HashMap<String, PaletteEntry>,
+    /// Additional versions of each output image, such as thumbnails, by name.
+    #[serde(default)]
+    pub output: HashMap<String, OutputProfile>,
+}
+
+/// An entry of the palette, either a single color or a table of colors by name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteEntry {
+    Color(Color),
+    Table(HashMap<String, Color>),
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -39,6 +71,29 @@ pub struct Base {
     pub ext: Vec<String>,
     #[serde(default = "default_identity")]
     pub identity: String,
+    /// Seed mixed with each card identity to seed the random generator available to decoders.
+    #[serde(default)]
+    pub seed: u64,
+    /// Whether card fields are looked up ignoring case, when not found with the exact name.
+    #[serde(default)]
+    pub ignore_case: bool,
+    /// Whether assets and artwork are stored with premultiplied alpha.
+    #[serde(default)]
+    pub premultiplied_alpha: bool,
+    /// Radius in pixels of rounded corners cut from each card, within the trim box when there
+    /// is bleed.
+    pub corner_radius: Option<f64>,
+    /// Print bleed in pixels, added around each card.
+    pub bleed: Option<i32>,
+    #[serde(default)]
+    pub bleed_mode: BleedMode,
+    #[serde(default)]
+    pub crop_marks: bool,
+    /// Resolution written to output images, in dots per inch.
+    pub dpi: Option<f64>,
+    /// TOML or CSV file with fields merged over the cards of the same id, relative to the
+    /// template folder.
+    pub overrides: Option<PathBuf>,
 }
 
 fn default_extensions() -> Vec<String> {
@@ -59,6 +114,16 @@ pub struct CardSize {
     pub height: i32,
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FontConfig {
+    /// Font used by text layers that don't set one. Defaults to the only font configured, if
+    /// there is a single one, or else to the font named `default`.
+    pub default_key: Option<String>,
+    #[serde(flatten)]
+    pub fonts: HashMap<String, FontPath>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct AssetsConfig {
     pub path: Option<PathBuf>,
@@ -70,10 +135,31 @@ pub struct ArtworkConfig {
     pub path: PathBuf,
 }
 
+/// A decode script used instead of `decode.lua` for the cards matching `filter`. When more
+/// than one filter matches a card, the first one listed is used.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DecoderConfig {
+    pub filter: String,
+    pub path: PathBuf,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct DataSourceConfig {
     pub sqlite: Option<SqliteSourceConfig>,
     pub csv: Option<CsvSourceConfig>,
+    #[cfg(feature = "xlsx")]
+    pub xlsx: Option<XlsxSourceConfig>,
+    /// Source types of input files by extension, e.g. `dat = "sqlite"`, tried before the
+    /// extensions known by default when the source type is not given.
+    #[serde(default)]
+    pub extensions: HashMap<String, SourceType>,
+    /// Delimiter between the items of fields holding lists, as matched by `HAS` in filters.
+    #[serde(default = "default_list_delimiter", rename = "list-delimiter")]
+    pub list_delimiter: String,
+}
+
+fn default_list_delimiter() -> String {
+    String::from(DEFAULT_LIST_DELIMITER)
 }
 
 impl Config {
@@ -94,27 +180,50 @@ impl Config {
         let path = path.as_ref();
         let content = fs::read_to_string(path)
             .map_err(|e| Error::config_open(path, e))?;
-        let raw: Self = toml::from_str(&content)
-            .map_err(|e| Error::config_deser(path, e))?;
         let folder = path
             .parent()
             .expect("toml file is inside some folder")
             .to_path_buf();
+        let config = Self::parse(&content, &folder, path)?;
+        Ok((folder, config))
+    }
+
+    /// Parses a config given in code, as if read from `template.toml` in `folder`, which
+    /// relative paths in the config are resolved against.
+    pub fn from_toml(content: &str, folder: impl AsRef<Path>) -> Result<Self> {
+        let folder = folder.as_ref();
+        Self::parse(content, folder, &folder.join("template.toml"))
+    }
+
+    fn parse(content: &str, folder: &Path, path: &Path) -> Result<Self> {
+        let raw: Self = toml::from_str(content)
+            .map_err(|e| Error::config_deser(path, e))?;
+        let folder = folder.to_path_buf();
         let fonts = raw
             .font
+            .fonts
             .into_iter()
             .map(|(k, v)| (k, Self::prefix_font_path(&folder, v)))
             .collect();
-        Ok((
-            folder,
-            Self {
-                base: raw.base,
-                assets: raw.assets,
-                artwork: raw.artwork,
-                font: fonts,
-                source: raw.source,
-            },
-        ))
+        let mut base = raw.base;
+        base.overrides = base.overrides.map(|path| folder.join(path));
+        let cmyk = raw.cmyk.map(|icc| IccProfile {
+            profile: folder.join(icc.profile),
+            ..icc
+        });
+        Ok(Self {
+            base,
+            assets: raw.assets,
+            artwork: raw.artwork,
+            font: FontConfig { default_key: raw.font.default_key, fonts },
+            source: raw.source,
+            decoder: raw.decoder,
+            watermark: raw.watermark,
+            cmyk,
+            derived: raw.derived,
+            palette: raw.palette,
+            output: raw.output,
+        })
     }
 
     #[cfg(target_os = "windows")]
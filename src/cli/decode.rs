@@ -1,46 +1,217 @@
 //! Implementation for the dynamic decoder, using Lua scripts.
 
 use crate::abox::AliasBox;
+use crate::cli::config::PaletteEntry;
 use crate::cli::DynCard;
+use crate::data::{identify, Predicate, DEFAULT_LIST_DELIMITER};
 use crate::decode::Decoder;
 use crate::error::{Error, Result};
-use crate::layer::{ArtworkLayer, AssetLayer, LabelLayer, TextLayer};
+use crate::image::{Color, Palette};
+use crate::layer::{ArtworkLayer, AssetLayer, LabelLayer, RepeatLayer, TextLayer};
 use crate::layer::{Layer, LayerStack};
+use crate::text::NumberFormat;
 
 use mlua::{
-    Error as LuaError, FromLua, Function, Lua, Result as LuaResult, Table, UserData,
+    Error as LuaError, FromLua, Function, Lua, LuaSerdeExt, Result as LuaResult, Table, UserData,
     Value as LuaValue, Variadic,
 };
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub struct LuaDecoderFactory {
     folder: PathBuf,
+    /// Path of the main script, or `None` when it was given in code.
+    path: Option<PathBuf>,
     chunk: String,
+    /// Scripts used for the cards matching each filter, with their paths, or `None` when
+    /// given in code.
+    chunks: Vec<(Predicate, Option<PathBuf>, String)>,
+    identity: String,
+    seed: u64,
+    reload: bool,
+    palettes: Palettes,
+    list_delimiter: String,
 }
 
+/// Named colors and tables of colors.
+type Palettes = HashMap<String, PaletteEntry>;
+
 impl LuaDecoderFactory {
-    pub fn new(folder: PathBuf) -> Result<Self> {
-        let mut path = folder.clone();
-        path.push("decode.lua");
-        let chunk = fs::read_to_string(&path)
-            .map_err(|e| Error::decoder_open(path, e))?;
-        Ok(Self { folder, chunk })
+    pub fn new(folder: PathBuf, identity: String, seed: u64) -> Result<Self> {
+        let path = folder.join("decode.lua");
+        let chunk = Self::read(&path)?;
+        let mut factory = Self::from_chunk(folder, chunk, identity, seed);
+        factory.path = Some(path);
+        Ok(factory)
+    }
+
+    /// Creates a factory whose main script is given in code instead of read from
+    /// `decode.lua`, e.g. to embed a template in a program. Scripts can still `require`
+    /// modules from `folder`.
+    pub fn from_chunk(folder: PathBuf, chunk: String, identity: String, seed: u64) -> Self {
+        Self {
+            folder,
+            path: None,
+            chunk,
+            chunks: Vec::new(),
+            identity,
+            seed,
+            reload: false,
+            palettes: Palettes::new(),
+            list_delimiter: String::from(DEFAULT_LIST_DELIMITER),
+        }
+    }
+
+    /// Adds a decode script, relative to the template folder, to be used for the cards
+    /// matching `filter` instead of `decode.lua`. Scripts are tried in the order added.
+    pub fn with_decoder(&mut self, filter: &str, path: impl AsRef<Path>) -> Result<()> {
+        let path = self.folder.join(path);
+        let chunk = Self::read(&path)?;
+        self.push_decoder(filter, Some(path), chunk)
+    }
+
+    /// Adds a decode script given in code, to be used for the cards matching `filter`, the
+    /// same as [`with_decoder`](Self::with_decoder).
+    pub fn with_decoder_chunk(&mut self, filter: &str, chunk: String) -> Result<()> {
+        self.push_decoder(filter, None, chunk)
+    }
+
+    fn push_decoder(&mut self, filter: &str, path: Option<PathBuf>, chunk: String) -> Result<()> {
+        let filter = Predicate::from_string(filter)?.with_delimiter(&self.list_delimiter);
+        filter.ensure_bound()?;
+        self.chunks.push((filter, path, chunk));
+        Ok(())
+    }
+
+    /// Sets the delimiter between the items of list fields, for the filters of the decode
+    /// scripts added afterwards.
+    pub fn set_list_delimiter(&mut self, delimiter: impl Into<String>) {
+        self.list_delimiter = delimiter.into();
+    }
+
+    /// Sets whether scripts are read again from disk every time a decoder is created, instead
+    /// of reusing the ones read when the factory was configured. Scripts given in code are
+    /// always reused.
+    pub fn set_reload(&mut self, reload: bool) {
+        self.reload = reload;
+    }
+
+    /// Sets the seed mixed with each card identity to seed the random generator of decoders.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Sets the palettes exposed to scripts in the `cartomata.palette` module.
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        self.palettes = palettes;
+    }
+
+    /// Paths of every decode script read from disk, starting with `decode.lua`.
+    pub fn scripts(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        let chunks = self.chunks.iter().filter_map(|(_, path, _)| path.clone());
+        self.path.clone().into_iter().chain(chunks)
     }
 
     pub fn create(&self) -> Result<LuaDecoder> {
-        LuaDecoder::new(&self.folder, &self.chunk)
+        if !self.reload {
+            return LuaDecoder::new(
+                &self.folder,
+                &self.chunk,
+                &self.chunks,
+                &self.palettes,
+                self.identity.clone(),
+                self.seed,
+            );
+        }
+        let chunk = match &self.path {
+            Some(path) => Self::read(path)?,
+            None => self.chunk.clone(),
+        };
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|(filter, path, chunk)| {
+                let chunk = match path {
+                    Some(path) => Self::read(path)?,
+                    None => chunk.clone(),
+                };
+                Ok((filter.clone(), path.clone(), chunk))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        LuaDecoder::new(
+            &self.folder,
+            &chunk,
+            &chunks,
+            &self.palettes,
+            self.identity.clone(),
+            self.seed,
+        )
+    }
+
+    fn read(path: &Path) -> Result<String> {
+        fs::read_to_string(path).map_err(|e| Error::decoder_open(path, e))
     }
 }
 
 pub struct LuaDecoder {
-    // actually has lifetime of `_lua``
+    // actually have lifetime of `_lua``
     decode: Function<'static>,
+    decoders: Vec<(Predicate, Function<'static>)>,
+    identity: String,
+    seed: u64,
+    rng: CardRng,
+    /// Colors that layer color fields can name, e.g. `@primary` or `@element.fire`.
+    palette: Rc<Palette>,
     // SAFETY: we must never move out of this box as long as `decode` is alive
     _lua: AliasBox<Lua>,
 }
 
+/// A SplitMix64 generator, reseeded before decoding each card so that random choices
+/// made by a decoder depend only on the card identity and the template seed.
+#[derive(Debug, Clone, Default)]
+struct CardRng(Rc<Cell<u64>>);
+
+impl CardRng {
+    fn reseed(&self, seed: u64, identity: &str) {
+        // FNV-1a, which unlike the std hasher is stable across Rust versions
+        let state = identity.bytes().fold(0xcbf29ce484222325 ^ seed, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        self.0.set(state);
+    }
+
+    fn next(&self) -> u64 {
+        let state = self.0.get().wrapping_add(0x9e3779b97f4a7c15);
+        self.0.set(state);
+        let z = (state ^ (state >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Mirrors `math.random`: a float in `[0, 1)` without arguments, an integer in `[1, m]`
+    /// with one, and an integer in `[m, n]` with two.
+    fn random<'lua>(&self, m: Option<i64>, n: Option<i64>) -> LuaResult<LuaValue<'lua>> {
+        let (lo, hi) = match (m, n) {
+            (None, _) => {
+                let x = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+                return Ok(LuaValue::Number(x));
+            }
+            (Some(m), None) => (1, m),
+            (Some(m), Some(n)) => (m, n),
+        };
+        if lo > hi {
+            return Err(LuaError::RuntimeError("interval is empty".to_string()));
+        }
+        let span = hi.wrapping_sub(lo) as u64 as u128 + 1;
+        let offset = (self.next() as u128 * span) >> 64;
+        Ok(LuaValue::Integer(lo.wrapping_add(offset as i64)))
+    }
+}
+
 macro_rules! register {
     (($( $layer:ty ),*) to $lua:expr, $module:expr) => {
         $(
@@ -50,27 +221,52 @@ macro_rules! register {
 }
 
 impl LuaDecoder {
-    fn new(req_path: &PathBuf, chunk: &str) -> Result<Self> {
+    fn new(
+        req_path: &PathBuf,
+        chunk: &str,
+        chunks: &[(Predicate, Option<PathBuf>, String)],
+        palettes: &Palettes,
+        identity: String,
+        seed: u64,
+    ) -> Result<Self> {
         let lua = AliasBox::new(Lua::new());
+        let rng = CardRng::default();
 
         Self::create_layer_module(&lua).map_err(Error::decoder_prep)?;
+        Self::create_random_module(&lua, rng.clone()).map_err(Error::decoder_prep)?;
+        Self::create_palette_module(&lua, palettes).map_err(Error::decoder_prep)?;
+        Self::create_text_module(&lua).map_err(Error::decoder_prep)?;
 
         Self::extend_package_path(&lua, req_path.display().to_string().as_str())
             .map_err(Error::decoder_prep)?;
 
-        let decode: Function = lua
-            .load(chunk)
-            .call(())
-            .map_err(Error::decoder_prep)?;
-
-        let decode = unsafe { std::mem::transmute(decode) };
+        let decode = Self::load(&lua, chunk)?;
+        let decoders = chunks
+            .iter()
+            .map(|(filter, _, chunk)| Ok((filter.clone(), Self::load(&lua, chunk)?)))
+            .collect::<Result<_>>()?;
 
         Ok(Self {
             decode,
+            decoders,
+            identity,
+            seed,
+            rng,
+            palette: Rc::new(Self::flatten_palettes(palettes)),
             _lua: lua,
         })
     }
 
+    /// Runs a script, which must return its decode function.
+    fn load(lua: &AliasBox<Lua>, chunk: &str) -> Result<Function<'static>> {
+        let decode: Function = lua
+            .load(chunk)
+            .call(())
+            .map_err(Error::decoder_prep)?;
+        // SAFETY: the function is only ever used while the boxed `Lua` is alive
+        Ok(unsafe { std::mem::transmute(decode) })
+    }
+
     fn extend_package_path(lua: &Lua, req_path: &str) -> LuaResult<()> {
         let globals = &lua.globals();
         let package: Table = globals.get("package")?;
@@ -84,25 +280,117 @@ impl LuaDecoder {
         Ok(())
     }
 
-    fn create_layer_module(lua: &Lua) -> LuaResult<()> {
+    fn module<'lua>(lua: &'lua Lua, name: &str) -> LuaResult<Table<'lua>> {
         let globals = &lua.globals();
         let loaded: Table = globals
             .get::<_, Table>("package")?
             .get::<_, Table>("loaded")?;
-        let module = match loaded.get("cartomata.layer")? {
+        match loaded.get(name)? {
             LuaValue::Table(module) => Ok(module),
             LuaValue::Nil => {
                 let module = lua.create_table()?;
-                loaded.set("cartomata.layer", &module)?;
+                loaded.set(name, &module)?;
                 Ok(module)
             }
-            _ => Err(LuaError::RuntimeError(
-                "failed to create cartomata.layer module".to_string(),
-            )),
-        }?;
+            _ => Err(LuaError::RuntimeError(format!(
+                "failed to create {name} module"
+            ))),
+        }
+    }
+
+    fn create_layer_module(lua: &Lua) -> LuaResult<()> {
+        let module = Self::module(lua, "cartomata.layer")?;
         register!((ArtworkLayer, AssetLayer, LabelLayer, TextLayer) to &lua, &module);
+        let repeat = lua.create_function(|lua, (params,): (LuaValue,)| {
+            RepeatLayer::from_lua(params, lua)
+        })?;
+        module.set("Repeat", repeat)?;
+        let canvas = lua.create_function(|_, (params,): (Table,)| Canvas::from_table(&params))?;
+        module.set("Canvas", canvas)?;
         Ok(())
     }
+
+    fn create_random_module(lua: &Lua, rng: CardRng) -> LuaResult<()> {
+        let module = Self::module(lua, "cartomata.random")?;
+        let random =
+            lua.create_function(move |_, (m, n): (Option<i64>, Option<i64>)| rng.random(m, n))?;
+        module.set("random", random)?;
+        Ok(())
+    }
+
+    /// Exposes each color of the palette, and each table of colors, with colors written as hex
+    /// strings, which layers accept wherever they take a color.
+    fn create_palette_module(lua: &Lua, palettes: &Palettes) -> LuaResult<()> {
+        let module = Self::module(lua, "cartomata.palette")?;
+        for (name, entry) in palettes {
+            match entry {
+                PaletteEntry::Color(color) => module.set(name.as_str(), color.to_string())?,
+                PaletteEntry::Table(colors) => {
+                    let colors = colors.iter().map(|(k, color)| (k.as_str(), color.to_string()));
+                    module.set(name.as_str(), lua.create_table_from(colors)?)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Exposes `format_number(n, options)`, which writes a number with its digits grouped,
+    /// e.g. `1,000,000`. Options are the fields of [`NumberFormat`], all optional, and numbers
+    /// may also be given as strings.
+    fn create_text_module(lua: &Lua) -> LuaResult<()> {
+        let module = Self::module(lua, "cartomata.text")?;
+        let format_number =
+            lua.create_function(|lua, (n, options): (LuaValue, Option<LuaValue>)| {
+                let format: NumberFormat = match options {
+                    Some(options) => lua.from_value(options)?,
+                    None => NumberFormat::default(),
+                };
+                let n = match n {
+                    LuaValue::String(s) => {
+                        let s = s.to_str()?.trim();
+                        match s.parse::<i64>() {
+                            Ok(n) => LuaValue::Integer(n),
+                            Err(_) => s.parse::<f64>().map(LuaValue::Number).map_err(|e| {
+                                LuaError::FromLuaConversionError {
+                                    from: "string",
+                                    to: "number",
+                                    message: Some(e.to_string()),
+                                }
+                            })?,
+                        }
+                    }
+                    n => n,
+                };
+                match n {
+                    LuaValue::Integer(n) => Ok(format.format_int(n)),
+                    LuaValue::Number(n) => Ok(format.format_float(n)),
+                    n => Err(LuaError::FromLuaConversionError {
+                        from: n.type_name(),
+                        to: "number",
+                        message: None,
+                    }),
+                }
+            })?;
+        module.set("format_number", format_number)?;
+        Ok(())
+    }
+
+    /// Names every color of the palette, with colors in tables named `table.entry`.
+    fn flatten_palettes(palettes: &Palettes) -> Palette {
+        let mut palette = Palette::new();
+        for (name, entry) in palettes {
+            match entry {
+                PaletteEntry::Color(color) => {
+                    palette.insert(name.clone(), *color);
+                }
+                PaletteEntry::Table(colors) => {
+                    let colors = colors.iter().map(|(k, color)| (format!("{name}.{k}"), *color));
+                    palette.extend(colors);
+                }
+            }
+        }
+        palette
+    }
 }
 
 macro_rules! cast_layer {
@@ -120,17 +408,113 @@ macro_rules! cast_layer {
             }
         }
     };
+    (($value:expr, $lua:expr, $tag:expr) from $($name:literal => $ltype:ty)|*) => {
+        match $tag.to_lowercase().as_str() {
+            $($name => $lua.from_value::<$ltype>($value).map(|l| Box::new(l) as Box<dyn Layer>),)*
+            tag => Err(LuaError::FromLuaConversionError {
+                from: "table",
+                to: "Layer",
+                message: Some(format!("unknown layer type {tag:?}")),
+            }),
+        }
+    };
+}
+
+/// Canvas size of a card, returned by a decode script along with its layers, e.g.
+/// `Canvas { width = 1500, height = 1050 }`, for cards larger than the template card size.
+#[derive(Debug, Clone, Copy)]
+struct Canvas {
+    width: i32,
+    height: i32,
+}
+
+impl Canvas {
+    fn from_table(params: &Table) -> LuaResult<Self> {
+        let (width, height) = (params.get("width")?, params.get("height")?);
+        if width <= 0 || height <= 0 {
+            return Err(LuaError::RuntimeError(format!(
+                "canvas size must be positive, got {width}x{height}"
+            )));
+        }
+        Ok(Self { width, height })
+    }
+}
+
+impl UserData for Canvas {}
+
+/// A value returned by a decode script, either a layer or the canvas size of the card.
+enum Decoded {
+    Layer(Box<dyn Layer>),
+    Canvas(Canvas),
+}
+
+impl<'lua> FromLua<'lua> for Decoded {
+    fn from_lua(value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        match &value {
+            LuaValue::UserData(ud) if ud.is::<Canvas>() => Ok(Self::Canvas(*ud.borrow()?)),
+            // plain tables can also be a canvas, e.g. `{ type = "canvas", width = 1500, ... }`
+            LuaValue::Table(table)
+                if table
+                    .get::<_, Option<String>>("type")?
+                    .is_some_and(|tag| tag.eq_ignore_ascii_case("canvas")) =>
+            {
+                Canvas::from_table(table).map(Self::Canvas)
+            }
+            _ => <Box<dyn Layer>>::from_lua(value, lua).map(Self::Layer),
+        }
+    }
 }
 
 impl UserData for Box<dyn Layer> {}
 
+impl UserData for RepeatLayer {}
+
+impl<'lua> FromLua<'lua> for RepeatLayer {
+    fn from_lua(value: LuaValue<'lua>, _: &'lua Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::UserData(ud) => ud.take::<Self>(),
+            LuaValue::Table(params) => Ok(Self {
+                layer: params.get("layer")?,
+                count: params.get("count")?,
+                dx: params.get::<_, Option<i32>>("dx")?.unwrap_or_default(),
+                dy: params.get::<_, Option<i32>>("dy")?.unwrap_or_default(),
+                enabled: params.get::<_, Option<bool>>("enabled")?.unwrap_or(true),
+            }),
+            _ => Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "RepeatLayer",
+                message: None,
+            }),
+        }
+    }
+}
+
 impl<'lua> FromLua<'lua> for Box<dyn Layer> {
     fn from_lua(value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
         match &value {
             LuaValue::UserData(ud) => {
                 cast_layer!(
                     (value, lua, ud)
-                    to AssetLayer | ArtworkLayer | LabelLayer | TextLayer
+                    to AssetLayer | ArtworkLayer | LabelLayer | TextLayer | RepeatLayer
+                )
+            }
+            // plain tables name their layer type, e.g. `{ type = "asset", path = "x" }`
+            LuaValue::Table(table) => {
+                let tag: Option<String> = table.get("type")?;
+                let tag = tag.ok_or_else(|| LuaError::FromLuaConversionError {
+                    from: "table",
+                    to: "Layer",
+                    message: Some(String::from("missing layer `type` field")),
+                })?;
+                if tag.eq_ignore_ascii_case("repeat") {
+                    return RepeatLayer::from_lua(value, lua).map(|l| Box::new(l) as Box<dyn Layer>);
+                }
+                cast_layer!(
+                    (value, lua, tag)
+                    from "asset" => AssetLayer
+                        | "artwork" => ArtworkLayer
+                        | "label" => LabelLayer
+                        | "text" => TextLayer
                 )
             }
             _ => Err(LuaError::FromLuaConversionError {
@@ -144,10 +528,23 @@ impl<'lua> FromLua<'lua> for Box<dyn Layer> {
 
 impl Decoder<DynCard> for LuaDecoder {
     fn decode(&self, card: &DynCard) -> Result<LayerStack> {
-        let layers: Variadic<Box<dyn Layer>> = self
-            .decode
-            .call(card.0.clone())
-            .map_err(Error::decode)?;
-        Ok(LayerStack(layers.into_iter().collect()))
+        self.rng.reseed(self.seed, &identify(&self.identity, card));
+        let decode = self
+            .decoders
+            .iter()
+            .find(|(filter, _)| filter.eval(card))
+            .map_or(&self.decode, |(_, decode)| decode);
+        // colors of the layers created are deserialized while decoding, so names resolve then
+        let values: Variadic<Decoded> =
+            Color::with_palette(self.palette.clone(), || decode.call(card.clone()))
+                .map_err(Error::decode)?;
+        let mut stack = LayerStack::new(Vec::with_capacity(values.len()));
+        for value in values {
+            match value {
+                Decoded::Layer(layer) => stack.layers.push(layer),
+                Decoded::Canvas(Canvas { width, height }) => stack.canvas = Some((width, height)),
+            }
+        }
+        Ok(stack)
     }
 }
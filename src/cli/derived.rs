@@ -0,0 +1,46 @@
+//! Fields computed from other fields of each card.
+
+use crate::cli::card::DynCard;
+use crate::cli::mapped::CardMap;
+use crate::data::Expr;
+use crate::error::Result;
+
+use std::collections::HashMap;
+
+/// Fields added to cards, each computed by an expression over the card's fields, e.g.
+///
+/// ```toml
+/// [derived]
+/// total = "atk + def"
+/// ```
+///
+/// Every expression sees the card as read, with overrides applied, so derived fields can't
+/// refer to each other.
+#[derive(Debug, Clone, Default)]
+pub struct DerivedFields(Vec<(String, Expr)>);
+
+impl DerivedFields {
+    pub fn new(fields: &HashMap<String, String>) -> Result<Self> {
+        let mut fields = fields
+            .iter()
+            .map(|(k, expr)| Ok((k.clone(), Expr::from_string(expr)?)))
+            .collect::<Result<Vec<_>>>()?;
+        fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(Self(fields))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl CardMap for DerivedFields {
+    /// Computes every derived field of a card and inserts them into it.
+    fn apply(&self, mut card: DynCard) -> DynCard {
+        let values: Vec<_> = self.0.iter().map(|(_, expr)| expr.eval(&card)).collect();
+        for ((k, _), v) in self.0.iter().zip(values) {
+            card.set(k.clone(), v);
+        }
+        card
+    }
+}
@@ -0,0 +1,58 @@
+//! Data sources whose cards are changed as they are read.
+
+use crate::cli::card::DynCard;
+use crate::data::{DataSource, Predicate};
+use crate::error::Result;
+
+/// Changes made to each card read from a data source, such as overrides or derived fields.
+pub trait CardMap: Send {
+    fn apply(&self, card: DynCard) -> DynCard;
+}
+
+/// Wraps a data source, changing each card read with a [`CardMap`].
+///
+/// Cards are filtered after they are changed, so that filters see the changed fields, which
+/// means the filter can't be passed on to the wrapped source.
+pub struct MappedSource<M: CardMap> {
+    source: Box<dyn DataSource<DynCard>>,
+    map: M,
+}
+
+impl<M: CardMap> MappedSource<M> {
+    pub fn new(source: Box<dyn DataSource<DynCard>>, map: M) -> Self {
+        Self { source, map }
+    }
+}
+
+impl<M: CardMap> DataSource<DynCard> for MappedSource<M> {
+    fn read(
+        &mut self,
+        filter: Option<Predicate>,
+    ) -> Result<Box<dyn Iterator<Item = Result<DynCard>> + '_>> {
+        let iterator = self.read_indexed(filter)?.map(|(_, card)| card);
+        Ok(Box::new(iterator))
+    }
+
+    fn read_indexed(
+        &mut self,
+        filter: Option<Predicate>,
+    ) -> Result<Box<dyn Iterator<Item = (i64, Result<DynCard>)> + '_>> {
+        let map = &self.map;
+        let iterator = self
+            .source
+            .read_indexed(None)?
+            .map(|(key, card)| (key, card.map(|card| map.apply(card))))
+            .filter(move |(_, card)| match (card, &filter) {
+                (Ok(card), Some(filter)) => filter.eval(card),
+                _ => true,
+            });
+        Ok(Box::new(iterator))
+    }
+
+    fn count(&self, filter: Option<&Predicate>) -> Option<usize> {
+        match filter {
+            Some(_) => None,
+            None => self.source.count(None),
+        }
+    }
+}
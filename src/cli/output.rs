@@ -1,13 +1,19 @@
-use crate::cli::card::DynCard;
-use crate::error::Result;
-use crate::image::ImgBackend;
+use crate::data::{identify, Card};
+use crate::error::{Error, Result};
+use crate::image::{IccProfile, ImgBackend};
+#[cfg(feature = "svg")]
+use crate::image::SvgDocument;
 
+use clap::ValueEnum;
 use libvips::VipsImage;
 use regex::Regex;
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fmt;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 #[derive(Debug, Clone)]
@@ -15,7 +21,46 @@ pub struct OutputMap {
     pub prefix: PathBuf,
     pub resize: Resize,
     pub pattern: String,
+    /// Text added before the file name of every output, after any subfolder in the pattern.
+    pub name_prefix: String,
+    /// Text added after the file name of every output, before its extension.
+    pub name_suffix: String,
     pub ext: String,
+    pub format: OutputFormat,
+    /// Profile raster images are converted to before being written, e.g. for CMYK print.
+    pub icc: Option<IccProfile>,
+    /// Resolution written to raster images, in dots per inch.
+    pub dpi: Option<f64>,
+    /// Additional versions of each raster image, by name, written from the same render.
+    pub profiles: Vec<(String, OutputProfile)>,
+}
+
+/// An additional version of each output image, such as a thumbnail, scaled down from the
+/// full image instead of rendering the card again.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OutputProfile {
+    /// Folder within the output folder the images are written to. Defaults to the name of
+    /// the profile.
+    pub folder: Option<PathBuf>,
+    /// How images are resized from the full image, ignoring the main resize.
+    #[serde(default)]
+    pub resize: Resize,
+    /// Extension of the images, which sets their format. Defaults to the main extension.
+    pub ext: Option<String>,
+    /// Resolution written to the images, in dots per inch. Defaults to the main resolution.
+    pub dpi: Option<f64>,
+}
+
+/// Format in which cards are rendered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// A raster image, encoded according to the output extension.
+    #[default]
+    Raster,
+    /// An SVG document with each layer embedded as a separate image.
+    #[cfg(feature = "svg")]
+    Svg,
 }
 
 impl OutputMap {
@@ -24,7 +69,13 @@ impl OutputMap {
             prefix: PathBuf::new(),
             resize: Resize::default(),
             pattern,
+            name_prefix: String::new(),
+            name_suffix: String::new(),
             ext: String::from("png"),
+            format: OutputFormat::default(),
+            icc: None,
+            dpi: None,
+            profiles: Vec::new(),
         }
     }
 
@@ -34,6 +85,16 @@ impl OutputMap {
         }
     }
 
+    /// Sets the text added around the file name of every output, e.g. to tell variants apart.
+    pub fn set_name_affixes(&mut self, prefix: Option<String>, suffix: Option<String>) {
+        if let Some(prefix) = prefix {
+            self.name_prefix = prefix;
+        }
+        if let Some(suffix) = suffix {
+            self.name_suffix = suffix;
+        }
+    }
+
     pub fn set_resize(&mut self, resize: Option<Resize>) {
         if let Some(resize) = resize {
             self.resize = resize;
@@ -46,43 +107,188 @@ impl OutputMap {
         }
     }
 
-    pub fn identify(&self, card: &DynCard) -> String {
-        let re = Regex::new(r"\{([^}]+)\}").unwrap();
-        re.replace_all(self.pattern.as_str(), |captures: &regex::Captures| {
-            card.0
-                .get(captures.get(1).unwrap().as_str())
-                .map(|v| v.to_string())
-                .unwrap_or_default()
-        })
-        .to_string()
+    pub fn set_format(&mut self, format: Option<OutputFormat>) {
+        if let Some(format) = format {
+            self.format = format;
+        }
     }
 
-    pub fn write(&self, card: &DynCard, img: &VipsImage, ib: &ImgBackend) -> Result<()> {
-        let img = ib.scale_to(img, self.resize.width, self.resize.height)?;
+    pub fn set_dpi(&mut self, dpi: Option<f64>) {
+        if dpi.is_some() {
+            self.dpi = dpi;
+        }
+    }
+
+    /// Sets the additional versions written of each image, in order of their names.
+    pub fn set_profiles(&mut self, profiles: HashMap<String, OutputProfile>) {
+        self.profiles = profiles.into_iter().collect();
+        self.profiles.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+
+    pub fn identify<C: Card>(&self, card: &C) -> String {
+        identify(&self.pattern, card)
+    }
+
+    /// Path a card is written to, with the name affixes around its file name, and the
+    /// extension given by the output format.
+    pub fn path<C: Card>(&self, card: &C) -> PathBuf {
+        self.path_in(card, None, self.output_ext())
+    }
+
+    /// Path a card is written to within a folder of the output folder, with an extension.
+    fn path_in<C: Card>(&self, card: &C, folder: Option<&Path>, ext: &str) -> PathBuf {
         let mut path = self.prefix.clone();
+        if let Some(folder) = folder {
+            path.push(folder);
+        }
         path.push(self.identify(card));
-        path.set_extension(self.ext.clone());
-        ib.write(&img, path)
+        path.set_extension("");
+        let mut name = OsString::from(&self.name_prefix);
+        name.push(path.file_name().unwrap_or_default());
+        name.push(&self.name_suffix);
+        // pushed by hand, as `set_extension` would replace any dot in the suffix
+        if !ext.is_empty() {
+            name.push(".");
+            name.push(ext);
+        }
+        path.set_file_name(name);
+        path
+    }
+
+    /// Writes a card, and then each additional version of it, returning the path of every
+    /// image written along with its size once resized, starting with the main image.
+    pub fn write<C: Card>(
+        &self,
+        card: &C,
+        img: &VipsImage,
+        ib: &ImgBackend,
+    ) -> Result<Vec<(PathBuf, (i32, i32))>> {
+        let path = self.path(card);
+        let size = self.write_resized(img, self.resize, self.dpi, &path, ib)?;
+        let mut written = Vec::with_capacity(self.profiles.len() + 1);
+        written.push((path, size));
+        for (name, profile) in &self.profiles {
+            let folder = profile.folder.as_deref().unwrap_or(Path::new(name));
+            let ext = self.raster_ext(profile.ext.as_deref().unwrap_or(&self.ext));
+            let profile_path = self.path_in(card, Some(folder), ext);
+            if let Some(dir) = profile_path.parent() {
+                fs::create_dir_all(dir).map_err(Error::io_error)?;
+            }
+            let dpi = profile.dpi.or(self.dpi);
+            let size = self.write_resized(img, profile.resize, dpi, &profile_path, ib)?;
+            written.push((profile_path, size));
+        }
+        Ok(written)
+    }
+
+    /// Writes an image resized, returning the size it was written with.
+    fn write_resized(
+        &self,
+        img: &VipsImage,
+        resize: Resize,
+        dpi: Option<f64>,
+        path: &Path,
+        ib: &ImgBackend,
+    ) -> Result<(i32, i32)> {
+        let img = resize.apply(img, ib)?;
+        let img = match &self.icc {
+            Some(icc) => ib.icc_transform(&img, icc)?,
+            None => img,
+        };
+        let img = match dpi {
+            Some(dpi) => ib.set_dpi(&img, dpi)?,
+            None => img,
+        };
+        ib.write(&img, path)?;
+        Ok((img.get_width(), img.get_height()))
+    }
+
+    /// Extension of output files. SVG documents always use `svg`, and images converted to an
+    /// ICC profile use `tif` unless the extension is already TIFF or JPEG, the only formats
+    /// that can hold CMYK.
+    fn output_ext(&self) -> &str {
+        match self.format {
+            #[cfg(feature = "svg")]
+            OutputFormat::Svg => "svg",
+            OutputFormat::Raster => self.raster_ext(&self.ext),
+        }
+    }
+
+    /// Extension of raster images given the one requested, which may change with an ICC
+    /// profile, as described in `output_ext`.
+    fn raster_ext<'a>(&self, ext: &'a str) -> &'a str {
+        match (&self.icc, ext.to_lowercase().as_str()) {
+            (Some(_), "tif" | "tiff" | "jpg" | "jpeg") | (None, _) => ext,
+            (Some(_), _) => "tif",
+        }
+    }
+
+    /// Writes an SVG document. Resizing is not applied.
+    #[cfg(feature = "svg")]
+    pub fn write_svg<C: Card>(&self, card: &C, doc: &SvgDocument) -> Result<PathBuf> {
+        let path = self.path(card);
+        std::fs::write(&path, doc.to_string()).map_err(crate::error::Error::io_error)?;
+        Ok(path)
     }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
-pub struct Resize {
-    width: Option<i32>,
-    height: Option<i32>,
+/// How output images are resized, parsed from one of these forms:
+///
+/// - `WxH`, where `W` and `H` are optional: scales to that width and height. When only one is
+///   given the aspect ratio is kept, but when both are given the image is stretched, which
+///   distorts it unless the ratio matches. `WxH!` is the same, stating the stretch explicitly.
+/// - `WxH>`: scales down to fit within that width and height, keeping the aspect ratio.
+///   Images that already fit are left as they are.
+/// - `N%`: scales both dimensions by a percentage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resize {
+    Stretch { width: Option<i32>, height: Option<i32> },
+    Shrink { width: Option<i32>, height: Option<i32> },
+    Percent(f64),
+}
+
+impl Default for Resize {
+    fn default() -> Self {
+        Self::Stretch { width: None, height: None }
+    }
+}
+
+impl Resize {
+    pub fn apply(&self, img: &VipsImage, ib: &ImgBackend) -> Result<VipsImage> {
+        match *self {
+            Self::Stretch { width, height } => ib.scale_to(img, width, height),
+            Self::Shrink { width, height } => {
+                let sx = width.map_or(1.0, |w| w as f64 / img.get_width() as f64);
+                let sy = height.map_or(1.0, |h| h as f64 / img.get_height() as f64);
+                let s = sx.min(sy).min(1.0);
+                ib.scale(img, s, s)
+            }
+            Self::Percent(p) => ib.scale(img, p / 100.0, p / 100.0),
+        }
+    }
 }
 
 impl FromStr for Resize {
     type Err = &'static str;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        let re = Regex::new(r"^(\d+)?\s*x\s*(\d+)?$").unwrap();
+        const EXPECTED: &str =
+            "string not in form WxH, WxH! or WxH> where W and H are optional integer numbers, \
+             or N% where N is a positive number";
+        let re_percent = Regex::new(r"^(\d+(?:\.\d+)?)\s*%$").unwrap();
+        if let Some(captures) = re_percent.captures(s) {
+            let p: f64 = captures.get(1).unwrap().as_str().parse().unwrap();
+            return if p > 0.0 && p.is_finite() { Ok(Self::Percent(p)) } else { Err(EXPECTED) };
+        }
 
-        let captures = re
-            .captures(s)
-            .ok_or("string not in form WxH where W and H are optional integer numbers")?;
-        let width = captures.get(1).map(|m| m.as_str().parse().unwrap());
-        let height = captures.get(2).map(|m| m.as_str().parse().unwrap());
-        Ok(Self { width, height })
+        let re = Regex::new(r"^(\d+)?\s*x\s*(\d+)?\s*([!>])?$").unwrap();
+        let captures = re.captures(s).ok_or(EXPECTED)?;
+        let parse = |i| captures.get(i).map(|m| m.as_str().parse().map_err(|_| EXPECTED));
+        let width = parse(1).transpose()?;
+        let height = parse(2).transpose()?;
+        match captures.get(3).map(|m| m.as_str()) {
+            Some(">") => Ok(Self::Shrink { width, height }),
+            _ => Ok(Self::Stretch { width, height }),
+        }
     }
 }
 
@@ -92,7 +298,7 @@ impl<'de> Visitor<'de> for ResizeVisitor {
     type Value = Resize;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a string in the form WxH where W and H are optional integer numbers")
+        formatter.write_str("a string in the form WxH, WxH!, WxH> or N%")
     }
 
     fn visit_str<E>(self, v: &str) -> std::result::Result<Self::Value, E>
@@ -111,3 +317,25 @@ impl<'de> Deserialize<'de> for Resize {
         deserializer.deserialize_str(ResizeVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_parses_sizes() {
+        let stretch = Resize::Stretch { width: Some(750), height: None };
+        assert_eq!("750x".parse(), Ok(stretch));
+        let shrink = Resize::Shrink { width: Some(750), height: Some(1050) };
+        assert_eq!("750 x 1050>".parse(), Ok(shrink));
+        assert_eq!("50%".parse(), Ok(Resize::Percent(50.0)));
+    }
+
+    #[test]
+    fn resize_rejects_sizes_out_of_range() {
+        assert!("99999999999x".parse::<Resize>().is_err());
+        assert!("x99999999999>".parse::<Resize>().is_err());
+        assert!(format!("{}%", "9".repeat(400)).parse::<Resize>().is_err());
+        assert!("0%".parse::<Resize>().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! Per-card overrides, merged over the cards read from a data source.
+
+use crate::cli::card::DynCard;
+use crate::cli::mapped::CardMap;
+#[cfg(feature = "csv")]
+use crate::data::source::{CsvSource, CsvSourceConfig};
+#[cfg(feature = "csv")]
+use crate::data::DataSource;
+use crate::data::{Card, Value};
+use crate::error::{Error, Result};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Fields to replace or add in cards, keyed by card id.
+///
+/// Overrides are read from a TOML file with a table for each card, e.g.
+///
+/// ```toml
+/// [123]
+/// artwork = "123-alt"
+/// ```
+///
+/// or from a CSV file with an `id` column, where empty cells are not applied.
+#[derive(Debug, Clone)]
+pub struct Overrides {
+    path: PathBuf,
+    cards: HashMap<String, HashMap<String, Value>>,
+}
+
+impl Overrides {
+    pub fn open(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "csv")]
+            Some("csv") => Self::open_csv(path),
+            _ => Self::open_toml(path),
+        }
+    }
+
+    fn open_toml(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| Error::source_open(path, e))?;
+        let cards = toml::from_str(&content).map_err(|e| Error::source_open(path, e))?;
+        Ok(Self { path: path.to_path_buf(), cards })
+    }
+
+    #[cfg(feature = "csv")]
+    fn open_csv(path: &Path) -> Result<Self> {
+        let mut source = CsvSource::open(CsvSourceConfig::default(), &path)?;
+        let mut cards = HashMap::new();
+        for card in source.read(None)? {
+            let card: DynCard = card?;
+            let fields = card
+                .fields()
+                .filter(|(k, v)| *k != "id" && !matches!(v, Value::Nil) && v.as_str() != Some(""))
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect();
+            cards.insert(card.id(), fields);
+        }
+        Ok(Self { path: path.to_path_buf(), cards })
+    }
+
+    /// Path of the file the overrides were read from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl CardMap for Overrides {
+    /// Merges the overrides of a card into it, if there are any.
+    fn apply(&self, mut card: DynCard) -> DynCard {
+        if let Some(fields) = self.cards.get(&card.id()) {
+            for (k, v) in fields {
+                card.set(k.clone(), v.clone());
+            }
+        }
+        card
+    }
+}
@@ -1,35 +1,120 @@
-use crate::cli::card::DynCard;
+use crate::cli::card::{CaseInsensitiveCard, DynCard};
 use crate::cli::config::Config;
 use crate::cli::decode::{LuaDecoder, LuaDecoderFactory};
-use crate::cli::output::{OutputMap, Resize};
+use crate::cli::derived::DerivedFields;
+use crate::cli::mapped::MappedSource;
+use crate::cli::output::{OutputFormat, OutputMap, Resize};
+use crate::cli::overrides::Overrides;
 #[cfg(feature = "csv")]
 use crate::data::source::{CsvSource, CsvSourceConfig};
+#[cfg(feature = "remote")]
+use crate::data::source::is_url;
 #[cfg(feature = "sqlite")]
 use crate::data::source::{SqliteSource, SqliteSourceConfig};
-use crate::data::{Card, DataSource};
+#[cfg(feature = "xlsx")]
+use crate::data::source::{XlsxSource, XlsxSourceConfig};
+use crate::data::{Card, DataSource, Predicate};
 use crate::error::{Error, Result};
-use crate::image::{ImageMap, ImgBackend};
+use crate::image::{Bleed, ImageMap, ImgBackend};
+use crate::layer::{LayerStack, RenderContext, RenderOptions, Watermark};
 use crate::template::Template;
 use crate::text::FontMap;
 
 use clap::ValueEnum;
 use libvips::VipsImage;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::num::NonZero;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 #[derive(Debug)]
 pub struct DynTemplate {
     name: String,
     source_map: SourceMap,
+    ignore_case: bool,
+    overrides: Option<Overrides>,
+    derived: DerivedFields,
     decoder_factory: LuaDecoderFactory,
     resource_map: ImageMap,
+    render_options: RenderOptions,
     font_map: FontMap,
     output_map: OutputMap,
+    /// When set, cards whose output was written after this time are skipped.
+    incremental: Option<SystemTime>,
 }
 
 impl DynTemplate {
-    pub fn from_config(config: Config, folder: PathBuf) -> Result<Self> {
+    pub fn from_config(mut config: Config, folder: PathBuf) -> Result<Self> {
+        let (identity, seed) = (config.base.identity.clone(), config.base.seed);
+        let mut decoder_factory = LuaDecoderFactory::new(folder.clone(), identity, seed)?;
+        decoder_factory.set_palettes(std::mem::take(&mut config.palette));
+        decoder_factory.set_list_delimiter(config.source.list_delimiter.clone());
+        for decoder in std::mem::take(&mut config.decoder) {
+            decoder_factory.with_decoder(&decoder.filter, decoder.path)?;
+        }
+
+        let mut font_map = FontMap::new()?;
+        let font = std::mem::take(&mut config.font);
+        font_map.load(font.fonts)?;
+        if let Some(key) = font.default_key {
+            font_map.set_default(key)?;
+        }
+
+        Self::from_parts(config, folder, decoder_factory, font_map)
+    }
+
+    /// Assembles a template from a decoder factory and fonts set up in code, e.g. with
+    /// [`LuaDecoderFactory::from_chunk`] and [`FontMap::load_font_from_bytes`], so that a
+    /// template can be embedded in a program without its scripts and fonts on disk. The
+    /// `decoder`, `palette` and `font` sections of the config are ignored, as those are set
+    /// up on the factory and the font map instead.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use cartomata::cli::{Config, DynTemplate, LuaDecoderFactory};
+    /// use cartomata::text::FontMap;
+    ///
+    /// let config = Config::from_toml(
+    ///     r#"
+    ///     [base]
+    ///     name = "embedded"
+    ///     size = { width = 750, height = 1050 }
+    ///
+    ///     [source.csv]
+    ///     "#,
+    ///     ".",
+    /// )
+    /// .unwrap();
+    /// let chunk = String::from(
+    ///     r#"
+    ///     local layer = require "cartomata.layer"
+    ///     return function(card)
+    ///         return layer.TextLayer {
+    ///             text = card.name, font = "body", size = 32, x = 60, y = 60,
+    ///         }
+    ///     end
+    ///     "#,
+    /// );
+    /// let factory = LuaDecoderFactory::from_chunk(".".into(), chunk, "{id}".into(), 0);
+    /// # let font: &[u8] = &[];
+    /// // e.g. `include_bytes!("body.ttf")`
+    /// let mut font_map = FontMap::new().unwrap();
+    /// font_map.load_font_from_bytes("body".into(), font).unwrap();
+    /// let template = DynTemplate::from_parts(config, ".".into(), factory, font_map).unwrap();
+    /// ```
+    pub fn from_parts(
+        config: Config,
+        folder: PathBuf,
+        decoder_factory: LuaDecoderFactory,
+        font_map: FontMap,
+    ) -> Result<Self> {
         let assets_folder = config.assets_folder(&folder);
         let name = config.base.name;
+        let ignore_case = config.base.ignore_case;
+        let overrides = config.base.overrides.as_deref().map(Overrides::open).transpose()?;
+        let derived = DerivedFields::new(&config.derived)?;
 
         let mut source_map = SourceMap::new();
 
@@ -39,7 +124,10 @@ impl DynTemplate {
         #[cfg(feature = "sqlite")]
         source_map.with_sqlite(config.source.sqlite);
 
-        let decoder_factory = LuaDecoderFactory::new(folder)?;
+        #[cfg(feature = "xlsx")]
+        source_map.with_xlsx(config.source.xlsx);
+
+        source_map.with_extensions(config.source.extensions);
 
         let resource_map = ImageMap {
             artwork_folder: config
@@ -52,20 +140,33 @@ impl DynTemplate {
             card_size: (config.base.size.width, config.base.size.height),
             placeholder: config.assets.map(|cfg| cfg.placeholder).unwrap_or_default(),
         };
-
-        let mut font_map = FontMap::new()?;
-        font_map.load(config.font)?;
+        let render_options = RenderOptions {
+            watermark: config.watermark,
+            premultiplied_alpha: config.base.premultiplied_alpha,
+            corner_radius: config.base.corner_radius,
+            bleed: config.base.bleed.map(|size| Bleed { size, mode: config.base.bleed_mode }),
+            crop_marks: config.base.crop_marks,
+            ..Default::default()
+        };
 
         let mut output_map = OutputMap::new(config.base.identity);
         output_map.set_ext(resource_map.extensions.first().cloned());
+        output_map.icc = config.cmyk;
+        output_map.set_dpi(config.base.dpi);
+        output_map.set_profiles(config.output);
 
         Ok(Self {
             name,
             source_map,
+            ignore_case,
+            overrides,
+            derived,
             decoder_factory,
             resource_map,
+            render_options,
             font_map,
             output_map,
+            incremental: None,
         })
     }
 
@@ -79,6 +180,88 @@ impl DynTemplate {
         self.output_map.set_resize(resize);
         self.output_map.set_ext(ext);
     }
+
+    /// Sets the text added before and after the file name of every output.
+    pub fn set_name_affixes(&mut self, prefix: Option<String>, suffix: Option<String>) {
+        self.output_map.set_name_affixes(prefix, suffix);
+    }
+
+    pub fn set_format(&mut self, format: Option<OutputFormat>) {
+        self.output_map.set_format(format);
+    }
+
+    /// Sets the resolution of output images, overriding the config when given.
+    pub fn set_dpi(&mut self, dpi: Option<f64>) {
+        self.output_map.set_dpi(dpi);
+    }
+
+    pub fn set_opacity(&mut self, opacity: Option<f64>) {
+        self.render_options.opacity = opacity;
+    }
+
+    /// Stamps a text over every card, keeping the other watermark settings from the config.
+    pub fn set_watermark(&mut self, text: Option<String>) {
+        if let Some(text) = text {
+            match &mut self.render_options.watermark {
+                Some(watermark) => watermark.text = text,
+                None => self.render_options.watermark = Some(Watermark::new(text)),
+            }
+        }
+    }
+
+    /// Sets the print bleed and crop marks, overriding the config when given.
+    pub fn set_print_margins(&mut self, bleed: Option<i32>, crop_marks: bool) {
+        if let Some(size) = bleed {
+            let mode = self.render_options.bleed.map(|b| b.mode).unwrap_or_default();
+            self.render_options.bleed = Some(Bleed { size, mode });
+        }
+        self.render_options.crop_marks |= crop_marks;
+    }
+
+    /// Skips the cards whose output is newer than the input data, the decode scripts, the
+    /// overrides and the template config, so that only outdated cards are rendered again.
+    /// Changes to assets and fonts are not detected.
+    ///
+    /// Input data read from a URL can't tell when it last changed, so every card is rendered.
+    pub fn set_incremental(&mut self, folder: &Path, input: &Path) -> Result<()> {
+        #[cfg(feature = "remote")]
+        if input.to_str().is_some_and(is_url) {
+            return Ok(());
+        }
+        let mut latest = SystemTime::UNIX_EPOCH;
+        let mut inputs = vec![input.to_path_buf(), folder.join("template.toml")];
+        inputs.extend(self.overrides.as_ref().map(|o| o.path().to_path_buf()));
+        for path in inputs.into_iter().chain(self.decoder_factory.scripts()) {
+            let modified = fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .map_err(Error::io_error)?;
+            latest = latest.max(modified);
+        }
+        self.incremental = Some(latest);
+        Ok(())
+    }
+
+    /// Sets the number of threads used to render the layers of each card concurrently.
+    pub fn set_layer_threads(&mut self, threads: Option<NonZero<usize>>) {
+        self.render_options.layer_threads = threads;
+    }
+
+    /// Writes each card after every layer to a folder named after the card within `dir`.
+    pub fn set_dump_layers(&mut self, dir: Option<PathBuf>) {
+        self.render_options.dump_layers = dir;
+    }
+
+    /// Sets whether decode scripts are read again every time a decoder is created.
+    pub fn set_reload(&mut self, reload: bool) {
+        self.decoder_factory.set_reload(reload);
+    }
+
+    /// Replaces the seed given in the template config, if any.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        if let Some(seed) = seed {
+            self.decoder_factory.set_seed(seed);
+        }
+    }
 }
 
 impl Template<DynCard> for DynTemplate {
@@ -90,11 +273,32 @@ impl Template<DynCard> for DynTemplate {
     }
 
     fn source(&self, key: Self::SourceKey) -> Result<Box<dyn DataSource<DynCard>>> {
-        self.source_map.select(key.0, key.1)
+        let source: Box<dyn DataSource<DynCard>> = if self.ignore_case {
+            let source = self.source_map.select(key.0, key.1)?;
+            Box::new(CaseInsensitiveSource(source))
+        } else {
+            self.source_map.select(key.0, key.1)?
+        };
+        let source: Box<dyn DataSource<DynCard>> = match &self.overrides {
+            Some(overrides) => Box::new(MappedSource::new(source, overrides.clone())),
+            None => source,
+        };
+        if self.derived.is_empty() {
+            Ok(source)
+        } else {
+            Ok(Box::new(MappedSource::new(source, self.derived.clone())))
+        }
     }
 
-    fn identify(&self, card: &DynCard) -> String {
-        self.output_map.identify(card)
+    fn identity(&self) -> Option<&str> {
+        Some(&self.output_map.pattern)
+    }
+
+    fn rendered(&self, card: &DynCard) -> Option<PathBuf> {
+        let latest = self.incremental?;
+        let path = self.output_map.path(card);
+        let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok()?;
+        (modified > latest).then_some(path)
     }
 
     fn decoder(&self) -> Result<Self::Decoder> {
@@ -109,17 +313,72 @@ impl Template<DynCard> for DynTemplate {
         &self.font_map
     }
 
-    fn output(&self, card: &DynCard, img: &VipsImage, ib: &ImgBackend) -> Result<()> {
-        self.output_map.write(card, img, ib)
+    fn render_options(&self) -> &RenderOptions {
+        &self.render_options
+    }
+
+    fn output(&self, card: &DynCard, img: &VipsImage, ib: &ImgBackend) -> Result<PathBuf> {
+        let written = self.output_map.write(card, img, ib)?;
+        Ok(written[0].0.clone())
+    }
+
+    fn render(&self, card: &DynCard, layers: LayerStack, ctx: &RenderContext) -> Result<PathBuf> {
+        let written = match self.output_map.format {
+            OutputFormat::Raster => {
+                let img = layers.render_with(ctx, self.dump_folder(card, ctx).as_deref())?;
+                self.output_map.write(card, &img, ctx.backend)?
+            }
+            #[cfg(feature = "svg")]
+            OutputFormat::Svg => {
+                let doc = layers.render_svg(ctx)?;
+                vec![(self.output_map.write_svg(card, &doc)?, doc.size())]
+            }
+        };
+        let path = written[0].0.clone();
+        for output in written {
+            ctx.outputs.push(output);
+        }
+        Ok(path)
+    }
+}
+
+/// Reads cards ignoring case, so that filters are also evaluated ignoring case.
+struct CaseInsensitiveSource(Box<dyn DataSource<CaseInsensitiveCard>>);
+
+impl DataSource<DynCard> for CaseInsensitiveSource {
+    fn read(
+        &mut self,
+        filter: Option<Predicate>,
+    ) -> Result<Box<dyn Iterator<Item = Result<DynCard>> + '_>> {
+        let iterator = self.0.read(filter)?.map(|card| card.map(|card| card.0));
+        Ok(Box::new(iterator))
+    }
+
+    fn read_indexed(
+        &mut self,
+        filter: Option<Predicate>,
+    ) -> Result<Box<dyn Iterator<Item = (i64, Result<DynCard>)> + '_>> {
+        let iterator = self
+            .0
+            .read_indexed(filter)?
+            .map(|(key, card)| (key, card.map(|card| card.0)));
+        Ok(Box::new(iterator))
+    }
+
+    fn count(&self, filter: Option<&Predicate>) -> Option<usize> {
+        self.0.count(filter)
     }
 }
 
-#[derive(Debug, Copy, Clone, ValueEnum)]
+#[derive(Debug, Copy, Clone, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SourceType {
     #[cfg(feature = "csv")]
     Csv,
     #[cfg(feature = "sqlite")]
     Sqlite,
+    #[cfg(feature = "xlsx")]
+    Xlsx,
 }
 
 #[derive(Debug, Clone)]
@@ -128,6 +387,10 @@ pub struct SourceMap {
     csv: Option<CsvSourceConfig>,
     #[cfg(feature = "sqlite")]
     sqlite: Option<SqliteSourceConfig>,
+    #[cfg(feature = "xlsx")]
+    xlsx: Option<XlsxSourceConfig>,
+    /// Source types by lowercase extension, without the leading dot.
+    extensions: HashMap<String, SourceType>,
 }
 
 impl SourceMap {
@@ -137,6 +400,9 @@ impl SourceMap {
             csv: None,
             #[cfg(feature = "sqlite")]
             sqlite: None,
+            #[cfg(feature = "xlsx")]
+            xlsx: None,
+            extensions: HashMap::new(),
         }
     }
 
@@ -150,13 +416,39 @@ impl SourceMap {
         self.sqlite = cfg;
     }
 
-    fn infer_source_type(path: impl AsRef<Path>) -> Option<SourceType> {
-        let ext = path.as_ref().extension()?.to_str()?;
+    #[cfg(feature = "xlsx")]
+    pub fn with_xlsx(&mut self, cfg: Option<XlsxSourceConfig>) {
+        self.xlsx = cfg;
+    }
+
+    /// Adds source types for extensions, which take precedence over the ones known by default.
+    /// Extensions are matched ignoring case, and may be given with a leading dot.
+    pub fn with_extensions(&mut self, extensions: HashMap<String, SourceType>) {
+        let extensions = extensions
+            .into_iter()
+            .map(|(ext, src_type)| (ext.trim_start_matches('.').to_lowercase(), src_type));
+        self.extensions.extend(extensions);
+    }
+
+    fn infer_source_type(&self, path: impl AsRef<Path>) -> Option<SourceType> {
+        let path = path.as_ref();
+        // compressed files are sniffed by the extension before `.gz`
+        #[cfg(feature = "gzip")]
+        let path = match path.extension() {
+            Some(ext) if ext == "gz" => Path::new(path.file_stem()?),
+            _ => path,
+        };
+        let ext = path.extension()?.to_str()?;
+        if let Some(src_type) = self.extensions.get(&ext.to_lowercase()) {
+            return Some(*src_type);
+        }
         match ext {
             #[cfg(feature = "csv")]
             "csv" | "tsv" => Some(SourceType::Csv),
             #[cfg(feature = "sqlite")]
             "db" | "cdb" => Some(SourceType::Sqlite),
+            #[cfg(feature = "xlsx")]
+            "xlsx" | "xls" => Some(SourceType::Xlsx),
             _ => None,
         }
     }
@@ -167,8 +459,12 @@ impl SourceMap {
         path: impl AsRef<Path>,
     ) -> Result<Box<dyn DataSource<C>>> {
         let path = path.as_ref();
+        #[cfg(feature = "remote")]
+        if let Some(url) = path.to_str().filter(|path| is_url(path)) {
+            return self.select_url(src_type, url);
+        }
         let src_type = src_type
-            .or_else(|| Self::infer_source_type(path))
+            .or_else(|| self.infer_source_type(path))
             .ok_or_else(|| Error::source_inference(path))?;
         match src_type {
             #[cfg(feature = "csv")]
@@ -186,6 +482,81 @@ impl SourceMap {
                 let source = SqliteSource::open(config, &path)?;
                 Ok(Box::new(source) as Box<dyn DataSource<C>>)
             }
+            #[cfg(feature = "xlsx")]
+            SourceType::Xlsx => {
+                let config = self.xlsx.clone().unwrap_or_default();
+                let source = XlsxSource::open(config, &path)?;
+                Ok(Box::new(source) as Box<dyn DataSource<C>>)
+            }
         }
     }
+
+    /// Opens a source read from a URL, inferring its type from the extension of the URL path,
+    /// e.g. `cards.csv` in `https://example.com/cards.csv?v=2`. URLs without one, such as
+    /// sheets published online, need the type given. Only CSV can be read this way.
+    #[cfg(feature = "remote")]
+    fn select_url<C: Card>(
+        &self,
+        src_type: Option<SourceType>,
+        url: &str,
+    ) -> Result<Box<dyn DataSource<C>>> {
+        let url_path = url.split(['?', '#']).next().unwrap_or(url);
+        let src_type = src_type
+            .or_else(|| self.infer_source_type(url_path))
+            .ok_or_else(|| Error::source_inference(url))?;
+        match src_type {
+            #[cfg(feature = "csv")]
+            SourceType::Csv => {
+                let config = self.csv.unwrap_or_default();
+                let source = CsvSource::open_url(config, url)?;
+                Ok(Box::new(source) as Box<dyn DataSource<C>>)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::remote_source(url)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Value;
+    use crate::decode::Decoder;
+
+    const CONFIG: &str = r#"
+        [base]
+        name = "embedded"
+        size = { width = 750, height = 1050 }
+
+        [source.csv]
+    "#;
+
+    const CHUNK: &str = r#"
+        local layer = require "cartomata.layer"
+        return function(card)
+            return layer.TextLayer {
+                text = card.name, font = "body", size = 32, x = 60, y = 60,
+            }
+        end
+    "#;
+
+    #[test]
+    fn template_from_parts_decodes_cards() {
+        let config = Config::from_toml(CONFIG, ".").unwrap();
+        let chunk = String::from(CHUNK);
+        let factory = LuaDecoderFactory::from_chunk(".".into(), chunk, "{id}".into(), 0);
+        let mut font_map = FontMap::new().unwrap();
+        font_map.load_font_from_name(String::from("body"), "sans-serif", None).unwrap();
+        let template = DynTemplate::from_parts(config, ".".into(), factory, font_map).unwrap();
+        assert_eq!(template.name(), Some("embedded"));
+        assert_eq!(template.resources().card_size, (750, 1050));
+
+        let name = (String::from("name"), Value::Str(String::from("Hello")));
+        let card = DynCard::new(HashMap::from([name]));
+        let decoder = template.decoder().unwrap();
+        let stack = decoder.decode(&card).unwrap();
+        assert_eq!(stack.layers.len(), 1);
+        assert_eq!(stack.layers[0].kind(), "text");
+        assert!(format!("{:?}", stack.layers[0]).contains("Hello"));
+    }
 }
@@ -1,20 +1,29 @@
 //! Representation, extraction and filtering of card data.
 
+mod expr;
 mod predicate;
 pub mod source;
 mod value;
 
-pub use crate::data::predicate::Predicate;
+pub use crate::data::expr::Expr;
+pub use crate::data::predicate::{ParamComparison, Predicate, DEFAULT_LIST_DELIMITER};
 pub use crate::data::source::DataSource;
 pub use crate::data::value::Value;
 
 #[cfg(feature = "derive")]
 pub use cartomata_derive::Card;
+use regex::Regex;
 use serde::de::DeserializeOwned;
+use std::sync::OnceLock;
 
 /// Represents a single card, to mark data types to be used as input to be processed.
 ///
-/// This trait can be derived if the `derive` feature is enabled.
+/// This trait can be derived if the `derive` feature is enabled. Derived implementations
+/// look fields up by the name they are deserialized from, following `#[serde(rename)]`,
+/// `#[serde(alias)]` and `#[serde(rename_all)]` on the struct. The attributes
+/// `#[card(rename = "...")]` and `#[card(alias = "...")]` take precedence over serde's, and
+/// `#[card(skip)]` hides a field from `get`. The card id is taken from the field marked with
+/// `#[card(id)]`, or else the field named `id`.
 ///
 /// # Example
 /// ```
@@ -25,13 +34,48 @@ use serde::de::DeserializeOwned;
 /// struct MyCard {
 ///     id: i64,
 ///     name: String,
+///     #[serde(rename = "Power")]
 ///     power: f64,
+///     #[card(skip)]
+///     tags: Vec<String>,
 /// }
 ///
-/// let sample = MyCard {id: 123, name: "Sample".to_string(), power: 3.14};
-/// assert_eq!(sample.get("power"), Value::Float(3.14))
+/// let sample = MyCard {id: 123, name: "Sample".to_string(), power: 3.14, tags: vec![]};
+/// assert_eq!(sample.get("Power"), Value::Float(3.14));
+/// assert_eq!(sample.get("tags"), Value::Nil);
+/// assert_eq!(sample.id(), "123");
+///
+/// #[derive(Card, Deserialize)]
+/// #[serde(rename_all = "PascalCase")]
+/// struct OtherCard {
+///     id: i64,
+///     card_name: String,
+///     #[serde(rename = "ATK")]
+///     attack: i64,
+/// }
+///
+/// let other = OtherCard {id: 1, card_name: "Other".to_string(), attack: 5};
+/// assert_eq!(other.get("CardName"), Value::Str("Other".to_string()));
+/// assert_eq!(other.get("ATK"), Value::Int(5));
+/// assert_eq!(other.get("card_name"), Value::Nil);
 /// ```
 pub trait Card: DeserializeOwned + 'static {
     /// Generic access to card data fields regardless of its implementation.
     fn get(&self, field: &str) -> Value;
+
+    /// Identifies the card, by default with its `id` field.
+    fn id(&self) -> String {
+        self.get("id").to_string()
+    }
+}
+
+/// Replaces each `{field}` in `pattern` with the value of that field in the card, e.g. to
+/// name output files with `{set}-{number}`. Missing fields are replaced with nothing.
+pub fn identify<C: Card>(pattern: &str, card: &C) -> String {
+    static FIELD: OnceLock<Regex> = OnceLock::new();
+    let re = FIELD.get_or_init(|| Regex::new(r"\{([^}]+)\}").unwrap());
+    re.replace_all(pattern, |captures: &regex::Captures| {
+        card.get(captures.get(1).unwrap().as_str()).to_string()
+    })
+    .to_string()
 }
@@ -0,0 +1,263 @@
+//! Arithmetic expressions over card fields.
+
+use crate::data::{Card, Value};
+use crate::error::{Error, Result};
+
+use logos::{Lexer, Logos};
+use std::iter::Peekable;
+
+/// An arithmetic expression computed from the fields of a card.
+///
+/// Expressions are parsed from strings, with the usual precedence for `+`, `-`, `*`, `/`
+/// and `%`, and parentheses. Operands are fields, numbers, strings quoted with `'`, booleans
+/// and `NULL`. As in predicates, keys with unusual characters are quoted with backticks;
+/// here that includes `-`, so that `atk-def` is a subtraction.
+///
+/// Integers stay integers, except for `/`, which always gives a float. Adding a string to
+/// anything concatenates them. Any other operation on strings, booleans or `NULL`, as well
+/// as a division by zero, gives `NULL`.
+///
+/// # Example
+/// ```
+/// use cartomata::data::{Card, Expr, Value};
+/// use serde::Deserialize;
+///
+/// #[derive(Card, Deserialize)]
+/// struct MyCard {
+///     name: String,
+///     atk: i64,
+///     def: i64,
+/// }
+///
+/// let card = MyCard { name: "Sample".to_string(), atk: 3, def: 4 };
+/// let eval = |expr: &str| Expr::from_string(expr).unwrap().eval(&card);
+/// assert_eq!(eval("atk + def * 2"), Value::Int(11));
+/// assert_eq!(eval("(atk + def) / 2"), Value::Float(3.5));
+/// assert_eq!(eval("name + ' #' + atk"), Value::Str("Sample #3".to_string()));
+/// assert_eq!(eval("atk / 0"), Value::Nil);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Value(Value),
+    Field(String),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Rem(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Parses a string to an expression.
+    pub fn from_string(expr: &str) -> Result<Self> {
+        Parser::new(expr).parse()
+    }
+
+    /// Evaluates an expression on an input card.
+    pub fn eval(&self, card: &impl Card) -> Value {
+        match self {
+            Self::Value(v) => v.clone(),
+            Self::Field(k) => card.get(k),
+            Self::Neg(a) => match a.eval(card) {
+                Value::Int(x) => Value::Int(-x),
+                Value::Float(x) => Value::Float(-x),
+                _ => Value::Nil,
+            },
+            Self::Add(a, b) => match (a.eval(card), b.eval(card)) {
+                (a @ Value::Str(_), b) | (a, b @ Value::Str(_)) => Value::Str(format!("{a}{b}")),
+                (a, b) => arithmetic(a, b, i64::checked_add, |a, b| a + b),
+            },
+            Self::Sub(a, b) => {
+                arithmetic(a.eval(card), b.eval(card), i64::checked_sub, |a, b| a - b)
+            }
+            Self::Mul(a, b) => {
+                arithmetic(a.eval(card), b.eval(card), i64::checked_mul, |a, b| a * b)
+            }
+            Self::Div(a, b) => match (a.eval(card).as_number(), b.eval(card).as_number()) {
+                (Some(a), Some(b)) if b != 0.0 => Value::Float(a / b),
+                _ => Value::Nil,
+            },
+            Self::Rem(a, b) => arithmetic(
+                a.eval(card),
+                b.eval(card),
+                i64::checked_rem_euclid,
+                f64::rem_euclid,
+            ),
+        }
+    }
+}
+
+/// Applies an operation to two numbers, over integers if both are, or else over floats.
+fn arithmetic(
+    a: Value,
+    b: Value,
+    int_op: fn(i64, i64) -> Option<i64>,
+    float_op: fn(f64, f64) -> f64,
+) -> Value {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => int_op(a, b).map_or(Value::Nil, Value::Int),
+        (a, b) => match (a.as_number(), b.as_number()) {
+            (Some(a), Some(b)) => {
+                let x = float_op(a, b);
+                if x.is_finite() {
+                    Value::Float(x)
+                } else {
+                    Value::Nil
+                }
+            }
+            _ => Value::Nil,
+        },
+    }
+}
+
+impl Value {
+    /// Returns the value as a float only if it is a number, unlike `as_f64`, which also
+    /// parses strings.
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Int(x) => Some(*x as f64),
+            Value::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Logos)]
+#[logos(skip r"[ \t\n\f]+")]
+enum Token {
+    #[token("(")]
+    ParenO,
+    #[token(")")]
+    ParenC,
+    #[token("+")]
+    Plus,
+    #[token("-")]
+    Minus,
+    #[token("*")]
+    Star,
+    #[token("/")]
+    Slash,
+    #[token("%")]
+    Percent,
+    #[regex("[a-z_][a-z0-9_]*|`([^`]|``)*`", unescape_ident, ignore(case))]
+    Key(String),
+    #[regex("'([^']|'')*'", unescape_str)]
+    ValStr(String),
+    #[regex(r"\d+", |lex| lex.slice().parse().ok())]
+    ValInt(i64),
+    #[regex(r"(\d*\.\d+(e[+-]?\d+)?|\d+e[+-]?\d+)", |lex| lex.slice().parse().ok(), ignore(case))]
+    ValFloat(f64),
+    #[regex("true|false", |lex| lex.slice().to_lowercase().parse().ok(), ignore(case))]
+    ValBool(bool),
+    #[regex("NULL|NIL", ignore(case))]
+    ValNil,
+}
+
+fn unescape_ident(lex: &Lexer<Token>) -> String {
+    match lex.slice().strip_prefix('`') {
+        Some(key) => key[..key.len() - 1].replace("``", "`"),
+        None => lex.slice().to_string(),
+    }
+}
+
+fn unescape_str(lex: &Lexer<Token>) -> String {
+    let slice = lex.slice();
+    slice[1..slice.len() - 1].replace("''", "'")
+}
+
+/// Recursive descent parser for the grammar:
+///
+/// ```text
+/// E → T | E + T | E - T
+/// T → U | T * U | T / U | T % U
+/// U → - U | ( E ) | key | value
+/// ```
+struct Parser<'src> {
+    src: &'src str,
+    tokens: Peekable<logos::SpannedIter<'src, Token>>,
+}
+
+impl<'src> Parser<'src> {
+    fn new(src: &'src str) -> Self {
+        Self { src, tokens: Token::lexer(src).spanned().peekable() }
+    }
+
+    fn parse(mut self) -> Result<Expr> {
+        let expr = self.parse_e()?;
+        match self.tokens.next() {
+            None => Ok(expr),
+            Some((Err(_), span)) => Err(Error::scan(&self.src[span])),
+            Some((Ok(_), span)) => Err(Error::syntax_error_expecting(
+                "an operator",
+                self.src,
+                span.start,
+            )),
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>> {
+        match self.tokens.next() {
+            Some((Ok(token), _)) => Ok(Some(token)),
+            Some((Err(_), span)) => Err(Error::scan(&self.src[span])),
+            None => Ok(None),
+        }
+    }
+
+    fn peek_token(&mut self) -> Option<&Token> {
+        self.tokens
+            .peek()
+            .and_then(|(token, _)| token.as_ref().ok())
+    }
+
+    fn parse_e(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_t()?;
+        loop {
+            let op: fn(Box<Expr>, Box<Expr>) -> Expr = match self.peek_token() {
+                Some(Token::Plus) => Expr::Add,
+                Some(Token::Minus) => Expr::Sub,
+                _ => return Ok(expr),
+            };
+            self.next_token()?;
+            expr = op(Box::new(expr), Box::new(self.parse_t()?));
+        }
+    }
+
+    fn parse_t(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_u()?;
+        loop {
+            let op: fn(Box<Expr>, Box<Expr>) -> Expr = match self.peek_token() {
+                Some(Token::Star) => Expr::Mul,
+                Some(Token::Slash) => Expr::Div,
+                Some(Token::Percent) => Expr::Rem,
+                _ => return Ok(expr),
+            };
+            self.next_token()?;
+            expr = op(Box::new(expr), Box::new(self.parse_u()?));
+        }
+    }
+
+    fn parse_u(&mut self) -> Result<Expr> {
+        let start = self
+            .tokens
+            .peek()
+            .map_or(self.src.len(), |(_, span)| span.start);
+        match self.next_token()? {
+            Some(Token::Minus) => Ok(Expr::Neg(Box::new(self.parse_u()?))),
+            Some(Token::ParenO) => {
+                let expr = self.parse_e()?;
+                match self.next_token()? {
+                    Some(Token::ParenC) => Ok(expr),
+                    _ => Err(Error::syntax_error_expecting(")", self.src, start)),
+                }
+            }
+            Some(Token::Key(k)) => Ok(Expr::Field(k)),
+            Some(Token::ValStr(v)) => Ok(Expr::Value(Value::Str(v))),
+            Some(Token::ValInt(v)) => Ok(Expr::Value(Value::Int(v))),
+            Some(Token::ValFloat(v)) => Ok(Expr::Value(Value::Float(v))),
+            Some(Token::ValBool(v)) => Ok(Expr::Value(Value::Bool(v))),
+            Some(Token::ValNil) => Ok(Expr::Value(Value::Nil)),
+            _ => Err(Error::syntax_error_expecting("a value", self.src, start)),
+        }
+    }
+}
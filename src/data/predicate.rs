@@ -10,6 +10,15 @@ use std::fmt::Display;
 /// Abstract representation of a predicate.
 ///
 /// A predicate can be created directly or parsed from a string, using a SQL like syntax.
+/// Whitespace, including line breaks, is ignored, as is everything from `--` to the end of
+/// a line, so that long predicates can be written over several commented lines.
+/// Values can be left as named parameters, written as `:name`, to be given later with
+/// [`Predicate::bind`], so that the same filter can be reused with different values.
+///
+/// Fields holding lists of values, such as `fire;flying`, can be matched by their items with
+/// `HAS`, as in `tags HAS 'flying'`, or against a set with `HAS ANY` and `HAS ALL`, as in
+/// `tags HAS ALL ('fire', 'flying')`. Items are separated by [`DEFAULT_LIST_DELIMITER`],
+/// unless changed with [`Predicate::with_delimiter`].
 ///
 /// # Example
 /// ```
@@ -35,12 +44,75 @@ pub enum Predicate {
     Le(String, Value),
     Gt(String, Value),
     Ge(String, Value),
+    /// Whether a field holding a list contains any of the values, with items separated by the
+    /// delimiter in the last field.
+    HasAny(String, ValueSet, String),
+    /// Whether a field holding a list contains all of the values, with items separated by the
+    /// delimiter in the last field.
+    HasAll(String, ValueSet, String),
+    /// A comparison whose value is the parameter with the given name, yet to be bound.
+    Param(ParamComparison, String),
+}
+
+/// A comparison of a field with a parameter, by operator, holding the key of the field. It
+/// becomes the same comparison with a value once the parameter is bound.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamComparison {
+    Eq(String),
+    Neq(String),
+    Like(String),
+    Lt(String),
+    Le(String),
+    Gt(String),
+    Ge(String),
+    /// Whether a field holding a list contains the value, with items separated by the
+    /// delimiter in the last field.
+    Has(String, String),
+}
+
+impl ParamComparison {
+    /// The comparison with a value in place of the parameter. Lists only hold integers and
+    /// strings, so `HAS` with any other value is never satisfied.
+    fn with_value(self, value: Value) -> Predicate {
+        match self {
+            Self::Eq(k) => Predicate::Eq(k, value),
+            Self::Neq(k) => Predicate::Neq(k, value),
+            Self::Like(k) => Predicate::Like(k, value),
+            Self::Lt(k) => Predicate::Lt(k, value),
+            Self::Le(k) => Predicate::Le(k, value),
+            Self::Gt(k) => Predicate::Gt(k, value),
+            Self::Ge(k) => Predicate::Ge(k, value),
+            Self::Has(k, d) => match value {
+                Value::Int(v) => Predicate::HasAny(k, ValueSet::Int(HashSet::from([v])), d),
+                Value::Str(v) => Predicate::HasAny(k, ValueSet::Str(HashSet::from([v])), d),
+                _ => Predicate::HasAny(k, ValueSet::Str(HashSet::new()), d),
+            },
+        }
+    }
+
+    fn fmt_with(&self, f: &mut std::fmt::Formatter<'_>, name: &str) -> std::fmt::Result {
+        match self {
+            Self::Eq(k) => write!(f, "{} = :{name}", escape_key(k)),
+            Self::Neq(k) => write!(f, "{} != :{name}", escape_key(k)),
+            Self::Like(k) => write!(f, "{} LIKE :{name}", escape_key(k)),
+            Self::Lt(k) => write!(f, "{} < :{name}", escape_key(k)),
+            Self::Le(k) => write!(f, "{} <= :{name}", escape_key(k)),
+            Self::Gt(k) => write!(f, "{} > :{name}", escape_key(k)),
+            Self::Ge(k) => write!(f, "{} >= :{name}", escape_key(k)),
+            Self::Has(k, _) => write!(f, "{} HAS :{name}", escape_key(k)),
+        }
+    }
 }
 
+/// Delimiter between the items of list fields, used by `HAS` comparisons unless changed with
+/// [`Predicate::with_delimiter`].
+pub const DEFAULT_LIST_DELIMITER: &str = ";";
+
 #[derive(Debug, Clone)]
 enum AnyValue {
     Set(ValueSet),
     Unit(Value),
+    Param(String),
 }
 
 /// Represents a set of values, used in predicates.
@@ -53,8 +125,19 @@ pub enum ValueSet {
 impl Display for ValueSet {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Int(vs) => write!(f, "({})", vs.iter().join(", ")),
-            Self::Str(vs) => write!(f, "({})", vs.iter().join(", ")),
+            Self::Int(vs) => write!(f, "({})", vs.iter().sorted().join(", ")),
+            Self::Str(vs) => write!(f, "({})", vs.iter().sorted().map(escape_str).join(", ")),
+        }
+    }
+}
+
+impl ValueSet {
+    /// Whether an item of a list field is in the set, parsing it as an integer for sets of
+    /// integers.
+    fn contains_item(&self, item: &str) -> bool {
+        match self {
+            Self::Int(vs) => item.parse::<i64>().is_ok_and(|x| vs.contains(&x)),
+            Self::Str(vs) => vs.contains(item),
         }
     }
 }
@@ -100,11 +183,23 @@ impl std::ops::Not for Predicate {
 
 impl Predicate {
     /// Parses a string to a predicate.
+    ///
+    /// # Example
+    /// ```
+    /// use cartomata::data::Predicate;
+    ///
+    /// let p = Predicate::from_string(
+    ///     "power >= 100 -- strong enough
+    ///      AND type = 'spell' -- and not a trap",
+    /// );
+    /// assert_eq!(p.unwrap(), Predicate::from_string("power >= 100 AND type = 'spell'").unwrap());
+    /// ```
     pub fn from_string(predicate: &str) -> Result<Self> {
         Parser::new(predicate).parse()
     }
 
-    /// Evaluates a predicate on an input card.
+    /// Evaluates a predicate on an input card. Comparisons with unbound parameters are false,
+    /// so check them beforehand with [`ensure_bound`](Self::ensure_bound).
     pub fn eval(&self, card: &impl Card) -> bool {
         match self {
             Self::And(a, b) => a.eval(card) && b.eval(card),
@@ -127,12 +222,312 @@ impl Predicate {
             Self::Le(k, v) => &card.get(k) <= v,
             Self::Gt(k, v) => &card.get(k) > v,
             Self::Ge(k, v) => &card.get(k) >= v,
+            Self::HasAny(k, vs, d) => {
+                list_items(&card.get(k), d).iter().any(|item| vs.contains_item(item))
+            }
+            Self::HasAll(k, ValueSet::Int(vs), d) => {
+                let items: HashSet<i64> =
+                    list_items(&card.get(k), d).iter().filter_map(|x| x.parse().ok()).collect();
+                vs.is_subset(&items)
+            }
+            Self::HasAll(k, ValueSet::Str(vs), d) => {
+                let items: HashSet<String> = list_items(&card.get(k), d).into_iter().collect();
+                vs.is_subset(&items)
+            }
+            Self::Param(_, _) => false,
+        }
+    }
+
+    /// Sets the delimiter between the items of list fields in `HAS` comparisons.
+    ///
+    /// # Example
+    /// ```
+    /// use cartomata::data::{Card, Predicate};
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Card, Deserialize)]
+    /// struct MyCard {
+    ///     id: i64,
+    ///     tags: String,
+    /// }
+    ///
+    /// let card = |tags: &str| MyCard { id: 1, tags: tags.to_string() };
+    ///
+    /// let p = Predicate::from_string("tags HAS 'flying'").unwrap();
+    /// assert!(p.eval(&card("fire;flying")));
+    /// assert!(!p.eval(&card("fire, flying")));
+    ///
+    /// let p = Predicate::from_string("tags HAS ALL ('fire', 'flying')").unwrap();
+    /// let p = p.with_delimiter(", ");
+    /// assert!(p.eval(&card("flying, fire")));
+    /// assert!(!p.eval(&card("fire, water")));
+    /// ```
+    pub fn with_delimiter(self, delimiter: &str) -> Self {
+        match self {
+            Self::And(a, b) => a.with_delimiter(delimiter) & b.with_delimiter(delimiter),
+            Self::Or(a, b) => a.with_delimiter(delimiter) | b.with_delimiter(delimiter),
+            Self::Not(a) => !a.with_delimiter(delimiter),
+            Self::HasAny(k, vs, _) => Self::HasAny(k, vs, delimiter.to_string()),
+            Self::HasAll(k, vs, _) => Self::HasAll(k, vs, delimiter.to_string()),
+            Self::Param(ParamComparison::Has(k, _), name) => {
+                Self::Param(ParamComparison::Has(k, delimiter.to_string()), name)
+            }
+            p => p,
+        }
+    }
+
+    /// Replaces the parameter `name` with a value wherever it appears.
+    ///
+    /// # Example
+    /// ```
+    /// use cartomata::data::{Predicate, Value};
+    ///
+    /// let p = Predicate::from_string("rarity >= :min_rarity AND set = :set").unwrap();
+    /// assert_eq!(p.params(), vec!["min_rarity", "set"]);
+    ///
+    /// let p = p.bind("min_rarity", 4);
+    /// assert_eq!(p.params(), vec!["set"]);
+    /// assert!(p.ensure_bound().is_err());
+    ///
+    /// let p = p.bind("set", "core");
+    /// assert_eq!(p, Predicate::from_string("rarity >= 4 AND set = 'core'").unwrap());
+    /// ```
+    pub fn bind(self, name: &str, value: impl Into<Value>) -> Self {
+        self.bind_value(name, &value.into())
+    }
+
+    fn bind_value(self, name: &str, value: &Value) -> Self {
+        match self {
+            Self::And(a, b) => a.bind_value(name, value) & b.bind_value(name, value),
+            Self::Or(a, b) => a.bind_value(name, value) | b.bind_value(name, value),
+            Self::Not(a) => !a.bind_value(name, value),
+            Self::Param(p, n) if n == name => p.with_value(value.clone()),
+            p => p,
+        }
+    }
+
+    /// Names of the parameters still unbound, in the order they appear.
+    pub fn params(&self) -> Vec<&str> {
+        match self {
+            Self::And(a, b) | Self::Or(a, b) => {
+                let mut params = a.params();
+                params.extend(b.params());
+                params
+            }
+            Self::Not(a) => a.params(),
+            Self::Param(_, name) => vec![name.as_str()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Fails if any parameter is still unbound.
+    pub fn ensure_bound(&self) -> Result<()> {
+        match self.params().first() {
+            Some(name) => Err(Error::unbound_param(name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether a card satisfies the predicate, the same as [`eval`](Self::eval).
+    pub fn matches<C: Card>(&self, card: &C) -> bool {
+        self.eval(card)
+    }
+
+    /// Keeps only the cards that satisfy the predicate, e.g. to filter cards already in
+    /// memory without going through a data source.
+    ///
+    /// # Example
+    /// ```
+    /// use cartomata::data::{Card, Predicate};
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Card, Deserialize)]
+    /// struct MyCard {
+    ///     id: i64,
+    ///     power: i64,
+    /// }
+    ///
+    /// let cards = vec![
+    ///     MyCard { id: 1, power: 50 },
+    ///     MyCard { id: 2, power: 150 },
+    ///     MyCard { id: 3, power: 100 },
+    /// ];
+    /// let p = Predicate::from_string("power >= 100").unwrap();
+    /// let ids: Vec<_> = p.filter_iter(cards).map(|card| card.id).collect();
+    /// assert_eq!(ids, vec![2, 3]);
+    /// ```
+    pub fn filter_iter<C: Card>(
+        self,
+        iter: impl IntoIterator<Item = C>,
+    ) -> impl Iterator<Item = C> {
+        iter.into_iter().filter(move |card| self.eval(card))
+    }
+
+    /// Simplifies a predicate without changing its meaning: double negations are removed,
+    /// nested `AND`s and `OR`s are flattened, and repeated operands of each are dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use cartomata::data::{Predicate, Value};
+    ///
+    /// let a = Predicate::Eq("a".to_string(), Value::Int(1));
+    /// let b = Predicate::Eq("b".to_string(), Value::Int(2));
+    /// let c = Predicate::Eq("c".to_string(), Value::Int(3));
+    ///
+    /// assert_eq!((!!a.clone()).simplify(), a);
+    /// assert_eq!((!!!a.clone()).simplify(), !a.clone());
+    /// assert_eq!((a.clone() & (b.clone() & a.clone())).simplify(), a.clone() & b.clone());
+    /// assert_eq!(
+    ///     ((a.clone() | b.clone()) | (b.clone() | c.clone())).simplify(),
+    ///     a.clone() | b.clone() | c.clone()
+    /// );
+    /// assert_eq!((a.clone() & a.clone()).simplify(), a);
+    /// ```
+    pub fn simplify(self) -> Predicate {
+        match self {
+            Self::Not(a) => match a.simplify() {
+                Self::Not(a) => *a,
+                a => !a,
+            },
+            p @ Self::And(..) => {
+                let mut operands = Vec::new();
+                p.flatten(false, false, &mut operands);
+                Self::rebuild(operands, std::ops::BitAnd::bitand)
+            }
+            p @ Self::Or(..) => {
+                let mut operands = Vec::new();
+                p.flatten(true, false, &mut operands);
+                Self::rebuild(operands, std::ops::BitOr::bitor)
+            }
+            p => p,
+        }
+    }
+
+    /// Pushes the operands of a chain of `AND`s, or of `OR`s if `or` is set, to `operands`,
+    /// skipping repeated ones. Operands are simplified once each, unless already `simple`,
+    /// and flattened again if that makes them a chain of the same kind.
+    fn flatten(self, or: bool, simple: bool, operands: &mut Vec<Predicate>) {
+        match (self, or) {
+            (Self::And(a, b), false) | (Self::Or(a, b), true) => {
+                a.flatten(or, simple, operands);
+                b.flatten(or, simple, operands);
+            }
+            (p, _) if !simple => p.simplify().flatten(or, true, operands),
+            (p, _) => {
+                if !operands.contains(&p) {
+                    operands.push(p);
+                }
+            }
+        }
+    }
+
+    fn rebuild(operands: Vec<Predicate>, op: fn(Predicate, Predicate) -> Predicate) -> Predicate {
+        operands
+            .into_iter()
+            .reduce(op)
+            .expect("there is at least one operand")
+    }
+}
+
+/// Formats a predicate with the same syntax accepted by [`Predicate::from_string`], so that
+/// parsing the result gives back the same predicate.
+///
+/// # Example
+/// ```
+/// use cartomata::data::{Predicate, Value};
+///
+/// let sources = [
+///     "power >= 100 AND name LIKE 'it''s'",
+///     "a = 1 OR b != 2.0 AND NOT (c < -3 OR d > 4.5e-7)",
+///     "(a = 1 OR b = 2) AND `card type` IN ('spell', 'trap')",
+///     "a = 1 OR (b = 2 OR c = TRUE) AND `not` = NULL",
+///     "NOT NOT id IN (3, 1, 2)",
+///     "rarity >= :min_rarity AND name LIKE :name",
+///     "tags HAS 'fire' AND (tags HAS ANY (1, 2) OR `has` HAS ALL ('a', 'b'))",
+///     "tags has all ('x') OR tags HAS :tag",
+/// ];
+/// for src in sources {
+///     let p = Predicate::from_string(src).unwrap();
+///     assert_eq!(Predicate::from_string(&p.to_string()).unwrap(), p);
+/// }
+///
+/// // floats that aren't finite are written so that they can be parsed too
+/// let p = Predicate::Lt("a".into(), Value::Float(f64::INFINITY))
+///     | Predicate::Gt("b".into(), Value::Float(f64::NEG_INFINITY));
+/// assert_eq!(p.to_string(), "a < 1e999 OR b > -1e999");
+/// assert_eq!(Predicate::from_string(&p.to_string()).unwrap(), p);
+///
+/// let p = Predicate::Eq("a".into(), Value::Float(f64::NAN));
+/// assert_eq!(p.to_string(), "a = NaN");
+/// let p = Predicate::from_string(&p.to_string()).unwrap();
+/// assert!(matches!(p, Predicate::Eq(_, Value::Float(v)) if v.is_nan()));
+/// ```
+impl Display for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_prec(f, 0)
+    }
+}
+
+impl Predicate {
+    /// Writes the predicate, wrapped in parentheses if its precedence is lower than `prec`:
+    /// `OR` is 0, `AND` is 1, and everything else is 2.
+    fn fmt_prec(&self, f: &mut std::fmt::Formatter<'_>, prec: u8) -> std::fmt::Result {
+        match self {
+            Self::Or(_, _) if prec > 0 => write!(f, "({self})"),
+            Self::Or(a, b) => {
+                a.fmt_prec(f, 0)?;
+                write!(f, " OR ")?;
+                b.fmt_prec(f, 1)
+            }
+            Self::And(_, _) if prec > 1 => write!(f, "({self})"),
+            Self::And(a, b) => {
+                a.fmt_prec(f, 1)?;
+                write!(f, " AND ")?;
+                b.fmt_prec(f, 2)
+            }
+            Self::Not(a) => {
+                write!(f, "NOT ")?;
+                a.fmt_prec(f, 2)
+            }
+            Self::Eq(k, v) => write!(f, "{} = {}", escape_key(k), ValueLiteral(v)),
+            Self::Neq(k, v) => write!(f, "{} != {}", escape_key(k), ValueLiteral(v)),
+            Self::In(k, vs) => write!(f, "{} IN {vs}", escape_key(k)),
+            Self::Like(k, v) => write!(f, "{} LIKE {}", escape_key(k), ValueLiteral(v)),
+            Self::Lt(k, v) => write!(f, "{} < {}", escape_key(k), ValueLiteral(v)),
+            Self::Le(k, v) => write!(f, "{} <= {}", escape_key(k), ValueLiteral(v)),
+            Self::Gt(k, v) => write!(f, "{} > {}", escape_key(k), ValueLiteral(v)),
+            Self::Ge(k, v) => write!(f, "{} >= {}", escape_key(k), ValueLiteral(v)),
+            Self::HasAny(k, vs, _) => write!(f, "{} HAS ANY {vs}", escape_key(k)),
+            Self::HasAll(k, vs, _) => write!(f, "{} HAS ALL {vs}", escape_key(k)),
+            Self::Param(p, name) => p.fmt_with(f, name),
+        }
+    }
+}
+
+/// Formats a value as a literal in the predicate syntax.
+struct ValueLiteral<'a>(&'a Value);
+
+impl Display for ValueLiteral<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Value::Bool(v) => write!(f, "{v}"),
+            Value::Int(v) => write!(f, "{v}"),
+            // too large for a float, so parsed back as infinity
+            Value::Float(v) if v.is_infinite() => {
+                write!(f, "{}1e999", if *v < 0.0 { "-" } else { "" })
+            }
+            Value::Float(v) if v.is_nan() => write!(f, "NaN"),
+            // debug formatting always keeps a decimal point or an exponent
+            Value::Float(v) => write!(f, "{v:?}"),
+            Value::Str(v) => write!(f, "{}", escape_str(v)),
+            Value::Nil => write!(f, "NULL"),
         }
     }
 }
 
 #[derive(Debug, Clone, Logos)]
-#[logos(skip r"[ \t\n\f]+")]
+#[logos(skip r"[ \t\r\n\f]+")]
+#[logos(skip r"--[^\n]*")]
 enum Token {
     #[token("(")]
     ParenO,
@@ -148,18 +543,21 @@ enum Token {
     Or,
     #[regex("[a-z][a-z0-9-]*|`([^`]|``)*`", unescape_ident, ignore(case))]
     Key(String),
-    #[regex("=|!=|>|>=|<|<=|IN|LIKE", Operator::new, priority = 3, ignore(case))]
+    #[regex("=|!=|>|>=|<|<=|IN|LIKE|HAS", Operator::new, priority = 3, ignore(case))]
     Op(Operator),
     #[regex("'([^']|'')*'", unescape_str)]
     ValStr(String),
     #[regex(r"[+-]?\d+", parse_int)]
     ValInt(i64),
     #[regex(r"[+-]?(\d*\.\d+(e[+-]?\d+)?|\d+e[+-]?\d+)", parse_float, ignore(case))]
+    #[token("NaN", |_| f64::NAN)]
     ValFloat(f64),
     #[regex("true|false", parse_bool, ignore(case))]
     ValBool(bool),
     #[regex("NULL|NIL", ignore(case))]
     ValNil,
+    #[regex(":[a-z_][a-z0-9_]*", |lex| lex.slice()[1..].to_string(), ignore(case))]
+    Param(String),
 }
 
 impl std::fmt::Display for Token {
@@ -178,6 +576,7 @@ impl std::fmt::Display for Token {
             Self::ValFloat(v) => write!(f, "number {v}"),
             Self::ValBool(v) => write!(f, "boolean {v}"),
             Self::ValNil => write!(f, "NULL"),
+            Self::Param(name) => write!(f, "parameter :{name}"),
         }
     }
 }
@@ -192,6 +591,9 @@ enum Operator {
     Ge,
     In,
     Like,
+    Has,
+    HasAny,
+    HasAll,
 }
 
 impl Operator {
@@ -205,10 +607,29 @@ impl Operator {
             ">=" => Self::Ge,
             "IN" => Self::In,
             "LIKE" => Self::Like,
+            "HAS" => Self::has(lex),
             _ => unreachable!("invalid operator"),
         }
     }
 
+    /// Reads the `ANY` or `ALL` that may follow `HAS` into the same token, since the lexer
+    /// can't tell them apart from a plain `HAS` without backtracking.
+    fn has(lex: &mut Lexer<Token>) -> Self {
+        let rest = lex.remainder();
+        let word = rest.trim_start();
+        let skipped = rest.len() - word.len();
+        let end = word
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .unwrap_or(word.len());
+        let op = match word[..end].to_uppercase().as_str() {
+            "ANY" if skipped > 0 => Self::HasAny,
+            "ALL" if skipped > 0 => Self::HasAll,
+            _ => return Self::Has,
+        };
+        lex.bump(skipped + end);
+        op
+    }
+
     fn predicate(self, key: String, val: AnyValue) -> Result<Predicate> {
         match (&self, val) {
             (Self::Eq, AnyValue::Unit(v)) => Ok(Predicate::Eq(key, v)),
@@ -219,7 +640,42 @@ impl Operator {
             (Self::Ge, AnyValue::Unit(v)) => Ok(Predicate::Ge(key, v)),
             (Self::In, AnyValue::Set(v)) => Ok(Predicate::In(key, v)),
             (Self::Like, AnyValue::Unit(v)) => Ok(Predicate::Like(key, v)),
-            (Self::In, AnyValue::Unit(v)) => Err(Error::predicate_operand(self, "a set", v)),
+            (Self::Has, AnyValue::Unit(Value::Int(v))) => {
+                let vs = ValueSet::Int(HashSet::from([v]));
+                Ok(Predicate::HasAny(key, vs, DEFAULT_LIST_DELIMITER.to_string()))
+            }
+            (Self::Has, AnyValue::Unit(Value::Str(v))) => {
+                let vs = ValueSet::Str(HashSet::from([v]));
+                Ok(Predicate::HasAny(key, vs, DEFAULT_LIST_DELIMITER.to_string()))
+            }
+            (Self::Has, AnyValue::Unit(v)) => {
+                Err(Error::predicate_operand(self, "an integer or a string", v))
+            }
+            (Self::HasAny, AnyValue::Set(v)) => {
+                Ok(Predicate::HasAny(key, v, DEFAULT_LIST_DELIMITER.to_string()))
+            }
+            (Self::HasAll, AnyValue::Set(v)) => {
+                Ok(Predicate::HasAll(key, v, DEFAULT_LIST_DELIMITER.to_string()))
+            }
+            (_, AnyValue::Param(name)) => {
+                let p = match self {
+                    Self::Eq => ParamComparison::Eq(key),
+                    Self::Neq => ParamComparison::Neq(key),
+                    Self::Lt => ParamComparison::Lt(key),
+                    Self::Le => ParamComparison::Le(key),
+                    Self::Gt => ParamComparison::Gt(key),
+                    Self::Ge => ParamComparison::Ge(key),
+                    Self::Like => ParamComparison::Like(key),
+                    Self::Has => ParamComparison::Has(key, DEFAULT_LIST_DELIMITER.to_string()),
+                    Self::In | Self::HasAny | Self::HasAll => {
+                        return Err(Error::predicate_operand(self, "a set", format!(":{name}")))
+                    }
+                };
+                Ok(Predicate::Param(p, name))
+            }
+            (Self::In | Self::HasAny | Self::HasAll, AnyValue::Unit(v)) => {
+                Err(Error::predicate_operand(self, "a set", v))
+            }
             (_, AnyValue::Set(v)) => Err(Error::predicate_operand(self, "a single value", v)),
         }
     }
@@ -236,6 +692,9 @@ impl std::fmt::Display for Operator {
             Self::Ge => write!(f, ">="),
             Self::In => write!(f, "IN"),
             Self::Like => write!(f, "LIKE"),
+            Self::Has => write!(f, "HAS"),
+            Self::HasAny => write!(f, "HAS ANY"),
+            Self::HasAll => write!(f, "HAS ALL"),
         }
     }
 }
@@ -259,6 +718,29 @@ fn escape_str(s: &String) -> String {
     format!("'{}'", s.replace("'", "''"))
 }
 
+fn escape_key(key: &str) -> String {
+    const KEYWORDS: [&str; 10] =
+        ["NOT", "AND", "OR", "IN", "LIKE", "HAS", "TRUE", "FALSE", "NULL", "NIL"];
+    let mut chars = key.chars();
+    let plain = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !KEYWORDS.iter().any(|kw| kw.eq_ignore_ascii_case(key));
+    if plain {
+        key.to_string()
+    } else {
+        format!("`{}`", key.replace("`", "``"))
+    }
+}
+
+/// Items of a field holding a list of values separated by `delimiter`.
+fn list_items(value: &Value, delimiter: &str) -> Vec<String> {
+    match value {
+        Value::Nil => Vec::new(),
+        value if delimiter.is_empty() => vec![value.to_string()],
+        value => value.to_string().split(delimiter).map(String::from).collect(),
+    }
+}
+
 fn parse_int(lex: &Lexer<Token>) -> i64 {
     lex.slice().parse().unwrap()
 }
@@ -559,6 +1041,7 @@ impl<'src> Parser<'src> {
         [14, ValFloat _] = shift 17
         [14, ValBool _] = shift 18
         [14, ValNil] = shift 19
+        [14, Param _] = shift 30
         [14, _] = error "a value"
         [15, ParenC] = reduce 7
         [15, And] = reduce 7
@@ -610,10 +1093,15 @@ impl<'src> Parser<'src> {
         [27, ValStr _] = shift 28
         [27, _] = error "a string"
         [28, Comma] = shift 29
-        [28, ParenC] = reduce 9
+        [28, ParenC] = reduce 10
         [28, _] = error "`,` or `)`"
         [29, ValStr _] = reduce 13
         [29, _] = error "a string"
+        [30, ParenC] = reduce 20
+        [30, And] = reduce 20
+        [30, Or] = reduce 20
+        [30, end] = reduce 20
+        [30, _] = error "AND, OR, `)` or end of expression"
     }
 
     goto_table! {
@@ -653,5 +1141,67 @@ impl<'src> Parser<'src> {
         17: V  -> [ :ValFloat(v) { AnyValue::Unit(Value::Float(v)) } ]
         18: V  -> [ :ValBool(v) { AnyValue::Unit(Value::Bool(v)) } ]
         19: V  -> [ :ValNil { AnyValue::Unit(Value::Nil) } ]
+        20: V  -> [ :Param(name) { AnyValue::Param(name) } ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eq(field: &str) -> Predicate {
+        Predicate::Eq(field.to_string(), Value::Int(1))
+    }
+
+    #[test]
+    fn simplify_removes_double_negations() {
+        assert_eq!((!!eq("a")).simplify(), eq("a"));
+        assert_eq!((!!!eq("a")).simplify(), !eq("a"));
+        assert_eq!((!!(!!eq("a") & eq("b"))).simplify(), eq("a") & eq("b"));
+    }
+
+    #[test]
+    fn simplify_flattens_ands() {
+        let p = (eq("a") & eq("b")) & (eq("c") & (eq("d") & eq("e")));
+        assert_eq!(p.simplify(), eq("a") & eq("b") & eq("c") & eq("d") & eq("e"));
+        // chains revealed by removing a double negation are flattened too
+        let p = eq("a") & !!(eq("b") & eq("c"));
+        assert_eq!(p.simplify(), eq("a") & eq("b") & eq("c"));
+    }
+
+    #[test]
+    fn simplify_flattens_ors() {
+        let p = (eq("a") | eq("b")) | (eq("c") | (eq("d") | eq("e")));
+        assert_eq!(p.simplify(), eq("a") | eq("b") | eq("c") | eq("d") | eq("e"));
+        let p = eq("a") | !!(eq("b") | eq("c"));
+        assert_eq!(p.simplify(), eq("a") | eq("b") | eq("c"));
+    }
+
+    #[test]
+    fn simplify_keeps_mixed_chains_apart() {
+        let p = eq("a") & (eq("b") | (eq("c") | eq("d")));
+        assert_eq!(p.simplify(), eq("a") & (eq("b") | eq("c") | eq("d")));
+        let p = eq("a") | (eq("b") & (eq("c") & eq("d")));
+        assert_eq!(p.simplify(), eq("a") | (eq("b") & eq("c") & eq("d")));
+    }
+
+    #[test]
+    fn simplify_drops_repeated_operands() {
+        assert_eq!((eq("a") & eq("a")).simplify(), eq("a"));
+        assert_eq!((eq("a") & (eq("b") & eq("a"))).simplify(), eq("a") & eq("b"));
+        assert_eq!((eq("a") | (eq("b") | !!eq("a"))).simplify(), eq("a") | eq("b"));
+    }
+
+    #[test]
+    fn simplify_long_chains() {
+        let fields: Vec<_> = (0..60).map(|i| format!("f{i}")).collect();
+        let flat = fields.iter().map(|f| eq(f)).reduce(|a, b| a & b).unwrap();
+        let nested = fields.iter().rev().map(|f| eq(f)).reduce(|a, b| b & a).unwrap();
+        assert_eq!(nested.simplify(), flat);
+        assert_eq!(flat.clone().simplify(), flat);
+
+        let flat = fields.iter().map(|f| eq(f)).reduce(|a, b| a | b).unwrap();
+        let nested = fields.iter().rev().map(|f| !!eq(f)).reduce(|a, b| b | a).unwrap();
+        assert_eq!(nested.simplify(), flat);
     }
 }
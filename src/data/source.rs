@@ -1,16 +1,25 @@
 //! Implementations for different data sources.
 //!
-//! Each data source type has to be enabled with its respective feature, e.g. `csv`, `sqlite`.
+//! Each data source type has to be enabled with its respective feature, e.g. `csv`, `sqlite`,
+//! `xlsx`. With the `remote` feature, CSV can also be read from `http://` and `https://` URLs.
 
 #[cfg(feature = "csv")]
 mod csv;
+#[cfg(feature = "remote")]
+mod remote;
 #[cfg(feature = "sqlite")]
 mod sqlite;
+#[cfg(feature = "xlsx")]
+mod xlsx;
 
 #[cfg(feature = "csv")]
 pub use crate::data::source::csv::{CsvSource, CsvSourceConfig};
+#[cfg(feature = "remote")]
+pub use crate::data::source::remote::{fetch_url, is_url};
 #[cfg(feature = "sqlite")]
 pub use crate::data::source::sqlite::{SqliteSource, SqliteSourceConfig};
+#[cfg(feature = "xlsx")]
+pub use crate::data::source::xlsx::{XlsxSource, XlsxSourceConfig};
 use crate::data::Card;
 use crate::data::Predicate;
 use crate::error::Result;
@@ -25,4 +34,23 @@ pub trait DataSource<C: Card>: Send {
         &mut self,
         filter: Option<Predicate>,
     ) -> Result<Box<dyn Iterator<Item = Result<C>> + '_>>;
+
+    /// Reads the data source like `read`, along with a key for each card that identifies it
+    /// within the source, such as the line it is in, so that it stays the same regardless of
+    /// the filter. Defaults to the position of each card among the ones read, for sources
+    /// that have no such key.
+    fn read_indexed(
+        &mut self,
+        filter: Option<Predicate>,
+    ) -> Result<Box<dyn Iterator<Item = (i64, Result<C>)> + '_>> {
+        let iterator = self.read(filter)?.enumerate().map(|(i, card)| (i as i64, card));
+        Ok(Box::new(iterator))
+    }
+
+    /// Counts how many cards would be read with the given predicate, if that can be done
+    /// cheaply before reading them. Defaults to `None`.
+    #[allow(unused_variables)]
+    fn count(&self, filter: Option<&Predicate>) -> Option<usize> {
+        None
+    }
 }
@@ -3,8 +3,11 @@
 use crate::data::{Card, DataSource, Predicate};
 use crate::error::{Error, Result};
 
-use itertools::Itertools;
+#[cfg(feature = "gzip")]
+use flate2::read::GzDecoder;
 use serde::Deserialize;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
 /// Configurations for reading a CSV file.
@@ -16,6 +19,14 @@ pub struct CsvSourceConfig {
     /// Whether the input file contains a header. Defaults to `true`.
     #[serde(default = "default_header")]
     pub header: bool,
+    /// Number of cards to skip before the first one read, counting only cards that match
+    /// the filter. Defaults to none.
+    #[serde(default)]
+    pub skip: Option<usize>,
+    /// Maximum number of cards to read, counting only cards that match the filter. Reading
+    /// stops as soon as it is reached. Defaults to all cards.
+    #[serde(default)]
+    pub take: Option<usize>,
 }
 
 fn default_delimiter() -> char {
@@ -28,7 +39,12 @@ fn default_header() -> bool {
 
 impl Default for CsvSourceConfig {
     fn default() -> Self {
-        CsvSourceConfig { delimiter: default_delimiter(), header: default_header() }
+        CsvSourceConfig {
+            delimiter: default_delimiter(),
+            header: default_header(),
+            skip: None,
+            take: None,
+        }
     }
 }
 
@@ -57,21 +73,57 @@ impl Default for CsvSourceConfig {
 /// let p = Predicate::from_string("power < 3.0").unwrap();
 /// let cards: Vec<Result<MyCard>> = csv_source.read(Some(p)).unwrap().collect();
 /// assert_eq!(cards[0], Ok(MyCard { id: 271, name: "E".to_string(), power: 2.71 }));
+///
+/// let config = CsvSourceConfig { skip: Some(1), take: Some(1), ..Default::default() };
+/// let mut csv_source = CsvSource::open(config, &path).unwrap();
+/// let cards: Vec<Result<MyCard>> = csv_source.read(None).unwrap().collect();
+/// assert_eq!(cards, vec![Ok(MyCard { id: 271, name: "E".to_string(), power: 2.71 })]);
+///
+/// // cards keep the line they are in, even when others are filtered out
+/// let mut csv_source = CsvSource::open(CsvSourceConfig::default(), &path).unwrap();
+/// let p = Predicate::from_string("power < 3.0").unwrap();
+/// let cards: Vec<(i64, Result<MyCard>)> = csv_source.read_indexed(Some(p)).unwrap().collect();
+/// assert_eq!(cards, vec![(3, Ok(MyCard { id: 271, name: "E".to_string(), power: 2.71 }))]);
 /// ```
 pub struct CsvSource {
-    reader: csv::Reader<std::fs::File>,
+    reader: csv::Reader<Box<dyn Read + Send>>,
+    skip: Option<usize>,
+    take: Option<usize>,
 }
 
 impl CsvSource {
     /// Opens a CSV file according to the configurations, to be used a card data source.
+    /// With the `gzip` feature, files ending in `.gz` are decompressed while read.
     pub fn open(config: CsvSourceConfig, path: &impl AsRef<Path>) -> Result<CsvSource> {
         let path = path.as_ref();
+        let file = File::open(path).map_err(|e| Error::source_open(path, e))?;
+        #[cfg(feature = "gzip")]
+        if path.extension().is_some_and(|ext| ext == "gz") {
+            return Ok(Self::from_reader(config, GzDecoder::new(file)));
+        }
+        Ok(Self::from_reader(config, file))
+    }
+
+    /// Reads a CSV file from an `http://` or `https://` URL, such as a sheet published online
+    /// as CSV, according to the configurations. The body is read as cards are read. With the
+    /// `gzip` feature, URLs whose path ends in `.gz` are decompressed while read.
+    #[cfg(feature = "remote")]
+    pub fn open_url(config: CsvSourceConfig, url: &str) -> Result<CsvSource> {
+        let body = crate::data::source::fetch_url(url)?;
+        #[cfg(feature = "gzip")]
+        if url.split(['?', '#']).next().is_some_and(|path| path.ends_with(".gz")) {
+            return Ok(Self::from_reader(config, GzDecoder::new(body)));
+        }
+        Ok(Self::from_reader(config, body))
+    }
+
+    /// Reads CSV data from any reader according to the configurations.
+    pub fn from_reader(config: CsvSourceConfig, reader: impl Read + Send + 'static) -> CsvSource {
         let reader = csv::ReaderBuilder::new()
             .delimiter(config.delimiter as u8)
             .has_headers(config.header)
-            .from_path(path)
-            .map_err(|e| Error::source_open(path, e))?;
-        Ok(Self { reader })
+            .from_reader(Box::new(reader) as Box<dyn Read + Send>);
+        Self { reader, skip: config.skip, take: config.take }
     }
 }
 
@@ -80,13 +132,39 @@ impl<C: Card> DataSource<C> for CsvSource {
         &mut self,
         filter: Option<Predicate>,
     ) -> Result<Box<dyn Iterator<Item = Result<C>> + '_>> {
-        let iterator = self
-            .reader
-            .deserialize::<C>()
-            .map(|r| r.map_err(Error::record_read));
+        let iterator = self.read_indexed(filter)?.map(|(_, card)| card);
+        Ok(Box::new(iterator))
+    }
+
+    /// Reads the cards along with the line each one starts at, counting from 1.
+    fn read_indexed(
+        &mut self,
+        filter: Option<Predicate>,
+    ) -> Result<Box<dyn Iterator<Item = (i64, Result<C>)> + '_>> {
+        let headers = if self.reader.has_headers() {
+            Some(self.reader.headers().map_err(Error::record_read)?.clone())
+        } else {
+            None
+        };
+        let iterator = self.reader.records().map(move |record| match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line() as i64);
+                let card = record.deserialize(headers.as_ref()).map_err(Error::record_read);
+                (line, card)
+            }
+            Err(e) => (e.position().map_or(0, |p| p.line() as i64), Err(Error::record_read(e))),
+        });
 
-        match filter {
-            Some(filter) => Ok(Box::new(iterator.filter_ok(move |card| filter.eval(card)))),
+        let iterator: Box<dyn Iterator<Item = (i64, Result<C>)>> = match filter {
+            Some(filter) => Box::new(iterator.filter(move |(_, card)| match card {
+                Ok(card) => filter.eval(card),
+                Err(_) => true,
+            })),
+            None => Box::new(iterator),
+        };
+        let iterator = iterator.skip(self.skip.unwrap_or(0));
+        match self.take {
+            Some(take) => Ok(Box::new(iterator.take(take))),
             None => Ok(Box::new(iterator)),
         }
     }
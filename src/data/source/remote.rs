@@ -0,0 +1,20 @@
+//! Reading data sources over HTTP, such as a sheet published online as CSV.
+
+use crate::error::{Error, Result};
+
+use std::io::Read;
+
+/// Whether a path given as input is an `http://` or `https://` URL instead of a local file.
+pub fn is_url(path: &str) -> bool {
+    let scheme = path.split_once("://").map(|(scheme, _)| scheme.to_lowercase());
+    matches!(scheme.as_deref(), Some("http" | "https"))
+}
+
+/// Requests a URL, returning a reader over the body of the response. Responses with an
+/// error status, like a sheet that is no longer published, fail as if the source could not be
+/// opened.
+pub fn fetch_url(url: &str) -> Result<impl Read + Send + 'static> {
+    reqwest::blocking::get(url)
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| Error::source_open(url, e))
+}
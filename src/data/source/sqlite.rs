@@ -25,6 +25,10 @@ pub struct SqliteSourceConfig {
     /// If `None`, the default query is used instead, and the predicate is appended at the end
     /// of the string.
     pub with_predicate: Option<String>,
+    /// Pragmas run in order right after opening the file, written without the `PRAGMA`
+    /// keyword, e.g. `case_sensitive_like = ON`. Opening fails if any of them fails.
+    #[serde(default)]
+    pub pragmas: Vec<String>,
 }
 
 /// A reader for a SQLite file as a card data source.
@@ -44,16 +48,38 @@ pub struct SqliteSourceConfig {
 /// }
 ///
 /// let path = "examples/sample.db".to_string();
-/// let config = SqliteSourceConfig { query: "SELECT * FROM card".into(), with_predicate: None };
-/// let mut sqlite_source = SqliteSource::open(config, &path).unwrap();
+/// let config = SqliteSourceConfig {
+///     query: "SELECT * FROM card".into(),
+///     with_predicate: None,
+///     pragmas: Vec::new(),
+/// };
+/// let mut sqlite_source = SqliteSource::open(config.clone(), &path).unwrap();
 /// let cards: Vec<Result<MyCard>> = sqlite_source.read(None).unwrap().collect();
 /// assert_eq!(cards[0], Ok(MyCard { id: 271, name: "E".to_string(), power: 2.71 }));
 ///
-/// let config = SqliteSourceConfig { query: "SELECT * FROM card".into(), with_predicate: None };
-/// let mut sqlite_source = SqliteSource::open(config, &path).unwrap();
+/// let mut sqlite_source = SqliteSource::open(config.clone(), &path).unwrap();
 /// let p = Predicate::from_string("power >= 3.0").unwrap();
 /// let cards: Vec<Result<MyCard>> = sqlite_source.read(Some(p)).unwrap().collect();
 /// assert_eq!(cards[0], Ok(MyCard { id: 314, name: "Pi".to_string(), power: 3.14 }));
+///
+/// // fields holding lists are matched by whole items
+/// let tagged = SqliteSourceConfig {
+///     query: "SELECT *, 'core;' || lower(name) AS tags FROM card".into(),
+///     ..config.clone()
+/// };
+/// let mut sqlite_source = SqliteSource::open(tagged, &path).unwrap();
+/// let p = Predicate::from_string("tags HAS ALL ('core', 'pi') AND NOT tags HAS 'p'").unwrap();
+/// let cards: Vec<Result<MyCard>> = sqlite_source.read(Some(p)).unwrap().collect();
+/// assert_eq!(cards, vec![Ok(MyCard { id: 314, name: "Pi".to_string(), power: 3.14 })]);
+///
+/// // sets can be larger than the number of variables SQLite allows, which can be 999
+/// let ids = (1000..3000).map(|id| id.to_string()).collect::<Vec<_>>().join(", ");
+/// let names = (0..2000).map(|i| format!("'card {i}'")).collect::<Vec<_>>().join(", ");
+/// let p = format!("id IN (314, {ids}) OR name IN ('it''s', {names})");
+/// let mut sqlite_source = SqliteSource::open(config, &path).unwrap();
+/// let p = Predicate::from_string(&p).unwrap();
+/// let cards: Vec<Result<MyCard>> = sqlite_source.read(Some(p)).unwrap().collect();
+/// assert_eq!(cards, vec![Ok(MyCard { id: 314, name: "Pi".to_string(), power: 3.14 })]);
 /// ```
 pub struct SqliteSource {
     query: String,
@@ -63,8 +89,24 @@ pub struct SqliteSource {
 
 impl SqliteSource {
     pub fn open(config: SqliteSourceConfig, path: impl AsRef<Path>) -> Result<SqliteSource> {
+        Self::open_with(config, path, |_| Ok(()))
+    }
+
+    /// Like `open`, but also sets up the connection with `setup` after running the pragmas,
+    /// before any query, e.g. to register functions or collations used by filters.
+    pub fn open_with(
+        config: SqliteSourceConfig,
+        path: impl AsRef<Path>,
+        setup: impl FnOnce(&Connection) -> rusqlite::Result<()>,
+    ) -> Result<SqliteSource> {
         let path = path.as_ref();
         let connection = Connection::open(path).map_err(|e| Error::source_open(path, e))?;
+        for pragma in &config.pragmas {
+            connection
+                .execute_batch(&format!("PRAGMA {pragma};"))
+                .map_err(|e| Error::source_pragma(pragma, e))?;
+        }
+        setup(&connection).map_err(Error::source_prep)?;
         Ok(Self {
             query: config.query,
             with_predicate: config.with_predicate,
@@ -73,12 +115,10 @@ impl SqliteSource {
     }
 }
 
-impl<'s, C: Card> DataSource<C> for SqliteSource {
-    fn read(
-        &mut self,
-        filter: Option<Predicate>,
-    ) -> Result<Box<dyn Iterator<Item = Result<C>> + '_>> {
-        let (stmt, vars) = match &filter {
+impl SqliteSource {
+    /// Builds the query to be executed, with its variables, according to the predicate.
+    fn query<'p>(&self, filter: Option<&'p Predicate>) -> Result<(String, Vec<ToSqlOutput<'p>>)> {
+        match filter {
             Some(filter) => {
                 let (clause, vars) = filter.where_clause()?;
                 let query = self
@@ -91,17 +131,20 @@ impl<'s, C: Card> DataSource<C> for SqliteSource {
                         query.push_str(&clause);
                         query
                     });
-                self.connection
-                    .prepare(&query)
-                    .map_err(Error::source_prep)
-                    .map(|stmt| (stmt, vars))?
+                Ok((query, vars))
             }
-            None => self
-                .connection
-                .prepare(&self.query)
-                .map_err(Error::source_prep)
-                .map(|stmt| (stmt, Vec::new()))?,
-        };
+            None => Ok((self.query.clone(), Vec::new())),
+        }
+    }
+}
+
+impl<'s, C: Card> DataSource<C> for SqliteSource {
+    fn read(
+        &mut self,
+        filter: Option<Predicate>,
+    ) -> Result<Box<dyn Iterator<Item = Result<C>> + '_>> {
+        let (query, vars) = self.query(filter.as_ref())?;
+        let stmt = self.connection.prepare(&query).map_err(Error::source_prep)?;
 
         let mut stmt = AliasBox::new(stmt);
         let rows = from_rows::<C>(
@@ -111,6 +154,19 @@ impl<'s, C: Card> DataSource<C> for SqliteSource {
         let rows = unsafe { std::mem::transmute(rows) };
         Ok(Box::new(SqliteIterator { rows, _stmt: stmt }))
     }
+
+    fn count(&self, filter: Option<&Predicate>) -> Option<usize> {
+        let (query, vars) = self.query(filter).ok()?;
+        let count: i64 = self
+            .connection
+            .query_row(
+                &format!("SELECT COUNT(*) FROM ({query})"),
+                params_from_iter(vars.iter()),
+                |row| row.get(0),
+            )
+            .ok()?;
+        usize::try_from(count).ok()
+    }
 }
 
 struct SqliteIterator<'c, C: Card> {
@@ -140,6 +196,10 @@ impl Value {
     }
 }
 
+/// Number of values in a set above which they are written in the query instead of bound to
+/// variables.
+const MAX_SET_VARS: usize = 100;
+
 macro_rules! seq_write {
     ($f:ident; $str:literal) => {
         write!($f, $str)?
@@ -158,6 +218,7 @@ macro_rules! seq_write {
 impl Predicate {
     /// Formats a predicate into a SQLite `WHERE` clause.
     pub fn where_clause(&self) -> Result<(String, Vec<ToSqlOutput>)> {
+        self.ensure_bound()?;
         let mut buf = String::from("WHERE ");
         let mut vars = Vec::new();
         self.sql_r(&mut buf, &mut vars)
@@ -182,6 +243,14 @@ impl Predicate {
                 write!(buf, "{} != ?", esc_col(col))?;
                 vars.push(v.to_sql());
             }
+            // large sets are written as literals, as SQLite limits how many variables a query
+            // can have, to as few as 999 in older versions
+            Self::In(col, ValueSet::Int(vs)) if vs.len() > MAX_SET_VARS => {
+                write!(buf, "{} IN ({})", esc_col(col), vs.iter().join(", "))?;
+            }
+            Self::In(col, ValueSet::Str(vs)) if vs.len() > MAX_SET_VARS => {
+                write!(buf, "{} IN ({})", esc_col(col), vs.iter().map(esc_str).join(", "))?;
+            }
             Self::In(col, ValueSet::Int(vs)) => {
                 write!(buf, "{} IN ({})", esc_col(col), repeat_vars(vs.len()))?;
                 vars.extend(vs.iter().map(|v| ToSqlOutput::Owned(SqlValue::Integer(*v))));
@@ -213,15 +282,59 @@ impl Predicate {
                 write!(buf, "{} >= ?", esc_col(col))?;
                 vars.push(v.to_sql());
             }
+            Self::HasAny(col, vs, delimiter) => write_has(buf, vars, col, vs, delimiter, false)?,
+            Self::HasAll(col, vs, delimiter) => write_has(buf, vars, col, vs, delimiter, true)?,
+            // checked by `where_clause` beforehand
+            Self::Param(_, _) => return Err(std::fmt::Error),
         };
         Ok(())
     }
 }
 
+/// Writes whether a column holding a list contains any or all of the values in a set. Items
+/// are searched for with the delimiters around them, so that only whole items match.
+fn write_has(
+    buf: &mut String,
+    vars: &mut Vec<ToSqlOutput>,
+    col: &str,
+    vs: &ValueSet,
+    delimiter: &str,
+    all: bool,
+) -> std::fmt::Result {
+    let items = match vs {
+        ValueSet::Int(vs) => vs.iter().map(|v| v.to_string()).collect_vec(),
+        ValueSet::Str(vs) => vs.iter().cloned().collect_vec(),
+    };
+    if items.is_empty() {
+        buf.push_str(if all { "1" } else { "0" });
+        return Ok(());
+    }
+    let d = esc_str(delimiter);
+    let list = format!("({d} || {} || {d})", esc_col(col));
+    let large = items.len() > MAX_SET_VARS;
+    let clause = items
+        .into_iter()
+        .map(|item| {
+            let item = format!("{delimiter}{item}{delimiter}");
+            if large {
+                format!("instr({list}, {}) > 0", esc_str(item))
+            } else {
+                vars.push(ToSqlOutput::Owned(SqlValue::Text(item)));
+                format!("instr({list}, ?) > 0")
+            }
+        })
+        .join(if all { " AND " } else { " OR " });
+    write!(buf, "({clause})")
+}
+
 fn esc_col(s: impl AsRef<str>) -> String {
     format!("`{}`", s.as_ref().replace("`", "``"))
 }
 
+fn esc_str(s: impl AsRef<str>) -> String {
+    format!("'{}'", s.as_ref().replace('\'', "''"))
+}
+
 fn repeat_vars(n: usize) -> String {
     (0..n).map(|_| "?").join(", ")
 }
@@ -0,0 +1,272 @@
+//! Implementation for spreadsheets, such as Excel workbooks, as card data source.
+
+use crate::data::{Card, DataSource, Predicate, Value};
+use crate::error::{Error, Result};
+
+use calamine::{open_workbook_auto, Data, Range, Reader};
+use serde::de::value::{Error as DeError, MapDeserializer, StringDeserializer};
+use serde::de::{IntoDeserializer, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
+use std::path::Path;
+
+/// Configurations for reading a spreadsheet.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct XlsxSourceConfig {
+    /// Name of the worksheet to read. Defaults to the first one in the workbook.
+    pub sheet: Option<String>,
+}
+
+/// A reader for a worksheet of a spreadsheet as a card data source, supporting `.xlsx`,
+/// `.xls` and other formats read by `calamine`.
+///
+/// The first row of the sheet is its header, naming the field of each column, and each row
+/// after it is a card. Columns without a name and rows without any value are skipped.
+///
+/// Cells are read as the closest [`Value`]: numbers without a fractional part are integers,
+/// as spreadsheets only store floats, dates are strings like `2024-09-30`, or
+/// `2024-09-30T18:30:00` if they have a time, durations are floats in seconds, and empty
+/// cells are `Nil`. Cards can still ask for another type, e.g. a number read into a `String`
+/// field, or text that parses as a number read into a numeric field.
+///
+/// # Example
+/// ```
+/// use cartomata::data::source::{DataSource, XlsxSource, XlsxSourceConfig};
+/// use cartomata::data::{Card, Predicate};
+/// use cartomata::Result;
+/// use serde::Deserialize;
+///
+/// #[derive(Debug, Card, Deserialize, PartialEq)]
+/// struct MyCard {
+///     id: i64,
+///     name: String,
+///     power: f64,
+/// }
+///
+/// let path = "examples/sample.xlsx".to_string();
+/// let mut xlsx_source = XlsxSource::open(XlsxSourceConfig::default(), &path).unwrap();
+/// let cards: Vec<Result<MyCard>> = xlsx_source.read(None).unwrap().collect();
+/// assert_eq!(cards[0], Ok(MyCard { id: 314, name: "Pi".to_string(), power: 3.14 }));
+///
+/// // cards keep the row they are in, even when others are filtered out
+/// let p = Predicate::from_string("power < 3.0").unwrap();
+/// let cards: Vec<(i64, Result<MyCard>)> = xlsx_source.read_indexed(Some(p)).unwrap().collect();
+/// assert_eq!(cards, vec![(3, Ok(MyCard { id: 271, name: "E".to_string(), power: 2.71 }))]);
+///
+/// #[derive(Debug, Card, Deserialize, PartialEq)]
+/// struct MySet {
+///     id: String,
+///     released: String,
+/// }
+///
+/// let config = XlsxSourceConfig { sheet: Some("Sets".to_string()) };
+/// let mut xlsx_source = XlsxSource::open(config, &path).unwrap();
+/// let sets: Vec<Result<MySet>> = xlsx_source.read(None).unwrap().collect();
+/// let released = "2024-09-30".to_string();
+/// assert_eq!(sets, vec![Ok(MySet { id: "1".to_string(), released })]);
+/// ```
+pub struct XlsxSource {
+    /// Field name of each column, if it has one.
+    headers: Vec<Option<String>>,
+    /// Cells of the sheet, including the header.
+    range: Range<Data>,
+    /// Row number of the header, counting from 1 as spreadsheets do.
+    header_row: i64,
+}
+
+impl XlsxSource {
+    /// Opens a spreadsheet and reads the worksheet given by the configurations, to be used as
+    /// a card data source.
+    pub fn open(config: XlsxSourceConfig, path: &impl AsRef<Path>) -> Result<XlsxSource> {
+        let path = path.as_ref();
+        let mut workbook = open_workbook_auto(path).map_err(|e| Error::source_open(path, e))?;
+        let range = match &config.sheet {
+            Some(sheet) => workbook.worksheet_range(sheet),
+            None => workbook
+                .worksheet_range_at(0)
+                .unwrap_or(Err(calamine::Error::Msg("workbook has no worksheets"))),
+        };
+        let range = range.map_err(|e| Error::source_open(path, e))?;
+        let header_row = range.start().map_or(0, |(row, _)| row as i64 + 1);
+        let headers = range
+            .rows()
+            .next()
+            .unwrap_or_default()
+            .iter()
+            .map(|cell| Some(cell.to_string().trim().to_string()).filter(|h| !h.is_empty()))
+            .collect();
+        Ok(Self { headers, range, header_row })
+    }
+
+    /// Rows after the header that have any value.
+    fn data_rows(&self) -> impl Iterator<Item = (usize, &[Data])> {
+        self.range
+            .rows()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, row)| row.iter().any(|cell| *cell != Data::Empty))
+    }
+
+    /// Reads every card with the row it is in, skipping rows without any value.
+    fn rows<C: Card>(&self) -> impl Iterator<Item = (i64, Result<C>)> + '_ {
+        self.data_rows()
+            .map(|(i, row)| (self.header_row + i as i64, self.deserialize(row)))
+    }
+
+    fn deserialize<C: Card>(&self, row: &[Data]) -> Result<C> {
+        let fields = self
+            .headers
+            .iter()
+            .zip(row)
+            .filter_map(|(header, cell)| Some((header.as_deref()?, CellDeserializer::from(cell))));
+        C::deserialize(MapDeserializer::new(fields)).map_err(Error::record_read)
+    }
+}
+
+impl<C: Card> DataSource<C> for XlsxSource {
+    fn read(
+        &mut self,
+        filter: Option<Predicate>,
+    ) -> Result<Box<dyn Iterator<Item = Result<C>> + '_>> {
+        let iterator = self.read_indexed(filter)?.map(|(_, card)| card);
+        Ok(Box::new(iterator))
+    }
+
+    /// Reads the cards along with the row each one is in, counting from 1.
+    fn read_indexed(
+        &mut self,
+        filter: Option<Predicate>,
+    ) -> Result<Box<dyn Iterator<Item = (i64, Result<C>)> + '_>> {
+        let iterator = self.rows();
+        match filter {
+            Some(filter) => Ok(Box::new(iterator.filter(move |(_, card)| match card {
+                Ok(card) => filter.eval(card),
+                Err(_) => true,
+            }))),
+            None => Ok(Box::new(iterator)),
+        }
+    }
+
+    /// The sheet is read as a whole when opened, so cards are counted without a filter.
+    fn count(&self, filter: Option<&Predicate>) -> Option<usize> {
+        filter.is_none().then(|| self.data_rows().count())
+    }
+}
+
+/// Converts a cell to the closest value, as described in [`XlsxSource`].
+fn cell_value(cell: &Data) -> Value {
+    // integers beyond this can't be told apart from floats
+    const MAX_EXACT: f64 = (1u64 << f64::MANTISSA_DIGITS) as f64;
+    match cell {
+        Data::Int(v) => Value::Int(*v),
+        Data::Float(v) if v.fract() == 0.0 && v.abs() <= MAX_EXACT => Value::Int(*v as i64),
+        Data::Float(v) => Value::Float(*v),
+        Data::String(v) => Value::Str(v.clone()),
+        Data::Bool(v) => Value::Bool(*v),
+        Data::DateTime(v) if v.is_duration() => v
+            .as_duration()
+            .map_or(Value::Float(v.as_f64()), |d| Value::Float(d.num_milliseconds() as f64 / 1e3)),
+        Data::DateTime(v) => match v.as_datetime() {
+            Some(dt) if v.as_f64().fract() == 0.0 => Value::Str(dt.date().to_string()),
+            Some(dt) => Value::Str(format!("{}T{}", dt.date(), dt.time())),
+            None => Value::Float(v.as_f64()),
+        },
+        Data::DateTimeIso(v) | Data::DurationIso(v) => Value::Str(v.clone()),
+        // errors are kept as shown in the spreadsheet, e.g. `#N/A`
+        Data::Error(e) => Value::Str(e.to_string()),
+        Data::Empty => Value::Nil,
+    }
+}
+
+/// Deserializes a cell as the type asked for, when its value can be converted to it, like
+/// [`Value::as_i64`] and the like do, falling back to its own type.
+struct CellDeserializer(Value);
+
+impl From<&Data> for CellDeserializer {
+    fn from(cell: &Data) -> Self {
+        Self(cell_value(cell))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, DeError> for CellDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_as {
+    ($($fn:ident)* => $as:ident $visit:ident) => {
+        $(fn $fn<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, DeError> {
+            match self.0.$as() {
+                Some(v) => visitor.$visit(v),
+                None => self.deserialize_any(visitor),
+            }
+        })*
+    };
+}
+
+impl<'de> Deserializer<'de> for CellDeserializer {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, DeError> {
+        match self.0 {
+            Value::Int(v) => visitor.visit_i64(v),
+            Value::Float(v) => visitor.visit_f64(v),
+            Value::Str(v) => visitor.visit_string(v),
+            Value::Bool(v) => visitor.visit_bool(v),
+            Value::Nil => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, DeError> {
+        match self.0 {
+            Value::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, DeError> {
+        visitor.visit_string(self.0.to_string())
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, DeError> {
+        self.deserialize_string(visitor)
+    }
+
+    deserialize_as!(
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 => as_i64 visit_i64
+    );
+    deserialize_as!(deserialize_f32 deserialize_f64 => as_f64 visit_f64);
+    deserialize_as!(deserialize_bool => as_bool visit_bool);
+
+    /// Reads enums from the text of the cell, so that columns can name unit variants.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> std::result::Result<V::Value, DeError> {
+        let text: StringDeserializer<DeError> = self.0.to_string().into_deserializer();
+        text.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char bytes byte_buf unit unit_struct newtype_struct seq tuple tuple_struct
+        map struct identifier ignored_any
+    }
+}
@@ -19,6 +19,46 @@ impl Default for Value {
     }
 }
 
+impl Value {
+    /// Returns the string slice, if the value is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Str(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an integer, if it is an integer, a float without a fractional
+    /// part, or a string that parses as an integer.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Int(v) => Some(*v),
+            Self::Float(v) if v.fract() == 0.0 => Some(*v as i64),
+            Self::Str(v) => v.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a float, if it is a number or a string that parses as a float.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(v) => Some(*v as f64),
+            Self::Float(v) => Some(*v),
+            Self::Str(v) => v.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a boolean, if it is a boolean or a string that parses as one.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(v) => Some(*v),
+            Self::Str(v) => v.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
 macro_rules! value_from {
     ($($V:ty)+ => $Variant:ident($T:ty)) => {
         $(
@@ -47,6 +87,63 @@ impl From<String> for Value {
     }
 }
 
+/// Converts a JSON value into a field value. Scalars map to the variant of the same kind and
+/// `null` to `Nil`, while arrays and objects, which fields can't hold, are kept as their
+/// JSON text in a `Str`. Integers too large for an `i64` become `Float`s, losing precision.
+///
+/// # Example
+/// ```
+/// use cartomata::data::Value;
+/// use serde_json::json;
+///
+/// assert_eq!(Value::from(json!(3)), Value::Int(3));
+/// assert_eq!(Value::from(json!("fire")), Value::Str("fire".into()));
+/// assert_eq!(Value::from(json!(null)), Value::Nil);
+/// assert_eq!(Value::from(json!(["a", 1])), Value::Str(r#"["a",1]"#.into()));
+/// ```
+#[cfg(feature = "json")]
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        use serde_json::Value as Json;
+        match value {
+            Json::Null => Value::Nil,
+            Json::Bool(v) => Value::Bool(v),
+            Json::Number(v) => match v.as_i64() {
+                Some(v) => Value::Int(v),
+                None => v.as_f64().map(Value::Float).unwrap_or_default(),
+            },
+            Json::String(v) => Value::Str(v),
+            v @ (Json::Array(_) | Json::Object(_)) => Value::Str(v.to_string()),
+        }
+    }
+}
+
+/// Converts a field value into a JSON value. Every value maps to the JSON type of the same
+/// kind, except for floats that are NaN or infinite, which JSON can't represent, and so
+/// become `null`. Strings are never parsed, even if they were converted from arrays or objects.
+///
+/// # Example
+/// ```
+/// use cartomata::data::Value;
+/// use serde_json::json;
+///
+/// assert_eq!(serde_json::Value::from(Value::Float(2.5)), json!(2.5));
+/// assert_eq!(serde_json::Value::from(Value::Float(f64::NAN)), json!(null));
+/// assert_eq!(serde_json::Value::from(Value::Nil), json!(null));
+/// ```
+#[cfg(feature = "json")]
+impl From<Value> for serde_json::Value {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Int(v) => v.into(),
+            Value::Float(v) => serde_json::Number::from_f64(v).map_or(Self::Null, Self::Number),
+            Value::Str(v) => v.into(),
+            Value::Bool(v) => v.into(),
+            Value::Nil => Self::Null,
+        }
+    }
+}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
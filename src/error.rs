@@ -1,6 +1,7 @@
 //! Common error types.
 
 use std::path::{Path, PathBuf};
+use unicode_width::UnicodeWidthChar;
 
 /// A shortcut type equivalent to `Result<T, cartomata::Error>`.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -32,6 +33,13 @@ pub enum Error {
     SourcePrep {
         reason: String,
     },
+    RemoteSource {
+        url: String,
+    },
+    SourcePragma {
+        pragma: String,
+        reason: String,
+    },
     RecordRead {
         reason: String,
     },
@@ -48,6 +56,16 @@ pub enum Error {
     NoArtwork {
         key: String,
     },
+    NoAsset {
+        path: PathBuf,
+    },
+    UnboundParam {
+        name: String,
+    },
+    FilterOpen {
+        path: PathBuf,
+        reason: String,
+    },
     ExternalError {
         source: &'static str,
         reason: String,
@@ -86,6 +104,7 @@ pub enum Error {
         to: &'static str,
         reason: String,
     },
+    LayerNotRepeatable,
     SyntaxError {
         desc: String,
         expected: Option<String>,
@@ -112,6 +131,27 @@ pub enum Error {
     },
     ThreadJoin {
         worker: usize,
+        reason: String,
+    },
+    ThreadPool {
+        reason: String,
+    },
+    WorkerPanic {
+        worker: usize,
+        reason: String,
+    },
+    CardPanic {
+        reason: String,
+    },
+    LayerPanic {
+        layer: usize,
+        kind: &'static str,
+        reason: String,
+    },
+    NoWorkers,
+    FailFast {
+        card: String,
+        reason: Box<Error>,
     },
     IoError {
         reason: String,
@@ -149,6 +189,12 @@ impl std::fmt::Display for Error {
                 write!(f, "failed to open data source {}: {reason}", path.display())
             }
             Error::SourcePrep { reason } => write!(f, "failed to prepare data source: {reason}"),
+            Error::RemoteSource { url } => {
+                write!(f, "only CSV data sources can be read from a URL, such as {url}")
+            }
+            Error::SourcePragma { pragma, reason } => {
+                write!(f, "failed to run pragma `{pragma}` on data source: {reason}")
+            }
             Error::RecordRead { reason } => write!(f, "failed to read record: {reason}"),
             Error::DecoderOpen { path, reason } => {
                 write!(
@@ -160,6 +206,11 @@ impl std::fmt::Display for Error {
             Error::DecoderPrep { reason } => write!(f, "failed to prepare decoder: {reason}"),
             Error::Decode { reason } => write!(f, "failed to run decoder:\n{reason}"),
             Error::NoArtwork { key } => write!(f, "artwork image not found for `{key}`"),
+            Error::NoAsset { path } => write!(f, "asset not found at {}", path.display()),
+            Error::FilterOpen { path, reason } => {
+                write!(f, "failed to open filter `{}`: {reason}", path.display())
+            }
+            Error::UnboundParam { name } => write!(f, "no value given for parameter `:{name}`"),
             Error::ExternalError { source, reason } => write!(f, "from {source}: {reason}"),
             Error::ScanError { slice } => write!(f, "invalid input {slice:?}"),
             Error::TextInvalidAttr { tag, attr } => {
@@ -182,6 +233,9 @@ impl std::fmt::Display for Error {
             Error::ImageConversion { from, to, reason } => {
                 write!(f, "failed to convert image from {from} to {to}: {reason}")
             }
+            Error::LayerNotRepeatable => {
+                write!(f, "only layers that don't depend on the image below can be repeated")
+            }
             Error::SyntaxError { desc, expected: Some(expected) } => {
                 write!(f, "syntax error, expected {expected}:\n{desc}")
             }
@@ -204,7 +258,21 @@ impl std::fmt::Display for Error {
             Error::ThreadSend { reason } => {
                 write!(f, "failed to send message to thread: {reason}")
             }
-            Error::ThreadJoin { worker } => write!(f, "failed to join thread {worker:02}"),
+            Error::ThreadJoin { worker, reason } => {
+                write!(f, "failed to join thread {worker:02}: {reason}")
+            }
+            Error::ThreadPool { reason } => write!(f, "failed to start threads: {reason}"),
+            Error::WorkerPanic { worker, reason } => {
+                write!(f, "worker {worker:02} panicked: {reason}")
+            }
+            Error::CardPanic { reason } => write!(f, "panicked while processing card: {reason}"),
+            Error::LayerPanic { layer, kind, reason } => {
+                write!(f, "panicked while rendering layer {layer} ({kind}): {reason}")
+            }
+            Error::NoWorkers => write!(f, "all workers stopped before every card was rendered"),
+            Error::FailFast { card, reason } => {
+                write!(f, "stopped after card {card} failed: {reason}")
+            }
             Error::IoError { reason } => write!(f, "i/o error: {reason}"),
             _ => write!(f, "unexpected error"),
         }
@@ -245,10 +313,18 @@ impl Error {
         }
     }
 
+    pub fn remote_source(url: impl Into<String>) -> Self {
+        Self::RemoteSource { url: url.into() }
+    }
+
     pub fn source_prep(reason: impl std::error::Error) -> Self {
         Self::SourcePrep { reason: reason.to_string() }
     }
 
+    pub fn source_pragma(pragma: impl Into<String>, reason: impl std::error::Error) -> Self {
+        Self::SourcePragma { pragma: pragma.into(), reason: reason.to_string() }
+    }
+
     pub fn record_read(reason: impl std::error::Error) -> Self {
         Self::RecordRead { reason: reason.to_string() }
     }
@@ -272,6 +348,18 @@ impl Error {
         Self::NoArtwork { key: key.as_ref().to_string() }
     }
 
+    pub fn filter_open(path: impl AsRef<Path>, reason: impl std::error::Error) -> Self {
+        Self::FilterOpen { path: path.as_ref().to_path_buf(), reason: reason.to_string() }
+    }
+
+    pub fn unbound_param(name: impl AsRef<str>) -> Self {
+        Self::UnboundParam { name: name.as_ref().to_string() }
+    }
+
+    pub fn no_asset(path: impl AsRef<Path>) -> Self {
+        Self::NoAsset { path: path.as_ref().to_path_buf() }
+    }
+
     pub fn vips(reason: libvips::error::Error, extra: Option<&str>) -> Self {
         Self::ExternalError {
             source: "libvips",
@@ -330,6 +418,10 @@ impl Error {
         Self::ImageConversion { from: "cairo", to: "vips", reason: reason.to_string() }
     }
 
+    pub fn layer_not_repeatable() -> Self {
+        Self::LayerNotRepeatable
+    }
+
     pub fn predicate_operand(
         operator: impl std::fmt::Display,
         expected: &'static str,
@@ -358,8 +450,36 @@ impl Error {
         Self::ThreadSend { reason: reason.to_string() }
     }
 
-    pub fn thread_join(worker: usize) -> Self {
-        Self::ThreadJoin { worker }
+    pub fn thread_join(worker: usize, payload: Box<dyn std::any::Any + Send>) -> Self {
+        Self::ThreadJoin { worker, reason: panic_reason(payload) }
+    }
+
+    pub fn thread_pool(reason: impl std::error::Error) -> Self {
+        Self::ThreadPool { reason: reason.to_string() }
+    }
+
+    pub fn worker_panic(worker: usize, payload: Box<dyn std::any::Any + Send>) -> Self {
+        Self::WorkerPanic { worker, reason: panic_reason(payload) }
+    }
+
+    pub fn card_panic(payload: Box<dyn std::any::Any + Send>) -> Self {
+        Self::CardPanic { reason: panic_reason(payload) }
+    }
+
+    pub fn layer_panic(
+        layer: usize,
+        kind: &'static str,
+        payload: Box<dyn std::any::Any + Send>,
+    ) -> Self {
+        Self::LayerPanic { layer, kind, reason: panic_reason(payload) }
+    }
+
+    pub fn no_workers() -> Self {
+        Self::NoWorkers
+    }
+
+    pub fn fail_fast(card: impl Into<String>, reason: Error) -> Self {
+        Self::FailFast { card: card.into(), reason: Box::new(reason) }
     }
 
     pub fn syntax_error_expecting(expected: &str, src: &str, i: usize) -> Self {
@@ -369,6 +489,21 @@ impl Error {
         }
     }
 
+    /// Creates a syntax error at byte `i` of `src`, describing it with an excerpt of
+    /// the surrounding text and a caret under the offending character.
+    ///
+    /// Tabs are expanded and wide characters take up their display width, so the
+    /// caret stays aligned:
+    ///
+    /// ```
+    /// use cartomata::Error;
+    ///
+    /// let err = Error::syntax_error("名前 =\t@x", 9);
+    /// assert_eq!(err.to_string(), "syntax error:\n名前 =  @x\n        ^");
+    ///
+    /// let err = Error::syntax_error("año == \"café\" and ¿", 20);
+    /// assert_eq!(err.to_string(), "syntax error:\n... and ¿\n        ^");
+    /// ```
     pub fn syntax_error(src: &str, i: usize) -> Self {
         Self::SyntaxError { desc: str_excerpt(10, i, src), expected: None }
     }
@@ -382,25 +517,52 @@ impl Error {
     }
 }
 
-fn str_excerpt(n: usize, index: usize, src: &str) -> String {
-    let n_start = n / 2;
-    let n_end = n - n_start;
-    let mut start = index.saturating_sub(n_start); // i - st = nst
-    let mut end = index.saturating_add(n_end).clamp(0, src.len());
-    while start > 0 && !src.is_char_boundary(start) {
-        start -= 1;
-    }
-    while end < src.len() && !src.is_char_boundary(end) {
-        end += 1;
+fn panic_reason(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(reason) => *reason,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(reason) => reason.to_string(),
+            Err(_) => String::from("unknown reason"),
+        },
     }
+}
+
+/// Number of columns a tab advances to in error excerpts.
+const TAB_WIDTH: usize = 4;
+
+fn str_excerpt(n: usize, index: usize, src: &str) -> String {
+    let mut index = index.min(src.len());
+    while !src.is_char_boundary(index) {
+        index -= 1;
+    }
+    let start = src[..index]
+        .char_indices()
+        .rev()
+        .take(n / 2)
+        .last()
+        .map_or(index, |(i, _)| i);
+    let end = src[index..]
+        .char_indices()
+        .nth(n - n / 2)
+        .map_or(src.len(), |(i, _)| index + i);
     let prefix = if start > 0 { "..." } else { "" };
     let suffix = if end < src.len() { "..." } else { "" };
-    let padding = " ".repeat(
-        prefix.len()
-            + src[start..]
-                .char_indices()
-                .take_while(|(i, _)| *i < index - start)
-                .count(),
-    );
-    format!("{prefix}{}{suffix}\n{padding}^", &src[start..end])
+    let mut excerpt = String::from(prefix);
+    let mut width = 0;
+    let mut padding = None;
+    for (i, c) in src[start..end].char_indices() {
+        if start + i == index {
+            padding = Some(width);
+        }
+        if c == '\t' {
+            let n = TAB_WIDTH - width % TAB_WIDTH;
+            excerpt.push_str(&" ".repeat(n));
+            width += n;
+        } else {
+            excerpt.push(c);
+            width += c.width().unwrap_or(0);
+        }
+    }
+    let padding = " ".repeat(prefix.len() + padding.unwrap_or(width));
+    format!("{excerpt}{suffix}\n{padding}^")
 }
@@ -5,25 +5,52 @@ mod color;
 mod map;
 mod origin;
 mod stroke;
+#[cfg(feature = "svg")]
+mod svg;
 
 use crate::error::{Error, Result};
 pub use crate::image::blend::BlendMode;
-pub use crate::image::color::Color;
+pub use crate::image::color::{Color, Palette};
 pub use crate::image::map::ImageMap;
 pub use crate::image::origin::{Origin, TextOrigin};
 pub use crate::image::stroke::Stroke;
-use crate::text::attr::{Gravity, ITagAttr, LayoutAttr};
-use crate::text::{FontMap, Markup};
+#[cfg(feature = "svg")]
+pub use crate::image::svg::SvgDocument;
+use crate::text::attr::{shape_position, Gravity, ITagAttr, LayoutAttr};
+use crate::text::{FontMap, Markup, FONT_FILES_ADDED};
 
 use cairo::ImageSurface;
 use libvips::{ops, VipsApp, VipsImage};
 use pango::prelude::FontMapExt;
 #[cfg(feature = "cli")]
 use serde::Deserialize;
-use std::path::Path;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::OnceLock;
+
+/// libvips must be initialized only once per process, so every backend shares the same app.
+static VIPS_APP: OnceLock<Result<VipsApp>> = OnceLock::new();
+
+thread_local! {
+    /// Context used to lay out text, reused by every call to `print` in a thread, as creating
+    /// a font map loads the fonts again, and pango objects can't be shared across threads.
+    /// It is kept along with the number of font files added when it was created.
+    static TEXT_CONTEXT: RefCell<Option<(usize, pango::Context)>> = const { RefCell::new(None) };
+}
 
 pub struct ImgBackend {
-    vips_app: VipsApp,
+    vips_app: &'static VipsApp,
+}
+
+/// Resources that text is printed with: the fonts it names, and the images inlined in it.
+#[derive(Debug, Clone, Copy)]
+pub struct TextResources<'a> {
+    pub img_map: &'a ImageMap,
+    pub font_map: &'a FontMap,
+    /// Whether inlined images are stored with premultiplied alpha, to be converted to
+    /// straight alpha when loaded.
+    pub premultiplied_alpha: bool,
 }
 
 #[derive(Debug, Copy, PartialEq, Eq, Clone)]
@@ -41,11 +68,159 @@ impl Default for FitMode {
     }
 }
 
+/// How the bleed around a card is filled.
+#[derive(Debug, Copy, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "cli", derive(Deserialize))]
+#[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
+pub enum BleedMode {
+    /// Mirrors the pixels next to the edges.
+    #[default]
+    Mirror,
+    /// Repeats the pixels at the edges.
+    Copy,
+}
+
+/// Margin added around a card for printing, so that no blank edge is left if it is cut
+/// slightly off the trim box.
+#[derive(Debug, Copy, PartialEq, Eq, Clone)]
+pub struct Bleed {
+    pub size: i32,
+    pub mode: BleedMode,
+}
+
+/// Options passed to the libvips loader when opening an image, e.g. to pick a page of a PDF
+/// or rasterize vector formats at a higher resolution. Only loaders of multi-page or vector
+/// formats, such as PDF and SVG, accept them.
+#[derive(Debug, Copy, PartialEq, Clone, Default)]
+pub struct OpenOptions {
+    /// Page to load, counting from 0.
+    pub page: Option<i32>,
+    /// Resolution that vector formats are rendered at, in dots per inch.
+    pub dpi: Option<f64>,
+    /// Number of pages to load, stacked vertically, or -1 for every page.
+    pub n: Option<i32>,
+}
+
+impl OpenOptions {
+    /// Formats the options in the `[name=value,...]` suffix that libvips reads from file
+    /// names, or an empty string if no option is set.
+    fn suffix(&self) -> String {
+        let options: Vec<_> = [
+            self.page.map(|page| format!("page={page}")),
+            self.dpi.map(|dpi| format!("dpi={dpi}")),
+            self.n.map(|n| format!("n={n}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if options.is_empty() {
+            String::new()
+        } else {
+            format!("[{}]", options.join(","))
+        }
+    }
+}
+
+/// Horizontal alignment of an image within a box, e.g. which part is kept when cropping.
+#[derive(Debug, Copy, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "cli", derive(Deserialize))]
+#[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
+pub enum AlignX {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+/// Vertical alignment of an image within a box, e.g. which part is kept when cropping.
+#[derive(Debug, Copy, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "cli", derive(Deserialize))]
+#[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
+pub enum AlignY {
+    Top,
+    #[default]
+    Center,
+    Bottom,
+}
+
+/// How colors outside the gamut of an ICC profile are mapped into it.
+#[derive(Debug, Copy, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "cli", derive(Deserialize))]
+#[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
+pub enum RenderingIntent {
+    Perceptual,
+    #[default]
+    Relative,
+    Saturation,
+    Absolute,
+}
+
+/// An output ICC profile, e.g. the CMYK profile given by a print shop.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "cli", derive(Deserialize))]
+pub struct IccProfile {
+    pub profile: PathBuf,
+    #[cfg_attr(feature = "cli", serde(default))]
+    pub intent: RenderingIntent,
+}
+
+impl From<RenderingIntent> for ops::Intent {
+    fn from(intent: RenderingIntent) -> Self {
+        match intent {
+            RenderingIntent::Perceptual => Self::Perceptual,
+            RenderingIntent::Relative => Self::Relative,
+            RenderingIntent::Saturation => Self::Saturation,
+            RenderingIntent::Absolute => Self::Absolute,
+        }
+    }
+}
+
+impl AlignX {
+    fn fraction(self) -> f64 {
+        match self {
+            Self::Left => 0.0,
+            Self::Center => 0.5,
+            Self::Right => 1.0,
+        }
+    }
+}
+
+impl AlignY {
+    fn fraction(self) -> f64 {
+        match self {
+            Self::Top => 0.0,
+            Self::Center => 0.5,
+            Self::Bottom => 1.0,
+        }
+    }
+}
+
 impl ImgBackend {
+    /// Length in pixels of crop marks.
+    const CROP_MARK_LENGTH: i32 = 24;
+    /// Gap in pixels between crop marks and the image they surround.
+    const CROP_MARK_GAP: i32 = 8;
+    /// Factors applied to the luminance of an image to get each band of its sepia tone.
+    const SEPIA_TONE: [f64; 3] = [1.07, 0.74, 0.43];
+
+    /// Maximum supersampling factor used to render strokes, which is also the minimum radius
+    /// in pixels of the supersampled stroke mask.
+    const STROKE_PRECISION: f64 = 4.0;
+
+    /// Creates a backend, initializing libvips if it was not initialized yet.
     pub fn new() -> Result<Self> {
-        Ok(Self {
-            vips_app: libvips::VipsApp::default("cartomata").map_err(|e| Error::vips(e, None))?,
-        })
+        let vips_app = VIPS_APP
+            .get_or_init(|| VipsApp::default("cartomata").map_err(|e| Error::vips(e, None)));
+        match vips_app {
+            Ok(vips_app) => Ok(Self { vips_app }),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    /// Returns a backend shared by the whole process.
+    pub fn shared() -> Result<&'static Self> {
+        static SHARED: OnceLock<Result<ImgBackend>> = OnceLock::new();
+        SHARED.get_or_init(Self::new).as_ref().map_err(Clone::clone)
     }
 
     fn err(&self, e: libvips::error::Error) -> Error {
@@ -53,7 +228,12 @@ impl ImgBackend {
         Error::vips(e, extra)
     }
 
-    fn reinterpret(&self, img: &VipsImage) -> Result<VipsImage> {
+    /// Normalizes an image to 8-bit sRGB with an alpha channel, which is the format every
+    /// other operation expects. Images already in that format are returned as they are.
+    fn reinterpret(&self, img: VipsImage) -> Result<VipsImage> {
+        if Self::is_normalized(&img) {
+            return Ok(img);
+        }
         let img = ops::cast(&img, ops::BandFormat::Uchar).map_err(|e| self.err(e))?;
         let img = ops::copy_with_opts(
             &img,
@@ -74,12 +254,19 @@ impl ImgBackend {
         }
     }
 
-    pub fn create(&self, bg: &Color, width: i32, height: i32) -> Result<VipsImage> {
+    fn is_normalized(img: &VipsImage) -> bool {
+        img.get_bands() == 4
+            && matches!(img.get_format(), Ok(ops::BandFormat::Uchar))
+            && matches!(img.get_interpretation(), Ok(ops::Interpretation::Srgb))
+    }
+
+    /// Creates a blank sRGB canvas with an alpha band, filled with the background color.
+    pub fn new_canvas(&self, bg: &Color, width: i32, height: i32) -> Result<VipsImage> {
         let (r, g, b, a) = bg.scaled_rgba();
         let img = ops::black_with_opts(width, height, &ops::BlackOptions { bands: 4 })
             .map_err(|e| self.err(e))?;
         let img = VipsImage::new_from_image(&img, &[r, g, b, a]).map_err(|e| self.err(e))?;
-        self.reinterpret(&img)
+        self.reinterpret(img)
     }
 
     pub fn cairo_to_vips(&self, img: ImageSurface) -> Result<VipsImage> {
@@ -88,13 +275,20 @@ impl ImgBackend {
             .map_err(Error::cairo_to_vips)?;
         let mut img = VipsImage::new_from_buffer(&buffer, "").map_err(|e| self.err(e))?;
         img.image_wio_input().map_err(|e| self.err(e))?;
-        self.reinterpret(&img)
+        self.reinterpret(img)
     }
 
     pub fn open(&self, fp: impl AsRef<str>) -> Result<VipsImage> {
         let fp = fp.as_ref();
         let img = VipsImage::new_from_file(fp).map_err(|e| self.err(e))?;
-        self.reinterpret(&img)
+        self.reinterpret(img)
+    }
+
+    /// Opens an image passing options to its loader, e.g. to pick a page of a PDF.
+    pub fn open_with(&self, fp: impl AsRef<str>, opt: &OpenOptions) -> Result<VipsImage> {
+        let fp = format!("{}{}", fp.as_ref(), opt.suffix());
+        let img = VipsImage::new_from_file(&fp).map_err(|e| self.err(e))?;
+        self.reinterpret(img)
     }
 
     pub fn set_color(&self, img: &VipsImage, color: Color) -> Result<VipsImage> {
@@ -109,7 +303,7 @@ impl ImgBackend {
             None => current_a,
         };
         let img = ops::bandjoin(&mut [rgb, a]).map_err(|e| self.err(e))?;
-        self.reinterpret(&img)
+        self.reinterpret(img)
     }
 
     pub fn set_opacity(&self, img: &VipsImage, alpha: f64) -> Result<VipsImage> {
@@ -119,7 +313,139 @@ impl ImgBackend {
         let rgb = ops::extract_band_with_opts(img, 0, &ops::ExtractBandOptions { n: 3 })
             .map_err(|e| self.err(e))?;
         let img = ops::bandjoin(&mut [rgb, a]).map_err(|e| self.err(e))?;
-        self.reinterpret(&img)
+        self.reinterpret(img)
+    }
+
+    /// Makes the corners of an image transparent, leaving a rounded rectangle with corners of
+    /// the given radius in pixels. The edge of the mask is anti-aliased.
+    pub fn round_corners(&self, img: &VipsImage, radius: f64) -> Result<VipsImage> {
+        use std::f64::consts::{FRAC_PI_2, PI};
+
+        let (w, h) = (img.get_width(), img.get_height());
+        let mask = ImageSurface::create(cairo::Format::ARgb32, w, h).map_err(Error::cairo)?;
+        let cr = cairo::Context::new(&mask).map_err(Error::cairo)?;
+        let (w, h) = (w as f64, h as f64);
+        let r = radius.clamp(0.0, w.min(h) / 2.0);
+        cr.new_sub_path();
+        cr.arc(w - r, r, r, -FRAC_PI_2, 0.0);
+        cr.arc(w - r, h - r, r, 0.0, FRAC_PI_2);
+        cr.arc(r, h - r, r, FRAC_PI_2, PI);
+        cr.arc(r, r, r, PI, 3.0 * FRAC_PI_2);
+        cr.close_path();
+        cr.set_source_rgba(1.0, 1.0, 1.0, 1.0);
+        cr.fill().map_err(Error::cairo)?;
+        drop(cr);
+
+        let mask = self.cairo_to_vips(mask)?;
+        let mask = ops::extract_band(&mask, 3).map_err(|e| self.err(e))?;
+        let a = ops::extract_band(img, 3).map_err(|e| self.err(e))?;
+        let a = ops::multiply(&a, &mask).map_err(|e| self.err(e))?;
+        let a = ops::linear(&a, &mut [1.0 / 255.0], &mut [0.0]).map_err(|e| self.err(e))?;
+        let rgb = ops::extract_band_with_opts(img, 0, &ops::ExtractBandOptions { n: 3 })
+            .map_err(|e| self.err(e))?;
+        let img = ops::bandjoin(&mut [rgb, a]).map_err(|e| self.err(e))?;
+        self.reinterpret(img)
+    }
+
+    /// Inverts the colors of an image, keeping its alpha channel.
+    pub fn invert(&self, img: &VipsImage) -> Result<VipsImage> {
+        let rgb = ops::extract_band_with_opts(img, 0, &ops::ExtractBandOptions { n: 3 })
+            .map_err(|e| self.err(e))?;
+        let rgb = ops::invert(&rgb).map_err(|e| self.err(e))?;
+        let a = ops::extract_band(img, 3).map_err(|e| self.err(e))?;
+        let img = ops::bandjoin(&mut [rgb, a]).map_err(|e| self.err(e))?;
+        self.reinterpret(img)
+    }
+
+    /// Tints an image with a sepia tone derived from its luminance, keeping its alpha channel.
+    /// `amount` goes from 0, leaving the image as it is, to 1, fully sepia.
+    pub fn sepia(&self, img: &VipsImage, amount: f64) -> Result<VipsImage> {
+        let amount = amount.clamp(0.0, 1.0);
+        let rgb = ops::extract_band_with_opts(img, 0, &ops::ExtractBandOptions { n: 3 })
+            .map_err(|e| self.err(e))?;
+        let luma = ops::colourspace(&rgb, ops::Interpretation::BW).map_err(|e| self.err(e))?;
+        let tone = ops::linear(
+            &luma,
+            &mut Self::SEPIA_TONE.map(|c| c * amount),
+            &mut [0.0; 3],
+        )
+        .map_err(|e| self.err(e))?;
+        let rgb = ops::linear(&rgb, &mut [1.0 - amount], &mut [0.0]).map_err(|e| self.err(e))?;
+        let rgb = ops::add(&rgb, &tone).map_err(|e| self.err(e))?;
+        let a = ops::extract_band(img, 3).map_err(|e| self.err(e))?;
+        let img = ops::bandjoin(&mut [rgb, a]).map_err(|e| self.err(e))?;
+        self.reinterpret(img)
+    }
+
+    /// Rotates the hue of an image by some degrees and multiplies its saturation and value,
+    /// keeping its alpha channel. Colors are adjusted in LCh with floating point precision,
+    /// so that converting back and forth doesn't cause banding.
+    pub fn adjust_hsv(
+        &self,
+        img: &VipsImage,
+        hue_deg: f64,
+        sat_mult: f64,
+        val_mult: f64,
+    ) -> Result<VipsImage> {
+        let rgb = ops::extract_band_with_opts(img, 0, &ops::ExtractBandOptions { n: 3 })
+            .map_err(|e| self.err(e))?;
+        let lch = ops::colourspace(&rgb, ops::Interpretation::Lch).map_err(|e| self.err(e))?;
+        let lch = ops::linear(
+            &lch,
+            &mut [val_mult, sat_mult, 1.0],
+            &mut [0.0, 0.0, hue_deg.rem_euclid(360.0)],
+        )
+        .map_err(|e| self.err(e))?;
+        let rgb = ops::colourspace(&lch, ops::Interpretation::Srgb).map_err(|e| self.err(e))?;
+        let a = ops::extract_band(img, 3).map_err(|e| self.err(e))?;
+        let img = ops::bandjoin(&mut [rgb, a]).map_err(|e| self.err(e))?;
+        self.reinterpret(img)
+    }
+
+    /// Applies a gaussian blur to an image. Colors are premultiplied by alpha while blurring,
+    /// so that transparent pixels don't darken the edges.
+    pub fn blur(&self, img: &VipsImage, sigma: f64) -> Result<VipsImage> {
+        let img = ops::premultiply(img).map_err(|e| self.err(e))?;
+        let img = ops::gaussblur(&img, sigma).map_err(|e| self.err(e))?;
+        let img = ops::unpremultiply(&img).map_err(|e| self.err(e))?;
+        self.reinterpret(img)
+    }
+
+    /// Converts an image whose colors were saved already multiplied by its alpha channel back
+    /// to straight alpha, which is what compositing expects. Such images otherwise show dark
+    /// halos around their translucent edges.
+    ///
+    /// This is applied when configured rather than detected, as no format read here records
+    /// it: PNG always means straight alpha, so files exported premultiplied are mislabeled,
+    /// and telling them apart from the pixels alone would guess wrong on images that are
+    /// simply dark where translucent.
+    pub fn unpremultiply(&self, img: &VipsImage) -> Result<VipsImage> {
+        let img = ops::unpremultiply(img).map_err(|e| self.err(e))?;
+        self.reinterpret(img)
+    }
+
+    /// Blurs the part of `base` under an image placed with its top left corner at `x` and `y`,
+    /// following its alpha channel, e.g. to draw a frosted glass panel over the card.
+    pub fn backdrop_blur(
+        &self,
+        base: &VipsImage,
+        mask: &VipsImage,
+        x: i32,
+        y: i32,
+        sigma: f64,
+    ) -> Result<VipsImage> {
+        let (w, h) = (base.get_width(), base.get_height());
+        let blurred = self.blur(base, sigma)?;
+        let rgb = ops::extract_band_with_opts(&blurred, 0, &ops::ExtractBandOptions { n: 3 })
+            .map_err(|e| self.err(e))?;
+        let a = ops::extract_band(&blurred, 3).map_err(|e| self.err(e))?;
+        let mask = ops::extract_band(mask, 3).map_err(|e| self.err(e))?;
+        let mask = ops::embed(&mask, x, y, w, h).map_err(|e| self.err(e))?;
+        let a = ops::multiply(&a, &mask).map_err(|e| self.err(e))?;
+        let a = ops::linear(&a, &mut [1.0 / 255.0], &mut [0.0]).map_err(|e| self.err(e))?;
+        let frosted = ops::bandjoin(&mut [rgb, a]).map_err(|e| self.err(e))?;
+        let frosted = self.reinterpret(frosted)?;
+        ops::composite_2(base, &frosted, BlendMode::Over.into()).map_err(|e| self.err(e))
     }
 
     pub fn scale(&self, img: &VipsImage, sx: f64, sy: f64) -> Result<VipsImage> {
@@ -175,6 +501,32 @@ impl ImgBackend {
         self.scale(img, sx, sy)
     }
 
+    /// Crops an image to at most `w` by `h`, keeping the part given by the alignment, e.g. to
+    /// remove the overflow of an image scaled with [`FitMode::Cover`].
+    pub fn crop(
+        &self,
+        img: &VipsImage,
+        w: f64,
+        h: f64,
+        ax: AlignX,
+        ay: AlignY,
+    ) -> Result<VipsImage> {
+        let (iw, ih) = (img.get_width(), img.get_height());
+        let (cw, ch) = ((w.round() as i32).min(iw), (h.round() as i32).min(ih));
+        let x = ((iw - cw) as f64 * ax.fraction()).round() as i32;
+        let y = ((ih - ch) as f64 * ay.fraction()).round() as i32;
+        ops::extract_area(img, x, y, cw, ch).map_err(|e| self.err(e))
+    }
+
+    /// Centers an image over a `w` by `h` canvas filled with `bg`, e.g. to fill the space left
+    /// around an image scaled with [`FitMode::Contain`].
+    pub fn letterbox(&self, img: &VipsImage, w: f64, h: f64, bg: &Color) -> Result<VipsImage> {
+        let canvas = self.new_canvas(bg, w.round() as i32, h.round() as i32)?;
+        let (x, y) = ((0.5 * w) as i32, (0.5 * h) as i32);
+        let o = Origin::Relative(0.5);
+        self.overlay(&canvas, img, x, y, o, o, BlendMode::Over)
+    }
+
     pub fn rotate(
         &self,
         img: &VipsImage,
@@ -195,9 +547,17 @@ impl ImgBackend {
         Ok((img, dx, dy))
     }
 
+    /// Draws a stroke around the opaque parts of an image. The image grows by
+    /// [`Stroke::padding`] pixels on each side, so an origin given for the original image must
+    /// be moved with [`Origin::padded`] for the stroked one to stay in place.
     pub fn stroke(&self, img: &VipsImage, stroke: Stroke) -> Result<VipsImage> {
         let Stroke { size, color } = stroke;
-        let mask = ops::black(size * 2 + 1, size * 2 + 1).map_err(|e| self.err(e))?;
+        let pad = stroke.padding();
+        // small and fractional strokes are dilated at a higher resolution and then downscaled,
+        // so that their edges are smooth
+        let factor = (Self::STROKE_PRECISION / size).ceil().clamp(1.0, Self::STROKE_PRECISION);
+        let radius = (size * factor).round() as i32;
+        let mask = ops::black(radius * 2 + 1, radius * 2 + 1).map_err(|e| self.err(e))?;
         let mask = ops::add(
             &mask,
             &VipsImage::new_from_image1(&mask, 128.0).map_err(|e| self.err(e))?,
@@ -206,21 +566,35 @@ impl ImgBackend {
         ops::draw_circle_with_opts(
             &mask,
             &mut [255.0],
-            size,
-            size,
-            size,
+            radius,
+            radius,
+            radius,
             &ops::DrawCircleOptions { fill: true },
         )
         .map_err(|e| self.err(e))?;
 
         let (w, h) = (img.get_width(), img.get_height());
-        let img =
-            ops::embed(&img, size, size, w + 2 * size, h + 2 * size).map_err(|e| self.err(e))?;
+        let img = ops::embed(&img, pad, pad, w + 2 * pad, h + 2 * pad).map_err(|e| self.err(e))?;
 
         let alpha = ops::extract_band(&img, 3).map_err(|e| self.err(e))?;
+        let alpha = if factor > 1.0 {
+            ops::resize_with_opts(
+                &alpha,
+                factor,
+                &ops::ResizeOptions { kernel: ops::Kernel::Nearest, ..Default::default() },
+            )
+            .map_err(|e| self.err(e))?
+        } else {
+            alpha
+        };
         // TODO: "binarize" alpha before blur for better results
         let alpha =
             ops::morph(&alpha, &mask, ops::OperationMorphology::Dilate).map_err(|e| self.err(e))?;
+        let alpha = if factor > 1.0 {
+            self.scale(&alpha, 1.0 / factor, 1.0 / factor)?
+        } else {
+            alpha
+        };
         let alpha = ops::gaussblur_with_opts(
             &alpha,
             0.5,
@@ -234,7 +608,7 @@ impl ImgBackend {
         let (r, g, b) = color.scaled_rgb();
         let stroke = VipsImage::new_from_image(&alpha, &[r, g, b]).map_err(|e| self.err(e))?;
         let stroke = ops::bandjoin(&mut [stroke, alpha]).map_err(|e| self.err(e))?;
-        let stroke = self.reinterpret(&stroke)?;
+        let stroke = self.reinterpret(stroke)?;
         let img = ops::composite_2(&stroke, &img, ops::BlendMode::Over).map_err(|e| self.err(e))?;
         Ok(img)
     }
@@ -257,35 +631,72 @@ impl ImgBackend {
         ops::composite_2(&base, &src, mode.into()).map_err(|e| self.err(e))
     }
 
+    /// Composites several images over `base` in a single operation, in order. Each image is
+    /// placed with its top left corner at the given position, and blended with the given mode.
+    pub fn composite_all(
+        &self,
+        base: VipsImage,
+        layers: Vec<(VipsImage, i32, i32, BlendMode)>,
+    ) -> Result<VipsImage> {
+        if layers.is_empty() {
+            return Ok(base);
+        }
+        let n = layers.len();
+        let mut images = Vec::with_capacity(n + 1);
+        let mut modes = Vec::with_capacity(n);
+        let (mut xs, mut ys) = (Vec::with_capacity(n), Vec::with_capacity(n));
+        images.push(base);
+        for (img, x, y, mode) in layers.into_iter() {
+            let mode: ops::BlendMode = mode.into();
+            images.push(img);
+            modes.push(mode as i32);
+            xs.push(x);
+            ys.push(y);
+        }
+        ops::composite_with_opts(
+            &mut images,
+            &mut modes,
+            &ops::CompositeOptions { x: xs, y: ys, ..Default::default() },
+        )
+        .map_err(|e| self.err(e))
+    }
+
+    /// Lays out and draws text, returning its image, its layout and the characters no font
+    /// could draw.
     pub fn print(
         &self,
         markup: Markup,
-        im: &ImageMap,
-        fm: &FontMap,
+        res: TextResources,
         font: &str,
         size: f64,
         color: Color,
         params: &[LayoutAttr],
-    ) -> Result<(VipsImage, pango::Layout)> {
+    ) -> Result<(VipsImage, pango::Layout, Vec<char>)> {
+        let fm = res.font_map;
         if fm.get(font).is_none() {
             return Err(Error::font_missing(font));
         }
-        let ctx = pangocairo::FontMap::new().create_context();
+        let ctx = Self::text_context();
         let layout = pango::Layout::new(&ctx);
-        params.iter().for_each(|p| p.configure(&ctx, &layout));
-
         let mut opt = cairo::FontOptions::new().map_err(Error::cairo)?;
         opt.set_antialias(cairo::Antialias::Good);
+        params.iter().for_each(|p| p.configure(&ctx, &layout, &mut opt));
         pangocairo::functions::context_set_font_options(&ctx, Some(&opt));
 
         let gravity = Gravity::from(ctx.gravity());
         let (attrs, text) =
             markup.parsed(font.to_string(), pango::SCALE * size as i32, color, gravity);
-        let (attr_list, images) = ITagAttr::vec_to_pango(attrs, self, im, fm, &ctx)?;
+        let (attr_list, images) = ITagAttr::vec_to_pango(attrs, self, res, &ctx)?;
+        // inserted before markup attributes, so that spans take precedence
+        for attr in params.iter().rev().filter_map(LayoutAttr::base_attr) {
+            attr_list.insert_before(attr);
+        }
         layout.set_font_description(fm.get_desc_pt(font, size).as_ref());
         layout.set_attributes(Some(&attr_list));
         layout.set_text(&text);
 
+        let missing = Self::missing_glyphs(&layout);
+
         let (_, log_rect) = layout.extents();
         let mut base = {
             let base = cairo::ImageSurface::create(
@@ -304,9 +715,7 @@ impl ImgBackend {
         if let Some(atl) = attr_list.filter(|att| att.type_() == pango::AttrType::Shape) {
             for (att, img) in atl.attributes().iter().zip(images) {
                 if let Some(img) = img {
-                    let i = att.start_index();
-                    let rect = layout.index_to_pos(i as i32);
-                    let (x, y) = (rect.x() / pango::SCALE, rect.y() / pango::SCALE);
+                    let (x, y) = shape_position(&layout, att.start_index());
                     base = self.overlay(
                         &base,
                         &img,
@@ -319,7 +728,144 @@ impl ImgBackend {
                 }
             }
         }
-        Ok((base, layout))
+        Ok((base, layout, missing))
+    }
+
+    /// Returns the text context of the current thread, with the settings that layout
+    /// parameters change set back to their defaults, so that no text leaks them into the next.
+    /// Font options are replaced by every call to `print`. The context is created again once
+    /// fonts are added, so that they can be found.
+    fn text_context() -> pango::Context {
+        TEXT_CONTEXT.with(|cached| {
+            let added = FONT_FILES_ADDED.load(Ordering::Acquire);
+            let mut cached = cached.borrow_mut();
+            let ctx = match &*cached {
+                Some((n, ctx)) if *n == added => ctx.clone(),
+                _ => cached
+                    .insert((added, pangocairo::FontMap::new().create_context()))
+                    .1
+                    .clone(),
+            };
+            pangocairo::functions::context_set_resolution(&ctx, -1.0);
+            ctx.set_base_dir(pango::Direction::WeakLtr);
+            ctx.set_base_gravity(pango::Gravity::South);
+            ctx.set_gravity_hint(pango::GravityHint::Natural);
+            ctx.set_language(Some(&pango::Language::default()));
+            ctx
+        })
+    }
+
+    /// Characters of a layout that no font could draw, which pango renders as boxes.
+    fn missing_glyphs(layout: &pango::Layout) -> Vec<char> {
+        let mut missing = Vec::new();
+        let mut iter = layout.iter();
+        loop {
+            if let Some(run) = iter.run() {
+                let glyphs = run.glyph_string();
+                for info in glyphs.glyph_info() {
+                    let glyph = info.glyph();
+                    if glyph & pango::GLYPH_UNKNOWN_FLAG == 0 {
+                        continue;
+                    }
+                    match char::from_u32(glyph & !pango::GLYPH_UNKNOWN_FLAG) {
+                        Some(c) if !c.is_control() && !missing.contains(&c) => missing.push(c),
+                        _ => {}
+                    }
+                }
+            }
+            if !iter.next_run() {
+                return missing;
+            }
+        }
+    }
+
+    /// Extends an image by the bleed size on every side, filling the new pixels from the ones
+    /// at the edges, rather than leaving them transparent.
+    pub fn bleed(&self, img: &VipsImage, bleed: Bleed) -> Result<VipsImage> {
+        let Bleed { size, mode } = bleed;
+        let (w, h) = (img.get_width(), img.get_height());
+        let extend = match mode {
+            BleedMode::Mirror => ops::Extend::Mirror,
+            BleedMode::Copy => ops::Extend::Copy,
+        };
+        let opts = ops::EmbedOptions { extend, ..Default::default() };
+        ops::embed_with_opts(img, size, size, w + 2 * size, h + 2 * size, &opts)
+            .map_err(|e| self.err(e))
+    }
+
+    /// Surrounds an image with a white margin holding crop marks, which are aligned with the
+    /// trim box, inset by `trim` pixels from the edges of the image, e.g. by its bleed.
+    pub fn crop_marks(&self, img: &VipsImage, trim: i32) -> Result<VipsImage> {
+        let (w, h) = (img.get_width(), img.get_height());
+        let (len, margin) = (
+            Self::CROP_MARK_LENGTH,
+            Self::CROP_MARK_LENGTH + Self::CROP_MARK_GAP,
+        );
+        let (tw, th) = (w + 2 * margin, h + 2 * margin);
+        let canvas = self.new_canvas(&Color::WHITE, tw, th)?;
+        let o = Origin::default();
+        let base = self.overlay(&canvas, img, margin, margin, o, o, BlendMode::Over)?;
+
+        let mut marks = Vec::with_capacity(8);
+        for x in [margin + trim, margin + w - trim] {
+            for y in [0, th - len] {
+                let mark = self.new_canvas(&Color::BLACK, 1, len)?;
+                marks.push((mark, x, y, BlendMode::Over));
+            }
+        }
+        for y in [margin + trim, margin + h - trim] {
+            for x in [0, tw - len] {
+                let mark = self.new_canvas(&Color::BLACK, len, 1)?;
+                marks.push((mark, x, y, BlendMode::Over));
+            }
+        }
+        self.composite_all(base, marks)
+    }
+
+    /// Converts an image to the color space of an ICC profile, such as CMYK for print. Alpha
+    /// is flattened over white first, as the result has no alpha channel.
+    pub fn icc_transform(&self, img: &VipsImage, icc: &IccProfile) -> Result<VipsImage> {
+        let flat;
+        let img = if img.get_bands() > 3 {
+            let opts = ops::FlattenOptions {
+                background: vec![255.0, 255.0, 255.0],
+                ..Default::default()
+            };
+            flat = ops::flatten_with_opts(img, &opts).map_err(|e| self.err(e))?;
+            &flat
+        } else {
+            img
+        };
+        let opts = ops::IccTransformOptions {
+            intent: icc.intent.into(),
+            input_profile: String::from("srgb"),
+            ..Default::default()
+        };
+        let profile = icc.profile.to_string_lossy();
+        ops::icc_transform_with_opts(img, &profile, &opts).map_err(|e| self.err(e))
+    }
+
+    /// Sets the resolution stored with an image, in dots per inch, so that printing software
+    /// reproduces it at the intended physical size.
+    pub fn set_dpi(&self, img: &VipsImage, dpi: f64) -> Result<VipsImage> {
+        // libvips keeps resolution in pixels per millimetre
+        let res = dpi / 25.4;
+        let opts = ops::CopyOptions {
+            width: img.get_width(),
+            height: img.get_height(),
+            bands: img.get_bands(),
+            format: img.get_format().map_err(|e| self.err(e))?,
+            interpretation: img.get_interpretation().map_err(|e| self.err(e))?,
+            xres: res,
+            yres: res,
+            ..Default::default()
+        };
+        ops::copy_with_opts(img, &opts).map_err(|e| self.err(e))
+    }
+
+    /// Encodes an image in the format given by a file suffix, such as `.png`.
+    pub fn encode(&self, img: &VipsImage, suffix: &str) -> Result<Vec<u8>> {
+        img.image_write_to_buffer(suffix).map_err(|e| self.err(e))
     }
 
     pub fn write(&self, img: &VipsImage, path: impl AsRef<Path>) -> Result<()> {
@@ -327,3 +873,197 @@ impl ImgBackend {
         img.image_write_to_file(&path).map_err(|e| self.err(e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend() -> &'static ImgBackend {
+        ImgBackend::shared().unwrap()
+    }
+
+    fn image_map() -> ImageMap {
+        ImageMap {
+            assets_folder: PathBuf::from("assets"),
+            artwork_folder: PathBuf::from("artwork"),
+            extensions: vec![String::from("png")],
+            placeholder: None,
+            card_size: (200, 200),
+            background: Color::WHITE,
+        }
+    }
+
+    /// A font map with any sans-serif font installed, for tests that only compare text drawn
+    /// with the same font.
+    fn font_map() -> FontMap {
+        let mut fm = FontMap::new().unwrap();
+        fm.load_font_from_name(String::from("sans"), "sans-serif", None).unwrap();
+        fm
+    }
+
+    fn print(text: &str, size: f64, params: &[LayoutAttr]) -> (VipsImage, pango::Layout) {
+        let (im, fm) = (image_map(), font_map());
+        let markup = Markup::plain(text);
+        let res = TextResources { img_map: &im, font_map: &fm, premultiplied_alpha: false };
+        let (img, layout, _) = backend()
+            .print(markup, res, "sans", size, Color::BLACK, params)
+            .unwrap();
+        (img, layout)
+    }
+
+    fn pixel(img: &VipsImage, x: i32, y: i32) -> Vec<f64> {
+        ops::getpoint(img, x, y).unwrap()
+    }
+
+    /// Sum of the alpha channel over the whole image, in pixels that are fully opaque.
+    fn coverage(img: &VipsImage) -> f64 {
+        let alpha = ops::extract_band(img, 3).unwrap();
+        let area = (img.get_width() * img.get_height()) as f64;
+        ops::avg(&alpha).unwrap() * area / 255.0
+    }
+
+    #[test]
+    fn fractional_stroke_is_between_whole_ones() {
+        let ib = backend();
+        let (glyph, _) = print("O", 48.0, &[]);
+        let stroked = |size: f64| {
+            let stroke = Stroke { size, color: Color::BLACK };
+            ib.stroke(&glyph, stroke).unwrap()
+        };
+        let (thin, mid, thick) = (stroked(1.0), stroked(1.5), stroked(2.0));
+        assert_eq!(thin.get_width(), glyph.get_width() + 2);
+        assert_eq!(mid.get_width(), glyph.get_width() + 4);
+        let (thin, mid, thick) = (coverage(&thin), coverage(&mid), coverage(&thick));
+        assert!(coverage(&glyph) < thin, "{thin}");
+        assert!(thin < mid && mid < thick, "{thin} {mid} {thick}");
+    }
+
+    #[test]
+    fn layout_params_dont_leak_into_the_next_print() {
+        use crate::text::attr::Direction;
+
+        let (plain, _) = print("Hello", 24.0, &[]);
+        let (hi_dpi, _) = print("Hello", 24.0, &[LayoutAttr::Dpi(192.0)]);
+        assert!(hi_dpi.get_width() > plain.get_width());
+        let (after, layout) = print("Hello", 24.0, &[]);
+        assert_eq!(after.get_width(), plain.get_width());
+        assert_eq!(after.get_height(), plain.get_height());
+        assert_eq!(layout.context().base_dir(), pango::Direction::WeakLtr);
+
+        let (_, layout) = print("Hello", 24.0, &[LayoutAttr::Direction(Direction::Rtl)]);
+        assert_eq!(layout.context().base_dir(), pango::Direction::Rtl);
+        let (_, layout) = print("Hello", 24.0, &[]);
+        assert_eq!(layout.context().base_dir(), pango::Direction::WeakLtr);
+    }
+
+    #[test]
+    fn dest_out_knocks_out_only_under_the_layer() {
+        let ib = backend();
+        let red = Color::from_rgb(1.0, 0.0, 0.0);
+        let base = ib.new_canvas(&red, 100, 100).unwrap();
+        let eraser = ib.new_canvas(&Color::from_rgb(0.0, 0.0, 1.0), 20, 20).unwrap();
+        let o = Origin::default();
+        let overlaid = ib.overlay(&base, &eraser, 10, 10, o, o, BlendMode::DestOut).unwrap();
+        let batched = ib
+            .composite_all(base.clone(), vec![(eraser.clone(), 10, 10, BlendMode::DestOut)])
+            .unwrap();
+        for img in [overlaid, batched] {
+            assert_eq!(pixel(&img, 15, 15)[3], 0.0);
+            assert_eq!(pixel(&img, 29, 29)[3], 0.0);
+            assert_eq!(pixel(&img, 30, 30), vec![255.0, 0.0, 0.0, 255.0]);
+            assert_eq!(pixel(&img, 5, 5), vec![255.0, 0.0, 0.0, 255.0]);
+        }
+
+        let kept = ib.overlay(&base, &eraser, 10, 10, o, o, BlendMode::DestIn).unwrap();
+        assert_eq!(pixel(&kept, 15, 15), vec![255.0, 0.0, 0.0, 255.0]);
+        assert_eq!(pixel(&kept, 50, 50)[3], 0.0);
+    }
+
+    #[test]
+    fn new_canvas_is_filled_with_background() {
+        let ib = backend();
+        let opaque = ib.new_canvas(&Color::from_rgb(0.2, 0.4, 0.6), 8, 4).unwrap();
+        assert_eq!((opaque.get_width(), opaque.get_height()), (8, 4));
+        assert!(ImgBackend::is_normalized(&opaque));
+        assert_eq!(pixel(&opaque, 0, 0), vec![51.0, 102.0, 153.0, 255.0]);
+        assert_eq!(pixel(&opaque, 7, 3), vec![51.0, 102.0, 153.0, 255.0]);
+
+        let translucent = ib.new_canvas(&Color::from_rgba(1.0, 1.0, 1.0, 0.2), 8, 4).unwrap();
+        assert_eq!(pixel(&translucent, 4, 2), vec![255.0, 255.0, 255.0, 51.0]);
+    }
+
+    #[test]
+    fn invert_keeps_alpha() {
+        let ib = backend();
+        let white = ib.new_canvas(&Color::from_rgba(1.0, 1.0, 1.0, 0.4), 4, 4).unwrap();
+        let black = ib.invert(&white).unwrap();
+        assert!(ImgBackend::is_normalized(&black));
+        assert_eq!(pixel(&black, 1, 1), vec![0.0, 0.0, 0.0, 102.0]);
+        let white = ib.invert(&black).unwrap();
+        assert_eq!(pixel(&white, 1, 1), vec![255.0, 255.0, 255.0, 102.0]);
+    }
+
+    #[test]
+    fn sepia_tints_and_keeps_alpha() {
+        let ib = backend();
+        let gray = ib.new_canvas(&Color::from_rgba(0.5, 0.5, 0.5, 0.4), 4, 4).unwrap();
+        let untouched = ib.sepia(&gray, 0.0).unwrap();
+        assert_eq!(pixel(&untouched, 1, 1), pixel(&gray, 1, 1));
+
+        let tinted = ib.sepia(&gray, 1.0).unwrap();
+        assert!(ImgBackend::is_normalized(&tinted));
+        let [r, g, b, a] = pixel(&tinted, 1, 1)[..] else { panic!("expected 4 bands") };
+        assert!(r > g && g > b, "{r} {g} {b}");
+        assert_eq!(a, 102.0);
+    }
+
+    #[test]
+    fn unpremultiply_restores_straight_alpha() {
+        let ib = backend();
+        // half transparent pure red, stored with its color multiplied by its alpha
+        let color = Color::from_rgba(64.0 / 255.0, 0.0, 0.0, 128.0 / 255.0);
+        let path = std::env::temp_dir()
+            .join(format!("cartomata-premultiplied-{}.png", std::process::id()));
+        ib.write(&ib.new_canvas(&color, 4, 4).unwrap(), &path).unwrap();
+        let img = ib.open(path.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(pixel(&img, 1, 1), vec![64.0, 0.0, 0.0, 128.0]);
+
+        let img = ib.unpremultiply(&img).unwrap();
+        assert!(ImgBackend::is_normalized(&img));
+        let [r, g, b, a] = pixel(&img, 1, 1)[..] else { panic!("expected 4 bands") };
+        assert!((r - 127.5).abs() <= 1.0, "{r}");
+        assert_eq!((g, b, a), (0.0, 0.0, 128.0));
+    }
+
+    #[test]
+    fn hue_turn_rotates_colors() {
+        let ib = backend();
+        let red = ib.new_canvas(&Color::from_rgba(1.0, 0.0, 0.0, 0.6), 4, 4).unwrap();
+        let turned = ib.adjust_hsv(&red, 120.0, 1.0, 1.0).unwrap();
+        assert!(ImgBackend::is_normalized(&turned));
+        let [r, g, b, a] = pixel(&turned, 1, 1)[..] else { panic!("expected 4 bands") };
+        assert!(g > r && g > b, "{r} {g} {b}");
+        assert_eq!(a, 153.0);
+
+        // a full turn is no turn at all
+        let color = Color::from_rgba(0.8, 0.3, 0.1, 0.6);
+        let img = ib.new_canvas(&color, 4, 4).unwrap();
+        let turned = ib.adjust_hsv(&img, 360.0, 1.0, 1.0).unwrap();
+        for (before, after) in pixel(&img, 1, 1).into_iter().zip(pixel(&turned, 1, 1)) {
+            assert!((before - after).abs() <= 1.0, "{before} {after}");
+        }
+    }
+
+    #[test]
+    fn no_saturation_is_grey() {
+        let ib = backend();
+        let color = Color::from_rgba(0.8, 0.3, 0.1, 0.6);
+        let img = ib.new_canvas(&color, 4, 4).unwrap();
+        let grey = ib.adjust_hsv(&img, 0.0, 0.0, 1.0).unwrap();
+        let [r, g, b, a] = pixel(&grey, 1, 1)[..] else { panic!("expected 4 bands") };
+        assert!((r - g).abs() <= 1.0 && (g - b).abs() <= 1.0, "{r} {g} {b}");
+        assert!(r > 0.0 && r < 255.0, "{r}");
+        assert_eq!(a, 153.0);
+    }
+}
@@ -8,12 +8,12 @@ macro_rules! into_vips {
     (
         $(#[$outer:meta])*
         $vis:vis enum $Enum:ident {
-            $( $Variant:ident ),*
+            $( $(#[$inner:meta])* $Variant:ident ),*
         }
     ) => {
         $(#[$outer])*
         $vis enum $Enum {
-            $( $Variant ),*
+            $( $(#[$inner])* $Variant ),*
         }
 
         impl Into<ops::$Enum> for $Enum {
@@ -27,6 +27,11 @@ macro_rules! into_vips {
 }
 
 into_vips! {
+    /// How a layer is blended with the image below it, which is the destination.
+    ///
+    /// A layer is composited over the whole card, and is transparent outside its bounds.
+    /// So modes that keep the destination only where the layer is opaque, such as `DestIn`,
+    /// also clear the card outside the layer, while `DestOut` leaves it untouched.
     #[derive(Copy, Clone, Debug, PartialEq, Eq)]
     #[cfg_attr(feature = "cli", derive(Deserialize, Serialize))]
     #[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
@@ -39,7 +44,11 @@ into_vips! {
         Atop,
         Dest,
         DestOver,
+        /// Keeps the destination only where the layer is opaque, using the layer as a mask.
         DestIn,
+        /// Erases the destination where the layer is opaque, e.g. to punch a hole in the card
+        /// with the shape of an asset. The layer colors are ignored.
+        #[cfg_attr(feature = "cli", serde(alias = "erase"))]
         DestOut,
         DestAtop,
         Xor,
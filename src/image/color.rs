@@ -3,9 +3,20 @@
 use regex::Regex;
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
 use std::str::FromStr;
 
+/// Colors by name, which colors being deserialized can refer to as `@name`, while the palette
+/// is in scope with [`Color::with_palette`].
+pub type Palette = HashMap<String, Color>;
+
+thread_local! {
+    static PALETTE: RefCell<Option<Rc<Palette>>> = const { RefCell::new(None) };
+}
+
 #[derive(Debug, Copy, Clone, Default)]
 pub struct Color {
     pub r: f64,
@@ -72,6 +83,48 @@ impl Color {
         let c = (x * 255.0) as u16;
         c | c << 8
     }
+
+    /// Runs a function with a palette in scope, so that colors deserialized by it, such as
+    /// the colors of layers created by a decoder, can also be written as `@name` to use a color
+    /// of the palette. Parsing with `from_str` never resolves names, as it has no palette.
+    ///
+    /// # Example
+    /// ```
+    /// use cartomata::image::{Color, Palette};
+    /// use serde::de::{value::Error, IntoDeserializer};
+    /// use serde::Deserialize;
+    /// use std::rc::Rc;
+    ///
+    /// let palette = Palette::from([("primary".to_string(), Color::from_rgb(1.0, 0.5, 0.0))]);
+    /// let parse = |s: &str| Color::deserialize(s.into_deserializer()).map_err(|e: Error| e);
+    /// let color = Color::with_palette(Rc::new(palette), || parse("@primary")).unwrap();
+    /// assert_eq!(color.to_string(), "#FF7F00");
+    /// assert!(parse("@primary").is_err());
+    /// ```
+    pub fn with_palette<R>(palette: Rc<Palette>, f: impl FnOnce() -> R) -> R {
+        /// Restores the palette in scope before, even if `f` panics.
+        struct Restore(Option<Rc<Palette>>);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                PALETTE.with(|p| *p.borrow_mut() = self.0.take());
+            }
+        }
+
+        let _restore = Restore(PALETTE.with(|p| p.replace(Some(palette))));
+        f()
+    }
+
+    /// Looks up a color by name in the palette in scope.
+    fn from_palette(name: &str) -> Result<Self, String> {
+        PALETTE.with(|p| match &*p.borrow() {
+            Some(palette) => palette
+                .get(name)
+                .copied()
+                .ok_or_else(|| format!("color `@{name}` not found in palette")),
+            None => Err(format!("no palette to pick color `@{name}` from")),
+        })
+    }
 }
 
 impl FromStr for Color {
@@ -117,14 +170,17 @@ impl<'de> Visitor<'de> for ColorVisitor {
     type Value = Color;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a string in the form #RRGGBBAA or #RRGGBB")
+        formatter.write_str("a string in the form #RRGGBBAA, #RRGGBB or @name")
     }
 
     fn visit_str<E>(self, v: &str) -> std::result::Result<Self::Value, E>
     where
         E: de::Error,
     {
-        v.parse::<Color>().map_err(|e| E::custom(e))
+        match v.strip_prefix('@') {
+            Some(name) => Color::from_palette(name).map_err(E::custom),
+            None => v.parse::<Color>().map_err(|e| E::custom(e)),
+        }
     }
 }
 
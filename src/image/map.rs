@@ -20,6 +20,21 @@ impl ImageMap {
         fp
     }
 
+    /// Path of an asset that may be named without its extension, e.g. `mana` for
+    /// `mana.png`, trying each of the template extensions in order when there is no file at
+    /// the path as given.
+    pub fn find_asset(&self, path: impl AsRef<Path>) -> PathBuf {
+        let fp = self.asset_path(path);
+        if fp.exists() || fp.extension().is_some() {
+            return fp;
+        }
+        self.extensions
+            .iter()
+            .map(|ext| fp.with_extension(ext))
+            .find(|path| path.exists())
+            .unwrap_or(fp)
+    }
+
     pub fn artwork_path(&self, key: impl AsRef<str>) -> Result<PathBuf> {
         let key = key.as_ref();
         let mut path = self.artwork_folder.clone();
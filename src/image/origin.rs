@@ -36,6 +36,23 @@ impl Origin {
             Self::Relative(a) => a * x,
         }
     }
+
+    /// Moves an origin given for an image of length `size` to the same point once the image
+    /// is padded by `pad` pixels on each side, e.g. by a stroke, so that the padded image is
+    /// placed where the original one would have been.
+    ///
+    /// ```
+    /// use cartomata::image::Origin;
+    ///
+    /// // the baseline of a text 20 pixels tall, and its right edge, once stroked by 3 pixels
+    /// let baseline = Origin::Absolute(16.0).padded(20.0, 3);
+    /// let right = Origin::Relative(1.0).padded(100.0, 3);
+    /// assert_eq!(baseline.apply(26.0), 19.0);
+    /// assert_eq!(right.apply(106.0), 103.0);
+    /// ```
+    pub fn padded(&self, size: f64, pad: i32) -> Self {
+        Self::Absolute(self.apply(size) + pad as f64)
+    }
 }
 
 impl TextOrigin {
@@ -6,6 +6,14 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
 pub struct Stroke {
-    pub size: i32,
+    /// Stroke width in pixels, which can be fractional.
+    pub size: f64,
     pub color: Color,
 }
+
+impl Stroke {
+    /// Number of whole pixels the stroke adds around each side of an image.
+    pub fn padding(&self) -> i32 {
+        self.size.ceil() as i32
+    }
+}
@@ -0,0 +1,113 @@
+//! SVG output, where each layer is embedded as a separate image.
+
+use crate::error::Result;
+use crate::image::{BlendMode, Color, ImgBackend};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use libvips::VipsImage;
+use std::fmt;
+
+/// An SVG document of a card, where layers are embedded as PNG data URIs positioned over
+/// the card, so that they can still be moved or hidden separately in a vector editor.
+#[derive(Debug, Clone)]
+pub struct SvgDocument {
+    width: i32,
+    height: i32,
+    opacity: Option<f64>,
+    body: String,
+}
+
+impl SvgDocument {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self { width, height, opacity: None, body: String::new() }
+    }
+
+    /// Width and height of the document, in pixels.
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    /// Sets the opacity of the whole document.
+    pub fn set_opacity(&mut self, opacity: Option<f64>) {
+        self.opacity = opacity;
+    }
+
+    /// Whether images blended with a mode can be embedded. SVG only supports the separable
+    /// and non-separable blend modes of CSS, which don't include most Porter-Duff operators.
+    pub fn supports(mode: BlendMode) -> bool {
+        css_blend_mode(mode).is_some()
+    }
+
+    /// Fills the whole card with a color.
+    pub fn push_fill(&mut self, color: &Color) {
+        let (r, g, b, a) = color.scaled_rgba();
+        self.body.push_str(&format!(
+            "<rect width=\"100%\" height=\"100%\" fill=\"rgb({r:.0},{g:.0},{b:.0})\" \
+             fill-opacity=\"{:.3}\"/>",
+            a / 255.0
+        ));
+    }
+
+    /// Embeds an image with its top left corner at a position over the card. Images with
+    /// modes that are not [`supported`](Self::supports) are blended as `Over`.
+    pub fn push_image(
+        &mut self,
+        ib: &ImgBackend,
+        img: &VipsImage,
+        x: i32,
+        y: i32,
+        mode: BlendMode,
+    ) -> Result<()> {
+        let png = ib.encode(img, ".png")?;
+        let (w, h) = (img.get_width(), img.get_height());
+        self.body.push_str(&format!(
+            "<image x=\"{x}\" y=\"{y}\" width=\"{w}\" height=\"{h}\" href=\"data:image/png;base64,{}\"",
+            BASE64_STANDARD.encode(png)
+        ));
+        match css_blend_mode(mode) {
+            Some(css) if mode != BlendMode::Over => {
+                self.body.push_str(&format!(" style=\"mix-blend-mode:{css}\"/>"))
+            }
+            _ => self.body.push_str("/>"),
+        }
+        Ok(())
+    }
+
+    /// Removes every element, e.g. to replace them by a single flattened image.
+    pub fn clear(&mut self) {
+        self.body.clear();
+    }
+}
+
+impl fmt::Display for SvgDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (w, h) = (self.width, self.height);
+        write!(
+            f,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+             viewBox=\"0 0 {w} {h}\""
+        )?;
+        if let Some(opacity) = self.opacity {
+            write!(f, " opacity=\"{opacity:.3}\"")?;
+        }
+        write!(f, ">{}</svg>", self.body)
+    }
+}
+
+fn css_blend_mode(mode: BlendMode) -> Option<&'static str> {
+    match mode {
+        BlendMode::Over => Some("normal"),
+        BlendMode::Multiply => Some("multiply"),
+        BlendMode::Screen => Some("screen"),
+        BlendMode::Overlay => Some("overlay"),
+        BlendMode::Darken => Some("darken"),
+        BlendMode::Lighten => Some("lighten"),
+        BlendMode::ColourDodge => Some("color-dodge"),
+        BlendMode::ColourBurn => Some("color-burn"),
+        BlendMode::HardLight => Some("hard-light"),
+        BlendMode::SoftLight => Some("soft-light"),
+        BlendMode::Difference => Some("difference"),
+        BlendMode::Exclusion => Some("exclusion"),
+        _ => None,
+    }
+}
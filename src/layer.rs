@@ -3,44 +3,567 @@
 mod artwork;
 mod asset;
 mod label;
+mod repeat;
 mod text;
+mod watermark;
 
 pub use artwork::ArtworkLayer;
 pub use asset::AssetLayer;
 pub use label::LabelLayer;
+pub use repeat::RepeatLayer;
 pub use text::TextLayer;
+pub use watermark::Watermark;
 
-use crate::error::Result;
-use crate::image::{ImageMap, ImgBackend};
+use crate::error::{Error, Result};
+use crate::image::{AlignX, AlignY, BlendMode, Bleed, ImageMap, ImgBackend, TextResources};
+#[cfg(feature = "svg")]
+use crate::image::SvgDocument;
+use crate::text::attr::Overflow;
 use crate::text::FontMap;
 
 use core::fmt::Debug;
 use libvips::VipsImage;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
+/// Resources shared by every layer while rendering a card. They are only ever borrowed
+/// immutably, so a single context can be used by several layers and the same backend
+/// can be shared across worker threads.
 #[derive(Clone)]
 pub struct RenderContext<'a> {
     pub backend: &'a ImgBackend,
     pub font_map: &'a FontMap,
     pub img_map: &'a ImageMap,
+    pub options: &'a RenderOptions,
+    /// Problems found while rendering that don't stop the card from being rendered, such as
+    /// text drawn with a font that lacks some of its glyphs.
+    pub warnings: Warnings,
+    /// Time spent rendering each layer of a card.
+    pub layer_times: LayerTimes,
+    /// Files written for a card, reported to visitors once it is done.
+    pub outputs: Outputs,
 }
-pub trait Layer: Debug {
+
+impl<'a> RenderContext<'a> {
+    /// Resources that text layers are printed with.
+    pub fn text_resources(&self) -> TextResources<'a> {
+        TextResources {
+            img_map: self.img_map,
+            font_map: self.font_map,
+            premultiplied_alpha: self.options.premultiplied_alpha,
+        }
+    }
+}
+
+/// Settings applied to every card rendered, as opposed to the resources they are drawn from.
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Opacity applied to the whole card after its layers are composited, e.g. to export
+    /// faded previews. Unlike the opacity of each layer, it also fades the background.
+    pub opacity: Option<f64>,
+    /// Text stamped over every card, after its layers.
+    pub watermark: Option<Watermark>,
+    /// Whether assets and artwork are stored with premultiplied alpha, to be converted to
+    /// straight alpha when loaded. Layers can override it.
+    pub premultiplied_alpha: bool,
+    /// Radius in pixels of the rounded corners cut from raster output. Corners are rounded
+    /// within the card itself, which is the trim box when there is bleed, before the bleed
+    /// and crop marks are added around it.
+    pub corner_radius: Option<f64>,
+    /// Margin added around raster output for printing, extending the card edges.
+    pub bleed: Option<Bleed>,
+    /// Whether to surround raster output with crop marks at the corners of the card.
+    pub crop_marks: bool,
+    /// Number of threads used to render the layers of each card, when rendering them
+    /// concurrently. Layers are composited in order regardless.
+    pub layer_threads: Option<NonZero<usize>>,
+    /// Folder where the card is written after each layer, within a folder named after the
+    /// card, to debug templates.
+    pub dump_layers: Option<PathBuf>,
+}
+
+/// Items collected while rendering a card, shared by every copy of a context.
+#[derive(Debug, Clone, Default)]
+pub struct Collector<T>(Arc<Mutex<Vec<T>>>);
+
+impl<T> Collector<T> {
+    pub fn push(&self, item: T) {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).push(item);
+    }
+
+    /// Removes and returns every item collected so far.
+    pub fn take(&self) -> Vec<T> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Warnings collected while rendering a card.
+pub type Warnings = Collector<String>;
+
+/// Time spent rendering each layer of a card, along with its position and kind, in the order
+/// they were rendered. Compositing consecutive layers together is not included.
+pub type LayerTimes = Collector<(usize, &'static str, Duration)>;
+
+/// Files written for a card, along with the width and height of the image written to each.
+pub type Outputs = Collector<(PathBuf, (i32, i32))>;
+
+pub trait Layer: Debug + Send + Sync {
     fn render(&self, img: VipsImage, ctx: &RenderContext) -> Result<VipsImage>;
+
+    /// Renders the layer as an image to be placed with its top left corner at a position
+    /// over the card, so that consecutive layers can be composited in a single operation.
+    ///
+    /// Layers that depend on the image below them return `None`, and are drawn with `render`.
+    fn overlay(&self, _ctx: &RenderContext) -> Result<Option<(VipsImage, i32, i32, BlendMode)>> {
+        Ok(None)
+    }
+
+    /// Name of the kind of layer, e.g. to label it in debug output.
+    fn kind(&self) -> &'static str {
+        "layer"
+    }
+
+    /// Whether the layer is drawn. Disabled layers are skipped by [`LayerStack`], as if they
+    /// weren't in it.
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    /// Checks that the resources the layer refers to, such as images and fonts, exist,
+    /// returning every problem found instead of failing at the first one.
+    fn validate(&self, _ctx: &RenderContext) -> Vec<Error> {
+        Vec::new()
+    }
+}
+
+/// Returns an error if a font, or the default font when none is given, is not loaded.
+pub(crate) fn missing_font(font: Option<&str>, ctx: &RenderContext) -> Option<Error> {
+    let font = font.unwrap_or(ctx.font_map.default_key());
+    ctx.font_map.get(font).is_none().then(|| Error::font_missing(font))
+}
+
+/// Warns about characters of a text that have no glyph in its font, nor in any fallback font,
+/// as they are drawn as boxes.
+pub(crate) fn warn_missing_glyphs(missing: &[char], font: &str, ctx: &RenderContext) {
+    if missing.is_empty() {
+        return;
+    }
+    let chars = missing
+        .iter()
+        .map(|c| format!("'{c}' (U+{:04X})", *c as u32))
+        .collect::<Vec<_>>()
+        .join(", ");
+    ctx.warnings.push(format!("font {font} has no glyphs for {chars}"));
 }
 
+/// Makes an image of text fit within a maximum height according to an overflow policy,
+/// returning it along with the factor by which it was scaled.
+pub(crate) fn fit_height(
+    ib: &ImgBackend,
+    img: VipsImage,
+    max_h: Option<i32>,
+    overflow: Overflow,
+) -> Result<(VipsImage, f64)> {
+    let (iw, ih) = (img.get_width(), img.get_height());
+    match max_h {
+        Some(h) if ih > h => match overflow {
+            Overflow::Shrink => {
+                let s = h as f64 / ih as f64;
+                Ok((ib.scale(&img, s, s)?, s))
+            }
+            // ellipsized text already fits, unless it couldn't be ellipsized
+            Overflow::Clip | Overflow::Ellipsize => {
+                let img = ib.crop(&img, iw as f64, h as f64, AlignX::Left, AlignY::Top)?;
+                Ok((img, 1.0))
+            }
+        },
+        _ => Ok((img, 1.0)),
+    }
+}
+
+/// An image to be placed with its top left corner at a position over the card.
+type Overlay = (VipsImage, i32, i32, BlendMode);
+
+/// An overlay rendered by another thread.
+struct SendOverlay(Overlay);
+
+/// Returns the pool of threads that render layers, one for each number of threads asked
+/// for. Pools are kept for the whole process, so that each of their threads keeps its own
+/// text context from card to card instead of loading the fonts again.
+fn layer_pool(threads: usize) -> Result<Arc<ThreadPool>> {
+    static POOLS: Mutex<Vec<(usize, Arc<ThreadPool>)>> = Mutex::new(Vec::new());
+    let mut pools = POOLS.lock().map_err(|e| Error::mutex_lock("layer pools", e))?;
+    if let Some((_, pool)) = pools.iter().find(|(n, _)| *n == threads) {
+        return Ok(pool.clone());
+    }
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("layer-{i}"))
+        .build()
+        .map_err(Error::thread_pool)?;
+    let pool = Arc::new(pool);
+    pools.push((threads, pool.clone()));
+    Ok(pool)
+}
+
+// SAFETY: libvips images are reference counted and immutable once built, and libvips
+// documents them as safe to share between threads
+unsafe impl Send for SendOverlay {}
+
 #[derive(Debug)]
-pub struct LayerStack<'a>(pub Vec<Box<dyn Layer + 'a>>);
+pub struct LayerStack<'a> {
+    pub layers: Vec<Box<dyn Layer + 'a>>,
+    /// Size of the canvas of this card, replacing the card size of the template, e.g. for
+    /// oversized or double-faced cards. Layers see it as the card size while rendering, and
+    /// outputs are resized from it.
+    pub canvas: Option<(i32, i32)>,
+}
 
 impl<'a> LayerStack<'a> {
+    pub fn new(layers: Vec<Box<dyn Layer + 'a>>) -> Self {
+        Self { layers, canvas: None }
+    }
+
+    /// Sets the size of the canvas of this card, if any, instead of the template card size.
+    pub fn with_canvas(mut self, canvas: Option<(i32, i32)>) -> Self {
+        self.canvas = canvas;
+        self
+    }
+
     pub fn render(self, ctx: &RenderContext) -> Result<VipsImage> {
+        self.render_with(ctx, None)
+    }
+
+    /// Runs a function over the layers with the context given, or with a copy of it whose
+    /// card size is the canvas size of this card.
+    fn sized<R>(
+        self,
+        ctx: &RenderContext,
+        f: impl FnOnce(Vec<Box<dyn Layer + 'a>>, &RenderContext) -> R,
+    ) -> R {
+        match self.canvas {
+            Some(card_size) => {
+                let img_map = ImageMap { card_size, ..ctx.img_map.clone() };
+                f(self.layers, &RenderContext { img_map: &img_map, ..ctx.clone() })
+            }
+            None => f(self.layers, ctx),
+        }
+    }
+
+    /// Like `render`, but when given a folder, also writes the card as it is after each layer
+    /// to `{n}-{kind}.png` in that folder, to help find which layer draws something wrong.
+    pub fn render_with(self, ctx: &RenderContext, dump: Option<&Path>) -> Result<VipsImage> {
+        self.sized(ctx, |layers, ctx| Self::render_layers(layers, ctx, dump))
+    }
+
+    fn render_layers(
+        mut layers: Vec<Box<dyn Layer + 'a>>,
+        ctx: &RenderContext,
+        dump: Option<&Path>,
+    ) -> Result<VipsImage> {
         let bg = ctx.img_map.background;
         let (w, h) = ctx.img_map.card_size;
+        let ib = ctx.backend;
 
-        let mut img = ctx.backend.create(&bg, w, h)?;
+        let mut img = ib.new_canvas(&bg, w, h)?;
 
-        let LayerStack(layers) = self;
-        for layer in layers.into_iter() {
-            img = layer.render(img, ctx)?;
+        layers.retain(|layer| layer.enabled());
+        if let Some(watermark) = &ctx.options.watermark {
+            layers.push(Box::new(watermark.clone()));
+        }
+        if let Some(dir) = dump {
+            fs::create_dir_all(dir).map_err(Error::io_error)?;
+        }
+        let width = layers.len().to_string().len();
+        let overlays = Self::overlays(&layers, ctx)?;
+        let mut batch = Vec::new();
+        for (i, (layer, (overlay, time))) in layers.iter().zip(overlays).enumerate() {
+            let time = match overlay {
+                Some(overlay) => {
+                    img = Self::batch_overlay(ib, img, &mut batch, overlay)?;
+                    time
+                }
+                None => {
+                    img = ib.composite_all(img, std::mem::take(&mut batch))?;
+                    let start = Instant::now();
+                    img = layer.render(img, ctx)?;
+                    time + start.elapsed()
+                }
+            };
+            ctx.layer_times.push((i + 1, layer.kind(), time));
+            if let Some(dir) = dump {
+                img = ib.composite_all(img, std::mem::take(&mut batch))?;
+                ib.write(&img, dir.join(format!("{:0width$}-{}.png", i + 1, layer.kind())))?;
+            }
         }
+        let img = ib.composite_all(img, batch)?;
+        let img = match ctx.options.opacity {
+            Some(opacity) => ctx.backend.set_opacity(&img, opacity)?,
+            None => img,
+        };
+        // corners are rounded within the trim box, so the bleed extends the rounded card and
+        // leaves what is cut around the corners transparent
+        let img = match ctx.options.corner_radius {
+            Some(radius) => ctx.backend.round_corners(&img, radius)?,
+            None => img,
+        };
+        // print margins go last, as they are outside of the card itself
+        let (img, trim) = match ctx.options.bleed {
+            Some(bleed) => (ctx.backend.bleed(&img, bleed)?, bleed.size),
+            None => (img, 0),
+        };
+        if ctx.options.crop_marks {
+            ctx.backend.crop_marks(&img, trim)
+        } else {
+            Ok(img)
+        }
+    }
+
+    /// Adds an overlay to the batch of consecutive overlays to be composited together, first
+    /// compositing the batch over the image when the overlay is blended in another mode, so
+    /// that each batch has a single blend mode.
+    fn batch_overlay(
+        ib: &ImgBackend,
+        img: VipsImage,
+        batch: &mut Vec<Overlay>,
+        overlay: Overlay,
+    ) -> Result<VipsImage> {
+        let img = match batch.last() {
+            Some((.., mode)) if *mode != overlay.3 => ib.composite_all(img, std::mem::take(batch))?,
+            _ => img,
+        };
+        batch.push(overlay);
         Ok(img)
     }
+
+    /// Checks every enabled layer, including the watermark, returning the problems found along
+    /// with the position and kind of the layer they were found in.
+    pub fn validate(&self, ctx: &RenderContext) -> Vec<(usize, &'static str, Error)> {
+        let layers = &self.layers;
+        let watermark = ctx.options.watermark.as_ref().map(|w| w as &dyn Layer);
+        layers
+            .iter()
+            .map(|layer| layer.as_ref() as &dyn Layer)
+            .filter(|layer| layer.enabled())
+            .chain(watermark)
+            .enumerate()
+            .flat_map(|(i, layer)| {
+                let kind = layer.kind();
+                layer.validate(ctx).into_iter().map(move |e| (i + 1, kind, e))
+            })
+            .collect()
+    }
+
+    /// Renders the overlay of every layer, spread over a pool of `layer_threads` threads when
+    /// set, which is kept from card to card. Each overlay is drawn on an image of its own, so
+    /// they are independent of each other, and are then composited in order by `render`.
+    /// Each is returned with the time it took.
+    ///
+    /// A layer panicking on another thread fails with its position and kind, as the panic
+    /// would otherwise only name the thread.
+    fn overlays(
+        layers: &[Box<dyn Layer + 'a>],
+        ctx: &RenderContext,
+    ) -> Result<Vec<(Option<Overlay>, Duration)>> {
+        let timed_overlay = |layer: &(dyn Layer + 'a)| {
+            let start = Instant::now();
+            layer.overlay(ctx).map(|overlay| (overlay, start.elapsed()))
+        };
+        let threads = match ctx.options.layer_threads {
+            Some(n) if layers.len() > 1 => n.get(),
+            _ => return layers.iter().map(|layer| timed_overlay(layer.as_ref())).collect(),
+        };
+        layer_pool(threads)?.install(|| {
+            layers
+                .par_iter()
+                .enumerate()
+                .map(|(i, layer)| {
+                    let layer = layer.as_ref();
+                    let (overlay, time) =
+                        panic::catch_unwind(AssertUnwindSafe(|| timed_overlay(layer)))
+                            .map_err(|e| Error::layer_panic(i + 1, layer.kind(), e))??;
+                    Ok((overlay.map(SendOverlay), time))
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .map(|overlays| {
+            overlays.into_iter().map(|(o, t)| (o.map(|SendOverlay(o)| o), t)).collect()
+        })
+    }
+
+    /// Renders the layers as an SVG document, embedding each overlay as a separate image.
+    ///
+    /// Layers that depend on the image below them, or whose blend mode SVG can't express,
+    /// are drawn over a raster of the layers below, which then replaces them in the document.
+    #[cfg(feature = "svg")]
+    pub fn render_svg(self, ctx: &RenderContext) -> Result<SvgDocument> {
+        self.sized(ctx, Self::render_layers_svg)
+    }
+
+    #[cfg(feature = "svg")]
+    fn render_layers_svg(
+        layers: Vec<Box<dyn Layer + 'a>>,
+        ctx: &RenderContext,
+    ) -> Result<SvgDocument> {
+        let bg = ctx.img_map.background;
+        let (w, h) = ctx.img_map.card_size;
+        let ib = ctx.backend;
+
+        let mut doc = SvgDocument::new(w, h);
+        doc.set_opacity(ctx.options.opacity);
+        doc.push_fill(&bg);
+        let mut img = ib.new_canvas(&bg, w, h)?;
+
+        let mut batch = Vec::new();
+        for layer in layers.into_iter().filter(|layer| layer.enabled()) {
+            match layer.overlay(ctx)? {
+                Some((src, x, y, mode)) if SvgDocument::supports(mode) => {
+                    doc.push_image(ib, &src, x, y, mode)?;
+                    img = Self::batch_overlay(ib, img, &mut batch, (src, x, y, mode))?;
+                }
+                overlay => {
+                    img = ib.composite_all(img, std::mem::take(&mut batch))?;
+                    img = match overlay {
+                        Some(overlay) => ib.composite_all(img, vec![overlay])?,
+                        None => layer.render(img, ctx)?,
+                    };
+                    doc.clear();
+                    doc.push_image(ib, &img, 0, 0, BlendMode::Over)?;
+                }
+            }
+        }
+        if let Some(watermark) = &ctx.options.watermark {
+            if let Some((src, x, y, mode)) = watermark.overlay(ctx)? {
+                doc.push_image(ib, &src, x, y, mode)?;
+            }
+        }
+        Ok(doc)
+    }
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+    use super::*;
+    use crate::image::Color;
+
+    /// Renders a layer alone over a white card, with any sans-serif font installed.
+    fn render(layer: impl Layer + 'static) -> Vec<u8> {
+        render_all(vec![Box::new(layer)], &RenderOptions::default())
+    }
+
+    /// Renders layers over a white card, with any sans-serif font installed.
+    fn render_all(layers: Vec<Box<dyn Layer>>, options: &RenderOptions) -> Vec<u8> {
+        with_context(options, |ctx| {
+            let img = LayerStack::new(layers).render(ctx).unwrap();
+            img.image_write_to_memory()
+        })
+    }
+
+    /// Runs a function with the context of a white card, with any sans-serif font installed.
+    fn with_context<R>(options: &RenderOptions, f: impl FnOnce(&RenderContext) -> R) -> R {
+        let mut font_map = FontMap::new().unwrap();
+        font_map.load_font_from_name(String::from("sans"), "sans-serif", None).unwrap();
+        let img_map = ImageMap {
+            assets_folder: PathBuf::from("assets"),
+            artwork_folder: PathBuf::from("artwork"),
+            extensions: vec![String::from("png")],
+            placeholder: None,
+            card_size: (300, 120),
+            background: Color::WHITE,
+        };
+        let ctx = RenderContext {
+            backend: ImgBackend::shared().unwrap(),
+            font_map: &font_map,
+            img_map: &img_map,
+            options,
+            warnings: Default::default(),
+            layer_times: Default::default(),
+            outputs: Default::default(),
+        };
+        f(&ctx)
+    }
+
+    /// Asserts that every pixel fully covered by the black text drawn without a stroke is
+    /// still black with a red stroke, which fails if the stroked text moved by a pixel.
+    fn assert_aligned(plain: &[u8], stroked: &[u8]) {
+        let black = [0, 0, 0, 255];
+        let covered: Vec<_> = plain
+            .chunks(4)
+            .enumerate()
+            .filter(|(_, px)| *px == black)
+            .map(|(i, _)| i)
+            .collect();
+        assert!(!covered.is_empty());
+        for i in covered {
+            assert_eq!(stroked[i * 4..i * 4 + 4], black, "pixel {i}");
+        }
+    }
+
+    const TEXT: &str = r##"
+        text = "Hello"
+        font = "sans"
+        x = 20
+        y = 80
+        size = 48.0
+        oy = "baseline"
+    "##;
+
+    const STROKE: &str = r##"stroke = { size = 3.0, color = "#ff0000" }"##;
+
+    #[test]
+    fn stroked_text_keeps_its_baseline() {
+        let layer = |toml: &str| toml::from_str::<TextLayer>(toml).unwrap();
+        let plain = render(layer(TEXT));
+        let stroked = render(layer(&format!("{TEXT}\n{STROKE}")));
+        assert_aligned(&plain, &stroked);
+    }
+
+    #[test]
+    fn stroked_label_keeps_its_baseline() {
+        let layer = |toml: &str| toml::from_str::<LabelLayer>(toml).unwrap();
+        let plain = render(layer(TEXT));
+        let stroked = render(layer(&format!("{TEXT}\n{STROKE}")));
+        assert_aligned(&plain, &stroked);
+    }
+
+    #[test]
+    fn threaded_layers_render_the_same() {
+        let layers = || {
+            (0..6)
+                .map(|i| {
+                    let y = 16 + i * 16;
+                    let toml = format!(
+                        "text = 'Line {i}'\nfont = 'sans'\nsize = 14.0\nx = 20\ny = {y}"
+                    );
+                    Box::new(toml::from_str::<TextLayer>(&toml).unwrap()) as Box<dyn Layer>
+                })
+                .collect::<Vec<_>>()
+        };
+        let sequential = render_all(layers(), &RenderOptions::default());
+        let options = RenderOptions { layer_threads: NonZero::new(3), ..Default::default() };
+        // rendered twice, so that the threads kept from the first card are used again
+        for _ in 0..2 {
+            assert!(render_all(layers(), &options) == sequential);
+        }
+    }
+
+    #[test]
+    fn wide_labels_are_condensed_horizontally() {
+        let size = |w: &str| {
+            let layer = toml::from_str::<LabelLayer>(&format!("{TEXT}\n{w}")).unwrap();
+            with_context(&RenderOptions::default(), |ctx| {
+                let (img, ..) = layer.overlay(ctx).unwrap().unwrap();
+                (img.get_width(), img.get_height())
+            })
+        };
+        let (w, h) = size("");
+        assert!(w > 60, "{w}");
+        assert_eq!(size("w = 60"), (60, h));
+    }
 }
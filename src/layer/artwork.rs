@@ -1,7 +1,7 @@
 //! Represents an image layer loaded from artwork folder.
 
-use crate::error::Result;
-use crate::image::{BlendMode, FitMode, Origin, Stroke};
+use crate::error::{Error, Result};
+use crate::image::{AlignX, AlignY, BlendMode, Color, FitMode, Origin, Stroke};
 use crate::layer::{Layer, RenderContext};
 
 #[cfg(feature = "cli")]
@@ -27,38 +27,123 @@ pub struct ArtworkLayer {
     pub oy: Origin,
     #[cfg_attr(feature = "cli", serde(default))]
     pub fit: FitMode,
+    /// Color to fill the space left around the artwork when it is contained, so that the
+    /// result is exactly `w` by `h`. Without it, that space is left empty.
+    pub letterbox: Option<Color>,
+    /// Which part of the artwork is kept when it covers the box, horizontally and vertically.
+    /// If either is given, the artwork is cropped to `w` by `h`, centered on the other axis.
+    pub crop_x: Option<AlignX>,
+    pub crop_y: Option<AlignY>,
+    /// Recolors the image with a single color, keeping its alpha channel, e.g. to tint an icon
+    /// with a color picked from a palette by a card field.
+    pub color: Option<Color>,
+    /// Inverts the colors of the image.
+    #[cfg_attr(feature = "cli", serde(default))]
+    pub invert: bool,
+    /// Tints the image with a sepia tone, from 0 to 1.
+    pub sepia: Option<f64>,
+    /// Applies a gaussian blur to the image, with this standard deviation in pixels.
+    pub blur: Option<f64>,
+    /// Blurs what is beneath the image, following its alpha channel, with this standard
+    /// deviation in pixels, e.g. for frosted glass panels.
+    pub backdrop_blur: Option<f64>,
     #[cfg_attr(feature = "cli", serde(default))]
     pub blend: BlendMode,
     pub stroke: Option<Stroke>,
+    /// Whether the image is stored with premultiplied alpha, overriding the template setting.
+    pub premultiplied: Option<bool>,
+    /// Whether the layer is drawn, so that it can stay defined but be turned off, e.g. by a
+    /// condition computed by the decoder.
+    #[cfg_attr(feature = "cli", serde(default = "default_enabled"))]
+    pub enabled: bool,
+}
+
+const fn default_enabled() -> bool {
+    true
 }
 
 fn default_origin() -> Origin {
     Origin::Relative(0.5)
 }
 
-impl Layer for ArtworkLayer {
-    fn render(&self, img: VipsImage, ctx: &RenderContext) -> Result<VipsImage> {
+impl ArtworkLayer {
+    fn place(&self, ctx: &RenderContext) -> Result<(VipsImage, i32, i32, BlendMode)> {
         let img_map = ctx.img_map;
         let ib = ctx.backend;
         let path = img_map.artwork_path(&self.id)?;
         let artwork = ib.open(path.to_string_lossy())?;
+        let artwork = if self.premultiplied.unwrap_or(ctx.options.premultiplied_alpha) {
+            ib.unpremultiply(&artwork)?
+        } else {
+            artwork
+        };
         let artwork = ib.scale_to_fit(&artwork, self.w, self.h, self.fit)?;
+        let artwork = match self.color {
+            Some(color) => ib.set_color(&artwork, color)?,
+            None => artwork,
+        };
+        let artwork = if self.invert { ib.invert(&artwork)? } else { artwork };
+        let artwork = match self.sepia {
+            Some(amount) => ib.sepia(&artwork, amount)?,
+            None => artwork,
+        };
+        let artwork = match self.blur {
+            Some(sigma) => ib.blur(&artwork, sigma)?,
+            None => artwork,
+        };
+        let artwork = match (self.fit, &self.letterbox) {
+            (FitMode::Contain, Some(bg)) => ib.letterbox(&artwork, self.w, self.h, bg)?,
+            _ => artwork,
+        };
+        let artwork = match (self.fit, self.crop_x, self.crop_y) {
+            (FitMode::Cover, None, None) => artwork,
+            (FitMode::Cover, ax, ay) => {
+                let (ax, ay) = (ax.unwrap_or_default(), ay.unwrap_or_default());
+                ib.crop(&artwork, self.w, self.h, ax, ay)?
+            }
+            _ => artwork,
+        };
         let artwork = if let Some(stroke) = self.stroke {
             ib.stroke(&artwork, stroke)?
         } else {
             artwork
         };
         let (artwork, dx, dy) = ib.rotate(&artwork, self.r, self.ox, self.oy)?;
-        let ox = Origin::Absolute(-self.ox.apply(self.w));
-        let oy = Origin::Absolute(-self.oy.apply(self.h));
-        ib.overlay(
-            &img,
-            &artwork,
-            self.x - dx as i32,
-            self.y - dy as i32,
-            ox,
-            oy,
-            self.blend,
-        )
+        let ox = -self.ox.apply(self.w);
+        let oy = -self.oy.apply(self.h);
+        let x = self.x - dx as i32 - ox as i32;
+        let y = self.y - dy as i32 - oy as i32;
+        Ok((artwork, x, y, self.blend))
+    }
+}
+
+impl Layer for ArtworkLayer {
+    fn render(&self, img: VipsImage, ctx: &RenderContext) -> Result<VipsImage> {
+        let (artwork, x, y, blend) = self.place(ctx)?;
+        let img = match self.backdrop_blur {
+            Some(sigma) => ctx.backend.backdrop_blur(&img, &artwork, x, y, sigma)?,
+            None => img,
+        };
+        let o = Origin::default();
+        ctx.backend.overlay(&img, &artwork, x, y, o, o, blend)
+    }
+
+    fn overlay(&self, ctx: &RenderContext) -> Result<Option<(VipsImage, i32, i32, BlendMode)>> {
+        if self.backdrop_blur.is_some() {
+            return Ok(None);
+        }
+        self.place(ctx).map(Some)
+    }
+
+    fn kind(&self) -> &'static str {
+        "artwork"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn validate(&self, ctx: &RenderContext) -> Vec<Error> {
+        ctx.img_map.artwork_path(&self.id).err().into_iter().collect()
     }
 }
@@ -1,7 +1,7 @@
 //! Represents an image layer loaded from the template assets
 
-use crate::error::Result;
-use crate::image::{BlendMode, FitMode, Origin, Stroke};
+use crate::error::{Error, Result};
+use crate::image::{BlendMode, Color, FitMode, OpenOptions, Origin, Stroke};
 use crate::layer::{Layer, RenderContext};
 
 #[cfg(feature = "cli")]
@@ -27,26 +27,127 @@ pub struct AssetLayer {
     pub oy: Origin,
     #[cfg_attr(feature = "cli", serde(default))]
     pub fit: FitMode,
+    /// Recolors the image with a single color, keeping its alpha channel, e.g. to tint an icon
+    /// with a color picked from a palette by a card field.
+    pub color: Option<Color>,
+    /// Inverts the colors of the image.
+    #[cfg_attr(feature = "cli", serde(default))]
+    pub invert: bool,
+    /// Tints the image with a sepia tone, from 0 to 1.
+    pub sepia: Option<f64>,
+    /// Applies a gaussian blur to the image, with this standard deviation in pixels.
+    pub blur: Option<f64>,
+    /// Blurs what is beneath the image, following its alpha channel, with this standard
+    /// deviation in pixels, e.g. for frosted glass panels.
+    pub backdrop_blur: Option<f64>,
+    /// Rotates the hue of the image, in degrees, e.g. to recolor a single frame.
+    #[cfg_attr(feature = "cli", serde(default))]
+    pub hue: f64,
+    /// Multiplies the saturation of the image.
+    #[cfg_attr(feature = "cli", serde(default = "default_saturation"))]
+    pub saturation: f64,
     #[cfg_attr(feature = "cli", serde(default))]
     pub blend: BlendMode,
     pub stroke: Option<Stroke>,
+    /// Whether the image is stored with premultiplied alpha, overriding the template setting.
+    pub premultiplied: Option<bool>,
+    /// Page of a multi-page asset to use, such as a PDF, counting from 0.
+    pub page: Option<i32>,
+    /// Resolution that vector assets, such as PDF and SVG, are rendered at, in dots per inch.
+    pub dpi: Option<f64>,
+    /// Whether the layer is drawn, so that it can stay defined but be turned off, e.g. by a
+    /// condition computed by the decoder.
+    #[cfg_attr(feature = "cli", serde(default = "default_enabled"))]
+    pub enabled: bool,
 }
 
-impl Layer for AssetLayer {
-    fn render(&self, img: VipsImage, ctx: &RenderContext) -> Result<VipsImage> {
+const fn default_enabled() -> bool {
+    true
+}
+
+fn default_saturation() -> f64 {
+    1.0
+}
+
+impl AssetLayer {
+    fn place(&self, ctx: &RenderContext) -> Result<(VipsImage, i32, i32, BlendMode)> {
         let ib = ctx.backend;
         let img_map = ctx.img_map;
 
         let path = img_map.asset_path(&self.path);
-        let asset = ib.open(&path.to_string_lossy())?;
+        let asset = match (self.page, self.dpi) {
+            (None, None) => ib.open(path.to_string_lossy())?,
+            (page, dpi) => {
+                let opt = OpenOptions { page, dpi, n: None };
+                ib.open_with(path.to_string_lossy(), &opt)?
+            }
+        };
+        let asset = if self.premultiplied.unwrap_or(ctx.options.premultiplied_alpha) {
+            ib.unpremultiply(&asset)?
+        } else {
+            asset
+        };
         let asset = ib.scale_to(&asset, self.w, self.h)?;
+        let asset = match self.color {
+            Some(color) => ib.set_color(&asset, color)?,
+            None => asset,
+        };
+        let asset = if self.invert { ib.invert(&asset)? } else { asset };
+        let asset = match self.sepia {
+            Some(amount) => ib.sepia(&asset, amount)?,
+            None => asset,
+        };
+        let asset = match self.blur {
+            Some(sigma) => ib.blur(&asset, sigma)?,
+            None => asset,
+        };
+        let asset = if self.hue != 0.0 || self.saturation != 1.0 {
+            ib.adjust_hsv(&asset, self.hue, self.saturation, 1.0)?
+        } else {
+            asset
+        };
         let asset = if let Some(stroke) = self.stroke {
             ib.stroke(&asset, stroke)?
         } else {
             asset
         };
         let (asset, ox, oy) = ib.rotate(&asset, self.r, self.ox, self.oy)?;
-        let (ox, oy) = (Origin::Absolute(ox), Origin::Absolute(oy));
-        ib.overlay(&img, &asset, self.x, self.y, ox, oy, self.blend)
+        Ok((asset, self.x - ox as i32, self.y - oy as i32, self.blend))
+    }
+}
+
+impl Layer for AssetLayer {
+    fn render(&self, img: VipsImage, ctx: &RenderContext) -> Result<VipsImage> {
+        let (asset, x, y, blend) = self.place(ctx)?;
+        let img = match self.backdrop_blur {
+            Some(sigma) => ctx.backend.backdrop_blur(&img, &asset, x, y, sigma)?,
+            None => img,
+        };
+        let o = Origin::default();
+        ctx.backend.overlay(&img, &asset, x, y, o, o, blend)
+    }
+
+    fn overlay(&self, ctx: &RenderContext) -> Result<Option<(VipsImage, i32, i32, BlendMode)>> {
+        if self.backdrop_blur.is_some() {
+            return Ok(None);
+        }
+        self.place(ctx).map(Some)
+    }
+
+    fn kind(&self) -> &'static str {
+        "asset"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn validate(&self, ctx: &RenderContext) -> Vec<Error> {
+        let path = ctx.img_map.asset_path(&self.path);
+        if path.exists() {
+            Vec::new()
+        } else {
+            vec![Error::no_asset(path)]
+        }
     }
 }
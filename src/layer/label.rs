@@ -1,10 +1,13 @@
 //! Represents a layer to render arbitrary text, including single line labels
 //! or multiline text areas.
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::image::{BlendMode, Color, ImgBackend, Origin, Stroke, TextOrigin};
-use crate::layer::{Layer, RenderContext};
-use crate::text::attr::{Direction, Gravity, GravityHint, LayoutAttr};
+use crate::layer::{fit_height, missing_font, warn_missing_glyphs, Layer, RenderContext};
+use crate::text::attr::{
+    Antialias, Direction, Gravity, GravityHint, HintMetrics, HintStyle, LayoutAttr, Overflow,
+    SubpixelOrder,
+};
 use crate::text::Markup;
 
 #[cfg(feature = "cli")]
@@ -17,6 +20,9 @@ use serde::Deserialize;
 #[derive(Debug, Clone, Deserialize)]
 #[cfg_attr(feature = "cli", derive(LuaLayer))]
 pub struct LabelLayer {
+    /// Text in markup, so `<` and `>` from card data must be escaped with [`escape`].
+    ///
+    /// [`escape`]: crate::text::escape
     pub text: String,
     pub x: i32,
     pub y: i32,
@@ -24,7 +30,13 @@ pub struct LabelLayer {
     pub font: Option<String>,
     #[serde(default = "default_color")]
     pub color: Color,
+    /// Maximum width of the label, which is condensed horizontally to fit.
     pub w: Option<i32>,
+    /// Maximum height of the label, past which it overflows.
+    pub max_h: Option<i32>,
+    /// How a label taller than `max_h` is made to fit, shrinking it by default. Labels are
+    /// not wrapped, so ellipsizing clips them instead.
+    pub overflow: Option<Overflow>,
     #[serde(default)]
     pub r: f64,
     #[serde(default)]
@@ -40,6 +52,18 @@ pub struct LabelLayer {
     pub gravity: Option<Gravity>,
     pub gravity_hint: Option<GravityHint>,
     pub language: Option<String>,
+    pub antialias: Option<Antialias>,
+    pub hint_style: Option<HintStyle>,
+    pub hint_metrics: Option<HintMetrics>,
+    pub subpixel_order: Option<SubpixelOrder>,
+    /// Whether the layer is drawn, so that it can stay defined but be turned off, e.g. by a
+    /// condition computed by the decoder.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+const fn default_enabled() -> bool {
+    true
 }
 
 const fn default_color() -> Color {
@@ -63,6 +87,10 @@ impl LabelLayer {
         self.language
             .as_ref()
             .map(|x| params.push(LayoutAttr::Language(x)));
+        params.extend(self.antialias.map(LayoutAttr::Antialias));
+        params.extend(self.hint_style.map(LayoutAttr::HintStyle));
+        params.extend(self.hint_metrics.map(LayoutAttr::HintMetrics));
+        params.extend(self.subpixel_order.map(LayoutAttr::SubpixelOrder));
         params
     }
 
@@ -71,7 +99,7 @@ impl LabelLayer {
             let iw = img.get_width();
             if iw > w {
                 let s = w as f64 / iw as f64;
-                ib.scale(&img, 1.0, s)
+                ib.scale(&img, s, 1.0)
             } else {
                 Ok(img)
             }
@@ -83,25 +111,55 @@ impl LabelLayer {
 
 impl Layer for LabelLayer {
     fn render(&self, img: VipsImage, ctx: &RenderContext) -> Result<VipsImage> {
-        let img_map = ctx.img_map;
+        let (text_img, x, y, blend) = self.place(ctx)?;
+        let o = Origin::default();
+        ctx.backend.overlay(&img, &text_img, x, y, o, o, blend)
+    }
+
+    fn overlay(&self, ctx: &RenderContext) -> Result<Option<(VipsImage, i32, i32, BlendMode)>> {
+        self.place(ctx).map(Some)
+    }
+
+    fn kind(&self) -> &'static str {
+        "label"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn validate(&self, ctx: &RenderContext) -> Vec<Error> {
+        missing_font(self.font.as_deref(), ctx).into_iter().collect()
+    }
+}
+
+impl LabelLayer {
+    fn place(&self, ctx: &RenderContext) -> Result<(VipsImage, i32, i32, BlendMode)> {
         let font_map = ctx.font_map;
         let ib = ctx.backend;
 
         let markup = Markup::from_string(&self.text)?;
-        let font = self.font.as_ref().map(|x| x.as_str()).unwrap_or("default");
+        let font = self.font.as_deref().unwrap_or(font_map.default_key());
         let params = self.layout_params();
-        let (text_img, layout) = ib.print(
-            markup, &img_map, &font_map, font, self.size, self.color, &params,
-        )?;
+        let res = ctx.text_resources();
+        let (text_img, layout, missing) =
+            ib.print(markup, res, font, self.size, self.color, &params)?;
+        warn_missing_glyphs(&missing, font, ctx);
         let text_img = self.resize(&ib, text_img)?;
-        let (text_img, dh) = if let Some(stroke) = self.stroke {
-            (ib.stroke(&text_img, stroke)?, stroke.size)
+        let overflow = self.overflow.unwrap_or_default();
+        let (text_img, s) = fit_height(ib, text_img, self.max_h, overflow)?;
+        let (w, h) = (text_img.get_width() as f64, text_img.get_height() as f64);
+        let baseline = (layout.baseline() as f64 * s) as i32;
+        let (text_img, pad) = if let Some(stroke) = self.stroke {
+            (ib.stroke(&text_img, stroke)?, stroke.padding())
         } else {
             (text_img, 0)
         };
-        let h = layout.baseline() + dh;
-        let (text_img, ox, oy) = ib.rotate(&text_img, self.r, self.ox, self.oy.into_origin(h))?;
-        let (ox, oy) = (Origin::Absolute(ox), Origin::Absolute(oy));
-        ib.overlay(&img, &text_img, self.x, self.y, ox, oy, self.blend)
+        // origins are taken over the text without its stroke, so that stroked text is placed
+        // at the same baseline and edges as unstroked text
+        let ox = self.ox.padded(w, pad);
+        let oy = self.oy.into_origin(baseline).padded(h, pad);
+        let (text_img, ox, oy) = ib.rotate(&text_img, self.r, ox, oy)?;
+        Ok((text_img, self.x - ox as i32, self.y - oy as i32, self.blend))
     }
 }
@@ -0,0 +1,45 @@
+//! Represents a layer drawn several times with incremental offsets, e.g. one icon per level.
+
+use crate::error::{Error, Result};
+use crate::layer::{Layer, RenderContext};
+
+use libvips::VipsImage;
+
+#[derive(Debug)]
+pub struct RepeatLayer {
+    pub layer: Box<dyn Layer>,
+    pub count: usize,
+    pub dx: i32,
+    pub dy: i32,
+    /// Whether the copies are drawn, which they aren't either if the inner layer is disabled.
+    pub enabled: bool,
+}
+
+impl Layer for RepeatLayer {
+    /// Draws the inner layer `count` times, the i-th copy being offset by `i * (dx, dy)`.
+    /// Only layers that can be rendered as an overlay can be repeated.
+    fn render(&self, img: VipsImage, ctx: &RenderContext) -> Result<VipsImage> {
+        let mut copies = Vec::with_capacity(self.count);
+        for i in 0..self.count as i32 {
+            // each copy is rendered on its own, as images can't be shared between overlays
+            let (copy, x, y, blend) = self
+                .layer
+                .overlay(ctx)?
+                .ok_or_else(Error::layer_not_repeatable)?;
+            copies.push((copy, x + i * self.dx, y + i * self.dy, blend));
+        }
+        ctx.backend.composite_all(img, copies)
+    }
+
+    fn kind(&self) -> &'static str {
+        "repeat"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.layer.enabled()
+    }
+
+    fn validate(&self, ctx: &RenderContext) -> Vec<Error> {
+        self.layer.validate(ctx)
+    }
+}
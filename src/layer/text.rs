@@ -1,10 +1,13 @@
 //! Represents a layer to render arbitrary text, including single line labels
 //! or multiline text areas.
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::image::{BlendMode, Color, Origin, Stroke, TextOrigin};
-use crate::layer::{Layer, RenderContext};
-use crate::text::attr::{Alignment, Direction, Gravity, GravityHint, LayoutAttr, WrapMode};
+use crate::layer::{fit_height, missing_font, warn_missing_glyphs, Layer, RenderContext};
+use crate::text::attr::{
+    Alignment, Antialias, Direction, Gravity, GravityHint, HintMetrics, HintStyle, LayoutAttr,
+    Overflow, SubpixelOrder, WrapMode,
+};
 use crate::text::Markup;
 
 #[cfg(feature = "cli")]
@@ -18,7 +21,14 @@ use serde::Deserialize;
 #[derive(Debug, Clone, Deserialize)]
 #[cfg_attr(feature = "cli", derive(LuaLayer))]
 pub struct TextLayer {
+    /// Text in markup, so `<` and `>` from card data must be escaped with [`escape`].
+    ///
+    /// [`escape`]: crate::text::escape
     pub text: String,
+    /// Markup built programmatically, used instead of `text` when given, so that it doesn't
+    /// need to be written and parsed back.
+    #[cfg_attr(feature = "cli", serde(skip))]
+    pub markup: Option<Markup>,
     pub x: i32,
     pub y: i32,
     pub size: f64,
@@ -43,10 +53,29 @@ pub struct TextLayer {
     pub gravity_hint: Option<GravityHint>,
     pub indent: Option<f64>,
     pub justify: Option<bool>,
+    pub justify_last_line: Option<bool>,
     pub language: Option<String>,
+    pub letter_spacing: Option<i32>,
+    pub line_height: Option<f64>,
     pub line_spacing: Option<f64>,
     pub spacing: Option<f64>,
     pub wrap: Option<WrapMode>,
+    /// Maximum height of the text, past which it overflows.
+    pub max_h: Option<i32>,
+    /// How text taller than `max_h` is made to fit, shrinking it by default.
+    pub overflow: Option<Overflow>,
+    pub antialias: Option<Antialias>,
+    pub hint_style: Option<HintStyle>,
+    pub hint_metrics: Option<HintMetrics>,
+    pub subpixel_order: Option<SubpixelOrder>,
+    /// Whether the layer is drawn, so that it can stay defined but be turned off, e.g. by a
+    /// condition computed by the decoder.
+    #[cfg_attr(feature = "cli", serde(default = "default_enabled"))]
+    pub enabled: bool,
+}
+
+const fn default_enabled() -> bool {
+    true
 }
 
 const fn default_color() -> Color {
@@ -66,38 +95,81 @@ impl TextLayer {
             .map(|x| params.push(LayoutAttr::GravityHint(x)));
         self.indent.map(|x| params.push(LayoutAttr::Indent(x)));
         self.justify.map(|x| params.push(LayoutAttr::Justify(x)));
+        params.extend(self.justify_last_line.map(LayoutAttr::JustifyLastLine));
         self.language
             .as_ref()
             .map(|x| params.push(LayoutAttr::Language(x)));
+        params.extend(self.letter_spacing.map(LayoutAttr::LetterSpacing));
+        params.extend(self.line_height.map(LayoutAttr::LineHeight));
         self.line_spacing
             .map(|x| params.push(LayoutAttr::LineSpacing(x)));
         self.spacing.map(|x| params.push(LayoutAttr::Spacing(x)));
         self.w.map(|x| params.push(LayoutAttr::Width(x)));
         self.wrap.map(|x| params.push(LayoutAttr::Wrap(x)));
+        if let (Some(h), Some(Overflow::Ellipsize)) = (self.max_h, self.overflow) {
+            params.push(LayoutAttr::Height(h));
+        }
+        params.extend(self.antialias.map(LayoutAttr::Antialias));
+        params.extend(self.hint_style.map(LayoutAttr::HintStyle));
+        params.extend(self.hint_metrics.map(LayoutAttr::HintMetrics));
+        params.extend(self.subpixel_order.map(LayoutAttr::SubpixelOrder));
         params
     }
 }
 
 impl Layer for TextLayer {
     fn render(&self, img: VipsImage, ctx: &RenderContext) -> Result<VipsImage> {
-        let img_map = ctx.img_map;
+        let (text_img, x, y, blend) = self.place(ctx)?;
+        let o = Origin::default();
+        ctx.backend.overlay(&img, &text_img, x, y, o, o, blend)
+    }
+
+    fn overlay(&self, ctx: &RenderContext) -> Result<Option<(VipsImage, i32, i32, BlendMode)>> {
+        self.place(ctx).map(Some)
+    }
+
+    fn kind(&self) -> &'static str {
+        "text"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn validate(&self, ctx: &RenderContext) -> Vec<Error> {
+        missing_font(self.font.as_deref(), ctx).into_iter().collect()
+    }
+}
+
+impl TextLayer {
+    fn place(&self, ctx: &RenderContext) -> Result<(VipsImage, i32, i32, BlendMode)> {
         let font_map = ctx.font_map;
         let ib = ctx.backend;
 
-        let markup = Markup::from_string(&self.text)?;
-        let font = self.font.as_ref().map(|x| x.as_str()).unwrap_or("default");
+        let markup = match &self.markup {
+            Some(markup) => markup.clone(),
+            None => Markup::from_string(&self.text)?,
+        };
+        let font = self.font.as_deref().unwrap_or(font_map.default_key());
         let params = self.layout_params();
-        let (text_img, layout) = ib.print(
-            markup, &img_map, &font_map, font, self.size, self.color, &params,
-        )?;
-        let (text_img, dh) = if let Some(stroke) = self.stroke {
-            (ib.stroke(&text_img, stroke)?, stroke.size)
+        let res = ctx.text_resources();
+        let (text_img, layout, missing) =
+            ib.print(markup, res, font, self.size, self.color, &params)?;
+        warn_missing_glyphs(&missing, font, ctx);
+        let overflow = self.overflow.unwrap_or_default();
+        let (text_img, s) = fit_height(ib, text_img, self.max_h, overflow)?;
+        let (w, h) = (text_img.get_width() as f64, text_img.get_height() as f64);
+        let baseline = (layout.baseline() as f64 * s) as i32;
+        let (text_img, pad) = if let Some(stroke) = self.stroke {
+            (ib.stroke(&text_img, stroke)?, stroke.padding())
         } else {
             (text_img, 0)
         };
-        let h = layout.baseline() + dh;
-        let (text_img, ox, oy) = ib.rotate(&text_img, self.r, self.ox, self.oy.into_origin(h))?;
-        let (ox, oy) = (Origin::Absolute(ox), Origin::Absolute(oy));
-        ib.overlay(&img, &text_img, self.x, self.y, ox, oy, self.blend)
+        // origins are taken over the text without its stroke, so that stroked text is placed
+        // at the same baseline and edges as unstroked text
+        let ox = self.ox.padded(w, pad);
+        let oy = self.oy.into_origin(baseline).padded(h, pad);
+        let (text_img, ox, oy) = ib.rotate(&text_img, self.r, ox, oy)?;
+        Ok((text_img, self.x - ox as i32, self.y - oy as i32, self.blend))
     }
 }
@@ -0,0 +1,101 @@
+//! Represents text stamped across the whole card, e.g. to mark proofs as samples.
+
+use crate::error::{Error, Result};
+use crate::image::{BlendMode, Color, Origin};
+use crate::layer::{missing_font, warn_missing_glyphs, Layer, RenderContext};
+use crate::text::Markup;
+
+use libvips::VipsImage;
+use serde::Deserialize;
+
+/// Text drawn rotated over the center of every card, after the layers given by the decoder.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Watermark {
+    pub text: String,
+    pub font: Option<String>,
+    #[serde(default = "default_size")]
+    pub size: f64,
+    #[serde(default = "default_color")]
+    pub color: Color,
+    /// Rotation in degrees, clockwise.
+    #[serde(default = "default_angle")]
+    pub angle: f64,
+    #[serde(default = "default_opacity")]
+    pub opacity: f64,
+}
+
+const fn default_size() -> f64 {
+    96.0
+}
+
+const fn default_color() -> Color {
+    Color::BLACK
+}
+
+const fn default_angle() -> f64 {
+    -30.0
+}
+
+const fn default_opacity() -> f64 {
+    0.25
+}
+
+impl Watermark {
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            font: None,
+            size: default_size(),
+            color: default_color(),
+            angle: default_angle(),
+            opacity: default_opacity(),
+        }
+    }
+
+    fn place(&self, ctx: &RenderContext) -> Result<(VipsImage, i32, i32, BlendMode)> {
+        let ib = ctx.backend;
+        let font_map = ctx.font_map;
+        let (w, h) = ctx.img_map.card_size;
+
+        let markup = Markup::plain(self.text.as_str());
+        let font = self.font.as_deref().unwrap_or(font_map.default_key());
+        let (text_img, _, missing) = ib.print(
+            markup,
+            ctx.text_resources(),
+            font,
+            self.size,
+            self.color,
+            &[],
+        )?;
+        warn_missing_glyphs(&missing, font, ctx);
+        let text_img = ib.set_opacity(&text_img, self.opacity)?;
+        let o = Origin::Relative(0.5);
+        let (text_img, ox, oy) = ib.rotate(&text_img, self.angle, o, o)?;
+        Ok((
+            text_img,
+            w / 2 - ox as i32,
+            h / 2 - oy as i32,
+            BlendMode::Over,
+        ))
+    }
+}
+
+impl Layer for Watermark {
+    fn render(&self, img: VipsImage, ctx: &RenderContext) -> Result<VipsImage> {
+        let (text_img, x, y, blend) = self.place(ctx)?;
+        let o = Origin::default();
+        ctx.backend.overlay(&img, &text_img, x, y, o, o, blend)
+    }
+
+    fn overlay(&self, ctx: &RenderContext) -> Result<Option<(VipsImage, i32, i32, BlendMode)>> {
+        self.place(ctx).map(Some)
+    }
+
+    fn kind(&self) -> &'static str {
+        "watermark"
+    }
+
+    fn validate(&self, ctx: &RenderContext) -> Vec<Error> {
+        missing_font(self.font.as_deref(), ctx).into_iter().collect()
+    }
+}
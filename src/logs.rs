@@ -1,10 +1,16 @@
 use crate::error::Error;
 
-use std::io::{stderr, Error as IoError, Stderr, Write};
+use regex::Regex;
+use std::env;
+use std::fmt::Display;
+use std::io::{stderr, Error as IoError, IsTerminal, Sink, Stderr, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, Sender, TryRecvError};
+use std::sync::OnceLock;
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
+#[derive(Debug)]
 pub enum LogMsg {
     Total(usize),
     Progress(usize),
@@ -28,15 +34,30 @@ impl Default for WorkerStatus {
     }
 }
 
+/// Displays the progress of a pipeline on `tty`, optionally also writing
+/// `progress <done> <total>` lines to a structured `sink`, to be read by other programs.
+///
+/// The bar of each worker is shown above a combined bar, on the last lines of the terminal,
+/// with messages scrolling above them. Sequential pipelines log everything as worker `0`,
+/// so with no workers only the combined bar is shown.
 #[derive(Debug, Clone)]
-pub struct ProgressBar<T> {
+pub struct ProgressBar<T, S = Sink> {
     n_workers: usize,
     tty: T,
+    sink: Option<S>,
     status: Vec<WorkerStatus>,
     counts: Vec<usize>,
     total: usize,
     frame: usize,
     time: Instant,
+    start: Instant,
+    sample: (Instant, usize),
+    rate: Option<f64>,
+    animated: bool,
+    last_shown: Option<usize>,
+    last_running: usize,
+    /// Size of the terminal, queried on every frame unless fixed.
+    size: Option<(u16, u16)>,
 }
 
 macro_rules! palette {
@@ -54,34 +75,130 @@ palette! {
     pub const EMPH_COLOR = termion::color::LightYellow;
 }
 
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Makes logging to stderr plain, without colors nor an animated progress bar, as if it
+/// wasn't a terminal. Logging is also plain when the `NO_COLOR` environment variable is set.
+pub fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+/// Whether logging to stderr must be plain, either by [`set_plain`] or by `NO_COLOR`.
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed) || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+/// Colors a piece of text for the terminal, unless logging is plain.
+pub fn paint(color: impl termion::color::Color, text: impl Display) -> String {
+    if is_plain() {
+        text.to_string()
+    } else {
+        format!("{}{text}{}", termion::color::Fg(color), termion::style::Reset)
+    }
+}
+
+/// Highlights a piece of text, such as a name, within a message.
+pub fn emph(text: impl Display) -> String {
+    paint(EMPH_COLOR, text)
+}
+
 impl ProgressBar<Stderr> {
+    /// Creates a progress bar over stderr, falling back to plain line logging
+    /// if stderr is not a terminal or logging is plain.
     pub fn new_stderr(n_workers: usize) -> Result<Self, Error> {
-        Self::new(n_workers, stderr())
+        let tty = stderr();
+        if tty.is_terminal() && !is_plain() {
+            Self::new(n_workers, tty)
+        } else {
+            Self::new_plain(n_workers, tty)
+        }
     }
 
+    /// Spawns a progress bar thread over stderr, falling back to plain line logging
+    /// if stderr is not a terminal or logging is plain.
     pub fn spawn_stderr(n_workers: usize) -> (Sender<LogMsg>, JoinHandle<Result<(), Error>>) {
-        Self::spawn(n_workers, stderr())
+        let tty = stderr();
+        let animated = tty.is_terminal() && !is_plain();
+        Self::spawn_with(n_workers, tty, animated, None)
+    }
+}
+
+impl<S: Write + Send + 'static> ProgressBar<Stderr, S> {
+    /// Spawns a progress bar thread over stderr, like [`ProgressBar::spawn_stderr`], that also
+    /// writes structured progress lines to `sink`.
+    pub fn spawn_stderr_with_sink(
+        n_workers: usize,
+        sink: S,
+    ) -> (Sender<LogMsg>, JoinHandle<Result<(), Error>>) {
+        let tty = stderr();
+        let animated = tty.is_terminal() && !is_plain();
+        Self::spawn_with(n_workers, tty, animated, Some(sink))
     }
 }
 
 impl<T: Write + Send + 'static> ProgressBar<T> {
+    pub fn spawn(n_workers: usize, tty: T) -> (Sender<LogMsg>, JoinHandle<Result<(), Error>>) {
+        Self::spawn_with(n_workers, tty, true, None)
+    }
+
+    /// Spawns a thread running a progress bar that only logs plain lines, without any
+    /// escape sequences.
+    pub fn spawn_plain(
+        n_workers: usize,
+        tty: T,
+    ) -> (Sender<LogMsg>, JoinHandle<Result<(), Error>>) {
+        Self::spawn_with(n_workers, tty, false, None)
+    }
+
+    pub fn new(n_workers: usize, tty: T) -> Result<Self, Error> {
+        Self::with_mode(n_workers, tty, true, None)
+    }
+
+    /// Creates a progress bar that only logs plain lines, without any escape sequences.
+    pub fn new_plain(n_workers: usize, tty: T) -> Result<Self, Error> {
+        Self::with_mode(n_workers, tty, false, None)
+    }
+}
+
+impl<T: Write + Send + 'static, S: Write + Send + 'static> ProgressBar<T, S> {
     const BAR_WIDTH: usize = 16;
     const WORKER_BAR_WIDTH: usize = 8;
     const WORKER_BAR_FACTOR: usize = 3;
     const FRAME_DURATION: f64 = 0.1;
     const FRAME_COUNT: usize = 256;
+    const PLAIN_FRAME_DURATION: f64 = 1.0;
+    const DEFAULT_SIZE: (u16, u16) = (80, 24);
+    const RATE_SAMPLE_DURATION: f64 = 1.0;
+    const RATE_SMOOTHING: f64 = 0.3;
 
-    pub fn spawn(n_workers: usize, tty: T) -> (Sender<LogMsg>, JoinHandle<Result<(), Error>>) {
+    /// Spawns a thread running an animated progress bar, that also writes structured
+    /// progress lines to `sink`.
+    pub fn spawn_with_sink(
+        n_workers: usize,
+        tty: T,
+        sink: S,
+    ) -> (Sender<LogMsg>, JoinHandle<Result<(), Error>>) {
+        Self::spawn_with(n_workers, tty, true, Some(sink))
+    }
+
+    fn spawn_with(
+        n_workers: usize,
+        tty: T,
+        animated: bool,
+        sink: Option<S>,
+    ) -> (Sender<LogMsg>, JoinHandle<Result<(), Error>>) {
         let (tx, rx) = mpsc::channel();
         let handle = thread::spawn(move || {
-            let mut pbar = Self::new(n_workers, tty)?;
+            let mut pbar = Self::with_mode(n_workers, tty, animated, sink)?;
             loop {
                 match rx.try_recv() {
                     Ok(LogMsg::Total(id)) => {
                         pbar.set_total(id);
+                        pbar.report()?;
                     }
                     Ok(LogMsg::Progress(id)) => {
                         pbar.progress(id);
+                        pbar.report()?;
                     }
                     Ok(LogMsg::Info(id, msg)) => {
                         pbar.info(id, msg)?;
@@ -108,20 +225,30 @@ impl<T: Write + Send + 'static> ProgressBar<T> {
         (tx, handle)
     }
 
-    pub fn new(n_workers: usize, tty: T) -> Result<Self, Error> {
+    fn with_mode(n_workers: usize, tty: T, animated: bool, sink: Option<S>) -> Result<Self, Error> {
         let mut pbar = Self {
             n_workers,
             tty,
+            sink,
             status: vec![WorkerStatus::default(); n_workers + 1],
             counts: vec![0; n_workers + 1],
             total: 0,
             frame: 0,
             time: Instant::now(),
+            start: Instant::now(),
+            sample: (Instant::now(), 0),
+            rate: None,
+            animated,
+            last_shown: None,
+            last_running: 0,
+            size: None,
         };
-        for _ in 0..=n_workers {
-            write!(pbar.tty, "\n").map_err(Error::io_error)?;
+        if animated {
+            for _ in 0..=n_workers {
+                write!(pbar.tty, "\n").map_err(Error::io_error)?;
+            }
+            pbar.show().map_err(Error::io_error)?;
         }
-        pbar.show().map_err(Error::io_error)?;
         Ok(pbar)
     }
 
@@ -136,6 +263,16 @@ impl<T: Write + Send + 'static> ProgressBar<T> {
         self.counts[0] += 1;
     }
 
+    /// Writes the current progress to the structured sink, if there is one.
+    fn report(&mut self) -> Result<(), Error> {
+        if let Some(sink) = self.sink.as_mut() {
+            writeln!(sink, "progress {} {}", self.counts[0], self.total)
+                .and_then(|_| sink.flush())
+                .map_err(Error::io_error)?;
+        }
+        Ok(())
+    }
+
     pub fn info(&mut self, id: usize, msg: String) -> Result<(), Error> {
         self.log_message(id, "INFO", &msg, INFO_COLOR)
             .map_err(Error::io_error)
@@ -147,6 +284,7 @@ impl<T: Write + Send + 'static> ProgressBar<T> {
     }
 
     pub fn running(&mut self, id: usize, msg: String) {
+        self.last_running = id;
         self.status[id] = WorkerStatus::Running(msg);
     }
 
@@ -165,12 +303,20 @@ impl<T: Write + Send + 'static> ProgressBar<T> {
         msg: &str,
         color: impl termion::color::Color,
     ) -> Result<(), IoError> {
-        let (_w, h) = termion::terminal_size()?;
+        if !self.animated {
+            return self.log_plain(id, label, msg);
+        }
+        let (_w, h) = self.terminal_size();
         let nl = msg.chars().filter(|c| *c == '\n').count() as u16;
         let msg = msg
             .replace("\t", "    ")
             .replace("\n", &format!("{}\n", termion::clear::UntilNewline));
-        let y = h - self.n_workers as u16 - (2 + nl);
+        // bars take the `n_workers + 1` lines above the last one, so once scrolled up, the
+        // message goes where the first bar was, and the bars are drawn again below it
+        let y = h
+            .saturating_sub(self.n_workers as u16)
+            .saturating_sub(2 + nl)
+            .max(1);
         let up = termion::scroll::Up(1 + nl);
         let goto = termion::cursor::Goto(1, y);
         let id_color = termion::color::Fg(INFO_COLOR);
@@ -192,9 +338,26 @@ impl<T: Write + Send + 'static> ProgressBar<T> {
         Ok(())
     }
 
+    fn log_plain(&mut self, id: usize, label: &'static str, msg: &str) -> Result<(), IoError> {
+        let msg = strip_escapes(msg);
+        if id > 0 {
+            writeln!(self.tty, "{id:02} [{label}] {msg}")
+        } else {
+            writeln!(self.tty, "[{label}] {msg}")
+        }
+    }
+
     pub fn update(&mut self) -> Result<(), Error> {
         let now = Instant::now();
+        self.sample_rate(now);
         let dt = now.duration_since(self.time).as_secs_f64();
+        if !self.animated {
+            if dt >= Self::PLAIN_FRAME_DURATION && self.last_shown != Some(self.counts[0]) {
+                self.time = now;
+                self.show().map_err(Error::io_error)?;
+            }
+            return Ok(());
+        }
         if dt >= Self::FRAME_DURATION {
             self.time = now;
             self.frame = (self.frame + 1) % Self::FRAME_COUNT;
@@ -203,9 +366,52 @@ impl<T: Write + Send + 'static> ProgressBar<T> {
         Ok(())
     }
 
+    /// Updates the throughput estimate, smoothing it with an exponential moving average to
+    /// avoid jitter. The first estimate is the average since the progress bar was created.
+    fn sample_rate(&mut self, now: Instant) {
+        let (last_time, last_count) = self.sample;
+        let dt = now.duration_since(last_time).as_secs_f64();
+        if dt < Self::RATE_SAMPLE_DURATION {
+            return;
+        }
+        let n = self.counts[0];
+        self.rate = Some(match self.rate {
+            Some(rate) => {
+                let current = n.saturating_sub(last_count) as f64 / dt;
+                Self::RATE_SMOOTHING * current + (1.0 - Self::RATE_SMOOTHING) * rate
+            }
+            None => n as f64 / now.duration_since(self.start).as_secs_f64(),
+        });
+        self.sample = (now, n);
+    }
+
+    /// Formats throughput and estimated time remaining, if there is enough information.
+    fn stats(&self) -> Option<String> {
+        let rate = self.rate.filter(|r| *r > 0.0)?;
+        let remaining = self
+            .total
+            .checked_sub(self.counts[0])
+            .filter(|_| self.total > 0);
+        match remaining {
+            Some(remaining) => {
+                let eta = (remaining as f64 / rate).round() as u64;
+                let (h, m, s) = (eta / 3600, eta / 60 % 60, eta % 60);
+                if h > 0 {
+                    Some(format!("{rate:.1}/s ETA {h}:{m:02}:{s:02}"))
+                } else {
+                    Some(format!("{rate:.1}/s ETA {m:02}:{s:02}"))
+                }
+            }
+            None => Some(format!("{rate:.1}/s")),
+        }
+    }
+
     fn show(&mut self) -> Result<(), IoError> {
-        let (w, h) = termion::terminal_size()?;
-        let y = h - self.n_workers as u16 - 1;
+        if !self.animated {
+            return self.show_plain();
+        }
+        let (w, h) = self.terminal_size();
+        let y = h.saturating_sub(self.n_workers as u16 + 1).max(1);
         write!(self.tty, "{}", termion::cursor::Goto(1, y))?;
         for id in 1..=self.n_workers {
             self.show_worker(w, id)?;
@@ -214,6 +420,33 @@ impl<T: Write + Send + 'static> ProgressBar<T> {
         Ok(())
     }
 
+    fn show_plain(&mut self) -> Result<(), IoError> {
+        let n = self.counts[0];
+        let total = self.total;
+        self.last_shown = Some(n);
+        let progress = match (total > 0, self.stats()) {
+            (true, Some(stats)) => format!("[{n}/{total}] {stats}"),
+            (true, None) => format!("[{n}/{total}]"),
+            (false, Some(stats)) => format!("[{n}] {stats}"),
+            (false, None) => format!("[{n}]"),
+        };
+        let msg = match (&self.status[self.last_running], &self.status[0]) {
+            (_, WorkerStatus::Error(msg) | WorkerStatus::Success(msg)) => msg,
+            (WorkerStatus::Running(msg), _) => msg,
+            (_, WorkerStatus::Running(msg)) => msg,
+        };
+        let msg = strip_escapes(msg);
+        writeln!(self.tty, "{progress} {msg}")?;
+        if let WorkerStatus::Success(_) | WorkerStatus::Error(_) = &self.status[0] {
+            for id in 1..=self.n_workers {
+                if let WorkerStatus::Error(msg) = &self.status[id] {
+                    writeln!(self.tty, "{id:02} [ERROR] {}", strip_escapes(msg))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn show_worker(&mut self, w: u16, id: usize) -> Result<(), IoError> {
         let (label, color, msg) = match &self.status[id] {
             WorkerStatus::Running(msg) => {
@@ -270,33 +503,137 @@ impl<T: Write + Send + 'static> ProgressBar<T> {
             WorkerStatus::Error(msg) => ("!".repeat(Self::BAR_WIDTH), ERR_COLOR.fg_str(), msg),
             WorkerStatus::Success(msg) => ("=".repeat(Self::BAR_WIDTH), OK_COLOR.fg_str(), msg),
         };
-        let msg = Self::ellipsize(msg, w, 27);
+        let stats = match (&self.status[0], self.stats()) {
+            (WorkerStatus::Running(_), Some(stats)) => format!("{stats} "),
+            _ => String::new(),
+        };
+        let msg = Self::ellipsize(msg, w, 27 + stats.chars().count() as u16);
         let reset = termion::style::Reset;
         let clear = termion::clear::UntilNewline;
+        let stats_color = termion::color::Fg(INFO_COLOR);
 
         if total > 0 {
             write!(
                 self.tty,
-                "{color}[{label} {n:3}/{total:3}] {reset}{msg}{clear}\n",
+                "{color}[{label} {n:3}/{total:3}] {stats_color}{stats}{reset}{msg}{clear}\n",
             )?;
         } else {
-            write!(self.tty, "{color}[{label} {n:3}] {reset}{msg}{clear}\n",)?;
+            write!(
+                self.tty,
+                "{color}[{label} {n:3}] {stats_color}{stats}{reset}{msg}{clear}\n",
+            )?;
         }
         Ok(())
     }
 
+    /// Queries the terminal size on every call unless it is fixed, so that resizing is taken
+    /// into account, falling back to a default size if the query fails.
+    fn terminal_size(&self) -> (u16, u16) {
+        self.size
+            .unwrap_or_else(|| termion::terminal_size().unwrap_or(Self::DEFAULT_SIZE))
+    }
+
     fn ellipsize(s: &str, w: u16, used: u16) -> String {
-        let w = w - used;
+        let w = w.saturating_sub(used) as usize;
         let len = s.chars().count();
-        if len >= w as usize {
-            format!(
-                "{}...",
-                s.chars()
-                    .take((w.saturating_sub(4)) as usize)
-                    .collect::<String>()
-            )
-        } else {
+        if len < w {
             s.to_string()
+        } else if w < 4 {
+            String::new()
+        } else {
+            format!("{}...", s.chars().take(w - 4).collect::<String>())
         }
     }
 }
+
+/// Removes terminal escape sequences from a message, for output that is not a terminal.
+fn strip_escapes(msg: &str) -> String {
+    static ESCAPE: OnceLock<Regex> = OnceLock::new();
+    let re = ESCAPE.get_or_init(|| Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap());
+    re.replace_all(msg, "").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    type Bar = ProgressBar<Sink>;
+
+    /// Keeps everything written to it, to be read back by the test that shares it.
+    #[derive(Debug, Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Buffer {
+        fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+        }
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs a progress bar with no workers over two cards, on an 80x24 terminal, returning
+    /// what was written to the terminal and to the sink.
+    fn run(animated: bool) -> (String, String) {
+        let (tty, sink) = (Buffer::default(), Buffer::default());
+        let mut pbar = ProgressBar::with_mode(0, tty.clone(), animated, Some(sink.clone()))
+            .unwrap();
+        pbar.size = Some((80, 24));
+        // drops the bar drawn before the size was fixed
+        tty.take();
+
+        pbar.set_total(2);
+        pbar.report().unwrap();
+        pbar.running(0, String::from("running"));
+        pbar.progress(0);
+        pbar.report().unwrap();
+        pbar.info(0, String::from("card 1\ndone")).unwrap();
+        pbar.progress(0);
+        pbar.report().unwrap();
+        pbar.success(0, String::from("finished!"));
+        pbar.show().unwrap();
+        (tty.take(), sink.take())
+    }
+
+    #[test]
+    fn animated_bar_stays_below_messages() {
+        let (output, sink) = run(true);
+        // the bar takes the line above the cursor, and a message of two lines scrolls the
+        // screen up by two and is written right above the bar
+        assert!(output.contains("\x1b[2S\x1b[21;1H"), "{output:?}");
+        assert!(output.contains("\x1b[23;1H"), "{output:?}");
+        assert!(output.contains("[================   2/  2] "), "{output:?}");
+        assert_eq!(sink, "progress 0 2\nprogress 1 2\nprogress 2 2\n");
+    }
+
+    #[test]
+    fn plain_bar_logs_lines() {
+        let (output, sink) = run(false);
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "[INFO] card 1");
+        assert!(lines.last().unwrap().starts_with("[2/2] "), "{output:?}");
+        assert!(lines.last().unwrap().ends_with("finished!"), "{output:?}");
+        assert_eq!(sink, "progress 0 2\nprogress 1 2\nprogress 2 2\n");
+    }
+
+    #[test]
+    fn ellipsize_fits_message() {
+        assert_eq!(Bar::ellipsize("card 1", 40, 18), "card 1");
+        assert_eq!(Bar::ellipsize("rendering card 1", 30, 18), "renderin...");
+    }
+
+    #[test]
+    fn ellipsize_narrower_than_used() {
+        assert_eq!(Bar::ellipsize("card 1", 10, 18), "");
+        assert_eq!(Bar::ellipsize("card 1", 0, u16::MAX), "");
+        assert_eq!(Bar::ellipsize("card 1", 21, 18), "");
+    }
+}
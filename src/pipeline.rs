@@ -1,25 +1,67 @@
+mod iter;
 mod parallel;
+mod report;
 mod sequential;
 
 use crate::data::Card;
 use crate::error::{Error, Result};
 use crate::logs::{self, LogMsg, ProgressBar};
+pub use crate::pipeline::iter::CardIter;
 pub use crate::pipeline::parallel::ParallelismOptions;
+pub use crate::pipeline::report::ReportVisitor;
 use crate::template::Template;
 
+use std::collections::BTreeMap;
+use std::io::Write;
 use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Processes a single card, turning a panic into an error of that card, so that a bug
+/// triggered by one card skips it instead of stopping every card after it.
+///
+/// Errors while processing a card, such as a missing asset, don't stop a pipeline unless it is
+/// set to [fail fast](Pipeline::set_fail_fast), as it otherwise only stops for problems that
+/// affect every card, such as a data source or decoder that can't be opened.
+fn process_card<R>(process: impl FnOnce() -> Result<R>) -> Result<R> {
+    panic::catch_unwind(AssertUnwindSafe(process)).unwrap_or_else(|e| Err(Error::card_panic(e)))
+}
+
+/// What became of a card processed without errors, along with the path of its output.
+enum Processed {
+    Rendered(PathBuf),
+    /// The output rendered earlier is still up to date, as found by [`Template::rendered`].
+    Skipped(PathBuf),
+}
 
 pub struct Pipeline<C: Card, T: Template<C>, V: Visitor<C, T> = ()> {
     pub(crate) template: T,
     pub(crate) visitor: V,
+    pub(crate) fail_fast: bool,
     _card: PhantomData<C>,
 }
 
 impl<C: Card, T: Template<C>, V: Visitor<C, T>> Pipeline<C, T, V> {
     pub fn new(template: T, visitor: V) -> Self {
-        Self { template, visitor, _card: PhantomData }
+        Self { template, visitor, fail_fast: false, _card: PhantomData }
+    }
+
+    /// Sets whether the pipeline stops at the first card that fails, instead of going on with
+    /// the other cards. The failed card is still reported to the visitor, and the error that
+    /// stopped the pipeline is returned by [`try_run`](Self::try_run) or, for parallel
+    /// pipelines, by `join`. Cards already being processed by other workers are finished
+    /// first.
+    pub fn set_fail_fast(&mut self, fail_fast: bool) {
+        self.fail_fast = fail_fast;
+    }
+
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.set_fail_fast(fail_fast);
+        self
     }
 }
 
@@ -27,12 +69,18 @@ impl<C: Card, T: Template<C>, V: Visitor<C, T>> Pipeline<C, T, V> {
 pub trait Visitor<C: Card, T: Template<C>> {
     fn on_start(&self, template: &T, worker: usize) {}
 
+    /// Called once by parallel pipelines, with the options actually used, e.g. to report
+    /// that fewer workers were started than requested.
+    fn on_parallelism(&self, template: &T, opt: &ParallelismOptions) {}
+
     fn on_total(&self, template: &T, total: usize) {}
 
     fn on_read(&self, template: &T, card: &Result<C>) -> bool {
         true
     }
 
+    fn on_queue(&self, template: &T, queued: usize, in_flight: usize) {}
+
     fn on_read_err(&self, template: &T, i: usize, error: Error) {
         self.on_read_err_r(template, i, &error);
     }
@@ -41,11 +89,50 @@ pub trait Visitor<C: Card, T: Template<C>> {
 
     fn on_iter_start(&self, template: &T, worker: usize, i: usize, card: &C) {}
 
-    fn on_iter_ok(&self, template: &T, worker: usize, i: usize, card: C) {
-        self.on_iter_ok_r(template, worker, i, &card);
+    /// Called for each problem found while rendering a card that didn't stop it from being
+    /// rendered, such as glyphs missing from a font, before the card is reported as done.
+    fn on_iter_warn(&self, template: &T, worker: usize, i: usize, card: &C, warning: &str) {}
+
+    /// Called once a card is done, whether it succeeded or not, with the time it took and the
+    /// time spent on each of its layers, by position and kind.
+    fn on_iter_times(
+        &self,
+        template: &T,
+        worker: usize,
+        i: usize,
+        card: &C,
+        total: Duration,
+        layers: &[(usize, &'static str, Duration)],
+    ) {
+    }
+
+    /// Called for each file written for a card, right after it is written and before the card
+    /// is reported as done, with the width and height of the image written to it. Cards that
+    /// were already rendered and are skipped write no files.
+    fn on_output(
+        &self,
+        template: &T,
+        worker: usize,
+        i: usize,
+        card: &C,
+        path: &Path,
+        size: (i32, i32),
+    ) {
+    }
+
+    fn on_iter_ok(&self, template: &T, worker: usize, i: usize, card: C, path: PathBuf) {
+        self.on_iter_ok_r(template, worker, i, &card, &path);
     }
 
-    fn on_iter_ok_r(&self, template: &T, worker: usize, i: usize, card: &C) {}
+    fn on_iter_ok_r(&self, template: &T, worker: usize, i: usize, card: &C, path: &Path) {}
+
+    /// Called instead of `on_iter_ok` for a card that was skipped, as its output rendered
+    /// earlier is still up to date, with the path of that output.
+    fn on_iter_skip(&self, template: &T, worker: usize, i: usize, card: C, path: PathBuf) {
+        self.on_iter_skip_r(template, worker, i, &card, &path);
+    }
+
+    fn on_iter_skip_r(&self, template: &T, worker: usize, i: usize, card: &C, path: &Path) {}
 
     fn on_iter_err(&self, template: &T, worker: usize, i: usize, card: C, error: Error) {
         self.on_iter_err_r(template, worker, i, &card, &error);
@@ -58,21 +145,307 @@ pub trait Visitor<C: Card, T: Template<C>> {
 
 impl<C: Card, T: Template<C>> Visitor<C, T> for () {}
 
+/// Calls both visitors in turn. Hooks that take ownership of a card or an error call the
+/// hooks that borrow it instead, and cards are read only if both visitors agree.
+impl<C: Card, T: Template<C>, A: Visitor<C, T>, B: Visitor<C, T>> Visitor<C, T> for (A, B) {
+    fn on_start(&self, template: &T, worker: usize) {
+        self.0.on_start(template, worker);
+        self.1.on_start(template, worker);
+    }
+
+    fn on_parallelism(&self, template: &T, opt: &ParallelismOptions) {
+        self.0.on_parallelism(template, opt);
+        self.1.on_parallelism(template, opt);
+    }
+
+    fn on_total(&self, template: &T, total: usize) {
+        self.0.on_total(template, total);
+        self.1.on_total(template, total);
+    }
+
+    fn on_read(&self, template: &T, card: &Result<C>) -> bool {
+        let a = self.0.on_read(template, card);
+        let b = self.1.on_read(template, card);
+        a && b
+    }
+
+    fn on_queue(&self, template: &T, queued: usize, in_flight: usize) {
+        self.0.on_queue(template, queued, in_flight);
+        self.1.on_queue(template, queued, in_flight);
+    }
+
+    fn on_read_err(&self, template: &T, i: usize, error: Error) {
+        self.on_read_err_r(template, i, &error);
+    }
+
+    fn on_read_err_r(&self, template: &T, i: usize, error: &Error) {
+        self.0.on_read_err_r(template, i, error);
+        self.1.on_read_err_r(template, i, error);
+    }
+
+    fn on_iter_start(&self, template: &T, worker: usize, i: usize, card: &C) {
+        self.0.on_iter_start(template, worker, i, card);
+        self.1.on_iter_start(template, worker, i, card);
+    }
+
+    fn on_iter_warn(&self, template: &T, worker: usize, i: usize, card: &C, warning: &str) {
+        self.0.on_iter_warn(template, worker, i, card, warning);
+        self.1.on_iter_warn(template, worker, i, card, warning);
+    }
+
+    fn on_iter_times(
+        &self,
+        template: &T,
+        worker: usize,
+        i: usize,
+        card: &C,
+        total: Duration,
+        layers: &[(usize, &'static str, Duration)],
+    ) {
+        self.0.on_iter_times(template, worker, i, card, total, layers);
+        self.1.on_iter_times(template, worker, i, card, total, layers);
+    }
+
+    fn on_output(
+        &self,
+        template: &T,
+        worker: usize,
+        i: usize,
+        card: &C,
+        path: &Path,
+        size: (i32, i32),
+    ) {
+        self.0.on_output(template, worker, i, card, path, size);
+        self.1.on_output(template, worker, i, card, path, size);
+    }
+
+    fn on_iter_ok(&self, template: &T, worker: usize, i: usize, card: C, path: PathBuf) {
+        self.on_iter_ok_r(template, worker, i, &card, &path);
+    }
+
+    fn on_iter_ok_r(&self, template: &T, worker: usize, i: usize, card: &C, path: &Path) {
+        self.0.on_iter_ok_r(template, worker, i, card, path);
+        self.1.on_iter_ok_r(template, worker, i, card, path);
+    }
+
+    fn on_iter_skip(&self, template: &T, worker: usize, i: usize, card: C, path: PathBuf) {
+        self.on_iter_skip_r(template, worker, i, &card, &path);
+    }
+
+    fn on_iter_skip_r(&self, template: &T, worker: usize, i: usize, card: &C, path: &Path) {
+        self.0.on_iter_skip_r(template, worker, i, card, path);
+        self.1.on_iter_skip_r(template, worker, i, card, path);
+    }
+
+    fn on_iter_err(&self, template: &T, worker: usize, i: usize, card: C, error: Error) {
+        self.on_iter_err_r(template, worker, i, &card, &error);
+    }
+
+    fn on_iter_err_r(&self, template: &T, worker: usize, i: usize, card: &C, error: &Error) {
+        self.0.on_iter_err_r(template, worker, i, card, error);
+        self.1.on_iter_err_r(template, worker, i, card, error);
+    }
+
+    fn on_finish(&self, template: &T, worker: usize, result: &Result<()>) {
+        self.0.on_finish(template, worker, result);
+        self.1.on_finish(template, worker, result);
+    }
+}
+
+/// Calls the visitor if there is one, e.g. for visitors that are only used when asked for.
+impl<C: Card, T: Template<C>, V: Visitor<C, T>> Visitor<C, T> for Option<V> {
+    fn on_start(&self, template: &T, worker: usize) {
+        if let Some(v) = self {
+            v.on_start(template, worker);
+        }
+    }
+
+    fn on_parallelism(&self, template: &T, opt: &ParallelismOptions) {
+        if let Some(v) = self {
+            v.on_parallelism(template, opt);
+        }
+    }
+
+    fn on_total(&self, template: &T, total: usize) {
+        if let Some(v) = self {
+            v.on_total(template, total);
+        }
+    }
+
+    fn on_read(&self, template: &T, card: &Result<C>) -> bool {
+        match self {
+            Some(v) => v.on_read(template, card),
+            None => true,
+        }
+    }
+
+    fn on_queue(&self, template: &T, queued: usize, in_flight: usize) {
+        if let Some(v) = self {
+            v.on_queue(template, queued, in_flight);
+        }
+    }
+
+    fn on_read_err(&self, template: &T, i: usize, error: Error) {
+        if let Some(v) = self {
+            v.on_read_err(template, i, error);
+        }
+    }
+
+    fn on_read_err_r(&self, template: &T, i: usize, error: &Error) {
+        if let Some(v) = self {
+            v.on_read_err_r(template, i, error);
+        }
+    }
+
+    fn on_iter_start(&self, template: &T, worker: usize, i: usize, card: &C) {
+        if let Some(v) = self {
+            v.on_iter_start(template, worker, i, card);
+        }
+    }
+
+    fn on_iter_warn(&self, template: &T, worker: usize, i: usize, card: &C, warning: &str) {
+        if let Some(v) = self {
+            v.on_iter_warn(template, worker, i, card, warning);
+        }
+    }
+
+    fn on_iter_times(
+        &self,
+        template: &T,
+        worker: usize,
+        i: usize,
+        card: &C,
+        total: Duration,
+        layers: &[(usize, &'static str, Duration)],
+    ) {
+        if let Some(v) = self {
+            v.on_iter_times(template, worker, i, card, total, layers);
+        }
+    }
+
+    fn on_output(
+        &self,
+        template: &T,
+        worker: usize,
+        i: usize,
+        card: &C,
+        path: &Path,
+        size: (i32, i32),
+    ) {
+        if let Some(v) = self {
+            v.on_output(template, worker, i, card, path, size);
+        }
+    }
+
+    fn on_iter_ok(&self, template: &T, worker: usize, i: usize, card: C, path: PathBuf) {
+        if let Some(v) = self {
+            v.on_iter_ok(template, worker, i, card, path);
+        }
+    }
+
+    fn on_iter_ok_r(&self, template: &T, worker: usize, i: usize, card: &C, path: &Path) {
+        if let Some(v) = self {
+            v.on_iter_ok_r(template, worker, i, card, path);
+        }
+    }
+
+    fn on_iter_skip(&self, template: &T, worker: usize, i: usize, card: C, path: PathBuf) {
+        if let Some(v) = self {
+            v.on_iter_skip(template, worker, i, card, path);
+        }
+    }
+
+    fn on_iter_skip_r(&self, template: &T, worker: usize, i: usize, card: &C, path: &Path) {
+        if let Some(v) = self {
+            v.on_iter_skip_r(template, worker, i, card, path);
+        }
+    }
+
+    fn on_iter_err(&self, template: &T, worker: usize, i: usize, card: C, error: Error) {
+        if let Some(v) = self {
+            v.on_iter_err(template, worker, i, card, error);
+        }
+    }
+
+    fn on_iter_err_r(&self, template: &T, worker: usize, i: usize, card: &C, error: &Error) {
+        if let Some(v) = self {
+            v.on_iter_err_r(template, worker, i, card, error);
+        }
+    }
+
+    fn on_finish(&self, template: &T, worker: usize, result: &Result<()>) {
+        if let Some(v) = self {
+            v.on_finish(template, worker, result);
+        }
+    }
+}
+
+/// Logs the progress of a pipeline to stderr.
+///
+/// Messages about a card, such as its warnings or why it failed, are held until every card
+/// read before it is done, so that they are logged in the order cards were read regardless of
+/// which worker finished first. Given the same input, the same messages are then logged in
+/// the same order, though the worker each one is tagged with may change between runs.
 #[derive(Debug, Clone)]
 pub struct LogVisitor {
     tx: Sender<LogMsg>,
+    held: Arc<Mutex<HeldMessages>>,
+}
+
+/// Messages about cards that can't be logged yet, by card index.
+#[derive(Debug, Default)]
+struct HeldMessages {
+    next: usize,
+    cards: BTreeMap<usize, (bool, Vec<LogMsg>)>,
 }
 
 impl LogVisitor {
     pub fn new(n_workers: usize) -> (Self, JoinHandle<Result<()>>) {
         let (tx, handle) = ProgressBar::spawn_stderr(n_workers);
-        (Self { tx }, handle)
+        (Self { tx, held: Default::default() }, handle)
+    }
+
+    /// Creates a visitor that logs to stderr and also writes `progress <done> <total>` lines
+    /// to `sink`, so that a wrapping application can display its own progress.
+    pub fn with_sink(
+        n_workers: usize,
+        sink: impl Write + Send + 'static,
+    ) -> (Self, JoinHandle<Result<()>>) {
+        let (tx, handle) = ProgressBar::spawn_stderr_with_sink(n_workers, sink);
+        (Self { tx, held: Default::default() }, handle)
     }
 
     fn log(&self, msg: LogMsg) {
         self.tx.send(msg).unwrap_or(())
     }
 
+    /// Logs a message about the `i`-th card once the cards before it are done, marking the
+    /// card as done as well if `done` is set.
+    fn log_card(&self, i: usize, msg: Option<LogMsg>, done: bool) {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        let HeldMessages { next, cards } = &mut *held;
+        let card = cards.entry(i).or_default();
+        card.0 |= done;
+        card.1.extend(msg);
+        while let Some(entry) = cards.first_entry() {
+            if *entry.key() != *next || !entry.get().0 {
+                break;
+            }
+            let (_, msgs) = entry.remove();
+            msgs.into_iter().for_each(|msg| self.log(msg));
+            *next += 1;
+        }
+    }
+
+    /// Logs every message still held, such as those after a card that was never done because
+    /// its worker stopped.
+    fn flush_cards(&self) {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        for (_, (_, msgs)) in std::mem::take(&mut held.cards) {
+            msgs.into_iter().for_each(|msg| self.log(msg));
+        }
+    }
+
     pub fn tx(&self) -> Sender<LogMsg> {
         self.tx.clone()
     }
@@ -84,15 +457,24 @@ impl<C: Card, T: Template<C>> Visitor<C, T> for LogVisitor {
             return;
         }
         match template.name() {
-            Some(name) => self.log(LogMsg::Running(
+            Some(name) => {
+                self.log(LogMsg::Running(0, format!("running template {}", logs::emph(name))))
+            }
+            None => {}
+        }
+    }
+
+    fn on_parallelism(&self, _template: &T, opt: &ParallelismOptions) {
+        if opt.is_clamped() {
+            self.log(LogMsg::Info(
                 0,
                 format!(
-                    "running template {}{name}{}",
-                    termion::color::Fg(logs::EMPH_COLOR),
-                    termion::style::Reset
+                    "using {} workers instead of {}, as only {} cores are available",
+                    opt.n_workers(),
+                    opt.requested(),
+                    opt.n_workers()
                 ),
-            )),
-            None => {}
+            ));
         }
     }
 
@@ -101,10 +483,8 @@ impl<C: Card, T: Template<C>> Visitor<C, T> for LogVisitor {
     }
 
     fn on_read_err_r(&self, _template: &T, i: usize, error: &Error) {
-        self.log(LogMsg::Warn(
-            0,
-            format!("failed to read card (#{i}): {error}"),
-        ));
+        let msg = LogMsg::Warn(0, format!("failed to read card (#{i}): {error}"));
+        self.log_card(i, Some(msg), true);
     }
 
     fn on_iter_start(&self, template: &T, worker: usize, i: usize, card: &C) {
@@ -115,28 +495,36 @@ impl<C: Card, T: Template<C>> Visitor<C, T> for LogVisitor {
         ))
     }
 
-    fn on_iter_ok_r(&self, _template: &T, worker: usize, _i: usize, _card: &C) {
+    fn on_iter_warn(&self, template: &T, worker: usize, i: usize, card: &C, warning: &str) {
+        let card_id = template.identify(card);
+        let msg = LogMsg::Info(worker, format!("card {card_id} (#{i}): {warning}"));
+        self.log_card(i, Some(msg), false);
+    }
+
+    fn on_iter_ok_r(&self, _template: &T, worker: usize, i: usize, _card: &C, _path: &Path) {
         self.log(LogMsg::Progress(worker));
+        self.log_card(i, None, true);
+    }
+
+    fn on_iter_skip_r(&self, _template: &T, worker: usize, i: usize, _card: &C, _path: &Path) {
+        self.log(LogMsg::Progress(worker));
+        self.log_card(i, None, true);
     }
 
     fn on_iter_err_r(&self, template: &T, worker: usize, i: usize, card: &C, error: &Error) {
         let card_id = template.identify(card);
-        self.log(LogMsg::Warn(
-            worker,
-            format!("failed to process card {card_id} (#{i}): {error}"),
-        ))
+        let msg = format!("failed to process card {card_id} (#{i}): {error}");
+        self.log_card(i, Some(LogMsg::Warn(worker, msg)), true);
     }
 
     fn on_finish(&self, template: &T, worker: usize, result: &Result<()>) {
+        if worker == 0 {
+            self.flush_cards();
+        }
         let msg = match (result, worker, template.name()) {
-            (Ok(()), 0, Some(name)) => LogMsg::Success(
-                worker,
-                format!(
-                    "finished template {}{name}{}!",
-                    termion::color::Fg(logs::EMPH_COLOR),
-                    termion::style::Reset
-                ),
-            ),
+            (Ok(()), 0, Some(name)) => {
+                LogMsg::Success(worker, format!("finished template {}!", logs::emph(name)))
+            }
             (Ok(()), _, _) => LogMsg::Success(worker, String::from("done!")),
             (Err(e), _, _) => LogMsg::Error(worker, e.to_string()),
         };
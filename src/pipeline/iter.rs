@@ -0,0 +1,75 @@
+use crate::data::{Card, DataSource, Predicate};
+use crate::decode::Decoder;
+use crate::error::Result;
+use crate::image::ImgBackend;
+use crate::layer::RenderContext;
+use crate::pipeline::{Pipeline, Visitor};
+use crate::template::Template;
+
+use libvips::VipsImage;
+
+impl<C, T, V> Pipeline<C, T, V>
+where
+    C: Card,
+    T: Template<C>,
+    V: Visitor<C, T>,
+{
+    /// Returns an iterator that reads, decodes and renders one card each time it is advanced,
+    /// without writing the images nor calling the visitor.
+    ///
+    /// The source is opened by the caller, e.g. with [`Template::source`], as the cards read
+    /// from it borrow it for as long as the iterator lives.
+    pub fn iter<'a>(
+        &'a self,
+        source: &'a mut dyn DataSource<C>,
+        filter: Option<Predicate>,
+    ) -> Result<CardIter<'a, C, T>> {
+        Ok(CardIter {
+            template: &self.template,
+            decoder: self.template.decoder()?,
+            backend: ImgBackend::shared()?,
+            cards: source.read(filter)?,
+        })
+    }
+
+    /// Returns the template used by the pipeline.
+    pub fn template(&self) -> &T {
+        &self.template
+    }
+}
+
+/// Iterator over rendered cards, created by [`Pipeline::iter`]. Each item is either a card
+/// and its image, or the error found while reading or rendering a card.
+pub struct CardIter<'a, C: Card, T: Template<C>> {
+    template: &'a T,
+    decoder: T::Decoder,
+    backend: &'static ImgBackend,
+    cards: Box<dyn Iterator<Item = Result<C>> + 'a>,
+}
+
+impl<'a, C: Card, T: Template<C>> CardIter<'a, C, T> {
+    fn render(&self, card: &C) -> Result<VipsImage> {
+        let ctx = RenderContext {
+            backend: self.backend,
+            font_map: self.template.fonts(),
+            img_map: self.template.resources(),
+            options: self.template.render_options(),
+            warnings: Default::default(),
+            layer_times: Default::default(),
+            outputs: Default::default(),
+        };
+        self.decoder.decode(card)?.render(&ctx)
+    }
+}
+
+impl<'a, C: Card, T: Template<C>> Iterator for CardIter<'a, C, T> {
+    type Item = Result<(C, VipsImage)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let card = match self.cards.next()? {
+            Ok(card) => card,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.render(&card).map(|img| (card, img)))
+    }
+}
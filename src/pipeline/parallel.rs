@@ -5,20 +5,22 @@ use crate::image::ImgBackend;
 use crate::layer::RenderContext;
 use crate::template::Template;
 
-use crate::pipeline::{Pipeline, Visitor};
+use crate::pipeline::{process_card, Pipeline, Processed, Visitor};
 
+use std::cell::Cell;
 use std::collections::VecDeque;
-use std::marker::PhantomData;
 use std::num::NonZero;
-use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock};
 use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
 macro_rules! lock {
     (read $T:literal $lock:expr) => {
         $lock.read().map_err(|e| Error::read_lock($T, e))?
     };
     (write $T:literal $lock:expr) => {
-        $lock.read().map_err(|e| Error::write_lock($T, e))?
+        $lock.write().map_err(|e| Error::write_lock($T, e))?
     };
     ($T:literal $lock:expr) => {
         $lock.lock().map_err(|e| Error::mutex_lock($T, e))?
@@ -27,24 +29,57 @@ macro_rules! lock {
 
 #[derive(Debug, Clone, Copy)]
 pub struct ParallelismOptions {
+    requested: usize,
     n_workers: usize,
     batch_size: usize,
+    /// Bounds within which the batch size is adjusted while running, if adaptive.
+    batch_bounds: Option<(usize, usize)>,
+    max_in_flight: Option<usize>,
 }
 
 impl ParallelismOptions {
+    /// Uses `n_workers` workers, or as many as there are cores available if fewer. The
+    /// default batch size follows the number of workers actually used.
     pub fn new(n_workers: NonZero<usize>) -> Self {
+        let requested = n_workers.get();
         let n_workers = Self::check_n_workers(n_workers);
-        Self { n_workers, batch_size: n_workers * 2 }
+        Self {
+            requested,
+            n_workers,
+            batch_size: n_workers * 2,
+            batch_bounds: None,
+            max_in_flight: None,
+        }
     }
 
+    /// Number of workers that will be used.
     pub fn n_workers(&self) -> usize {
         self.n_workers
     }
 
+    /// Number of workers asked for, which may be more than `n_workers` if there aren't
+    /// enough cores available.
+    pub fn requested(&self) -> usize {
+        self.requested
+    }
+
+    /// Whether fewer workers will be used than requested.
+    pub fn is_clamped(&self) -> bool {
+        self.n_workers < self.requested
+    }
+
+    /// Uses as many workers as there are cores available.
+    pub fn auto() -> Self {
+        Self::new(Self::available_workers())
+    }
+
+    /// Number of threads that can run in parallel, or 1 if that can't be determined.
+    pub fn available_workers() -> NonZero<usize> {
+        thread::available_parallelism().unwrap_or_else(|_| NonZero::new(1).unwrap())
+    }
+
     fn check_n_workers(n_workers: NonZero<usize>) -> usize {
-        let av_workers =
-            thread::available_parallelism().unwrap_or_else(|_| NonZero::new(1).unwrap());
-        n_workers.min(av_workers).get()
+        n_workers.min(Self::available_workers()).get()
     }
 
     pub fn set_batch_size(&mut self, batch_size: Option<NonZero<usize>>) {
@@ -57,6 +92,46 @@ impl ParallelismOptions {
         self.set_batch_size(batch_size);
         self
     }
+
+    /// Lets the batch size change while running, between `min` and `max` cards. The batch
+    /// grows when workers find the queue empty, and shrinks when the reader finds it full,
+    /// so that workers are kept fed without holding more cards than needed. The batch size
+    /// set otherwise is used to start with.
+    pub fn set_adaptive_batch(&mut self, bounds: Option<(NonZero<usize>, NonZero<usize>)>) {
+        self.batch_bounds = bounds.map(|(min, max)| (min.get(), max.max(min).get()));
+    }
+
+    pub fn with_adaptive_batch(
+        mut self,
+        bounds: Option<(NonZero<usize>, NonZero<usize>)>,
+    ) -> Self {
+        self.set_adaptive_batch(bounds);
+        self
+    }
+
+    /// Minimum and maximum batch size, which are the same unless the batch is adaptive.
+    fn batch_bounds(&self) -> (usize, usize) {
+        self.batch_bounds.unwrap_or((self.batch_size, self.batch_size))
+    }
+
+    /// Maximum number of cards held in memory at a time, counting both the ones waiting in
+    /// the queue and the ones being processed by workers. By default, that is the largest
+    /// batch size plus the number of workers.
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+            .unwrap_or(self.batch_bounds().1 + self.n_workers)
+    }
+
+    pub fn set_max_in_flight(&mut self, max_in_flight: Option<NonZero<usize>>) {
+        if let Some(max_in_flight) = max_in_flight {
+            self.max_in_flight = Some(max_in_flight.get());
+        }
+    }
+
+    pub fn with_max_in_flight(mut self, max_in_flight: Option<NonZero<usize>>) -> Self {
+        self.set_max_in_flight(max_in_flight);
+        self
+    }
 }
 
 impl<C, T, V> Pipeline<C, T, V>
@@ -74,11 +149,13 @@ where
     ) -> Result<PipelineJoinHandle<C, T, V>> {
         let nw = opt.n_workers;
         let batch = opt.batch_size;
+        let fail_fast = self.fail_fast;
+        let bounds = opt.batch_bounds();
 
         let template = Arc::new(RwLock::new(self.template));
         let visitor = self.visitor;
-        let queue = Arc::new(CardQueue::<C>::new(batch));
-        let img_backend = Arc::new(RwLock::new(ImgBackend::new()?));
+        let queue = Arc::new(CardQueue::<C>::new(batch, bounds, opt.max_in_flight(), nw));
+        let img_backend = ImgBackend::shared()?;
 
         let handle = {
             let template = template.clone();
@@ -87,23 +164,13 @@ where
 
             thread::spawn(move || {
                 let template = lock!(read "template" template);
-                let mut source = template.source(source_key)?;
-                visitor.on_start(&*template, 0);
-
-                let mut total: usize = 0;
-                let cards_iter = source
-                    .read(filter)?
-                    .filter(|card_res| visitor.on_read(&*template, card_res));
-                for (i, card) in cards_iter.enumerate() {
-                    total += 1;
-                    match card {
-                        Ok(card) => queue.push(i, card)?,
-                        Err(e) => visitor.on_read_err(&*template, i, e),
-                    }
-                }
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    Self::read_cards(&*template, &visitor, &queue, &opt, source_key, filter)
+                }))
+                .unwrap_or_else(|e| Err(Error::worker_panic(0, e)));
+                // workers must be released even if reading failed
                 queue.done()?;
-                visitor.on_total(&*template, total);
-                Ok(())
+                result
             })
         };
         let mut workers = Vec::with_capacity(nw + 1);
@@ -113,26 +180,62 @@ where
             let queue = queue.clone();
             let template = template.clone();
             let visitor = visitor.clone();
-            let img_backend = img_backend.clone();
 
             let handle = thread::spawn(move || {
                 let template = lock!(read "template" template);
-                let img_backend = lock!(read "image backend" img_backend);
                 let worker = Worker {
                     id,
                     queue,
                     template: &*template,
                     visitor: &visitor,
-                    img_backend: &*img_backend,
+                    img_backend,
+                    busy: Cell::new(false),
+                    fail_fast,
                 };
-                let result = worker.run();
+                let result = panic::catch_unwind(AssertUnwindSafe(|| worker.run()))
+                    .unwrap_or_else(|e| Err(Error::worker_panic(id, e)));
+                // the reader and other workers must know this worker is gone, even if it panicked
+                let result = worker.queue.leave(worker.busy.get()).and(result);
                 visitor.on_finish(&*template, id, &result);
                 result
             });
             workers.push(handle);
         }
 
-        Ok(PipelineJoinHandle::new(template, visitor, workers))
+        Ok(PipelineJoinHandle::new(template, visitor, workers, queue))
+    }
+
+    fn read_cards(
+        template: &T,
+        visitor: &V,
+        queue: &CardQueue<C>,
+        opt: &ParallelismOptions,
+        source_key: T::SourceKey,
+        filter: Option<Predicate>,
+    ) -> Result<()> {
+        let mut source = template.source(source_key)?;
+        visitor.on_start(template, 0);
+        visitor.on_parallelism(template, opt);
+        if let Some(total) = source.count(filter.as_ref()) {
+            visitor.on_total(template, total);
+        }
+
+        let mut total: usize = 0;
+        let cards_iter = source
+            .read(filter)?
+            .filter(|card_res| visitor.on_read(template, card_res));
+        for (i, card) in cards_iter.enumerate() {
+            total += 1;
+            match card {
+                Ok(card) => {
+                    let (queued, in_flight) = queue.push(i, card)?;
+                    visitor.on_queue(template, queued, in_flight);
+                }
+                Err(e) => visitor.on_read_err(template, i, e),
+            }
+        }
+        visitor.on_total(template, total);
+        Ok(())
     }
 }
 
@@ -140,7 +243,7 @@ pub struct PipelineJoinHandle<C: Card, T: Template<C>, V: Visitor<C, T> = ()> {
     template: Arc<RwLock<T>>,
     visitor: V,
     handles: Vec<JoinHandle<Result<()>>>,
-    _marker: PhantomData<C>,
+    queue: Arc<CardQueue<C>>,
 }
 
 impl<C, T, V> PipelineJoinHandle<C, T, V>
@@ -149,86 +252,195 @@ where
     T: Template<C>,
     V: Visitor<C, T>,
 {
-    fn new(template: Arc<RwLock<T>>, visitor: V, handles: Vec<JoinHandle<Result<()>>>) -> Self {
-        Self { template, visitor, handles, _marker: PhantomData }
+    fn new(
+        template: Arc<RwLock<T>>,
+        visitor: V,
+        handles: Vec<JoinHandle<Result<()>>>,
+        queue: Arc<CardQueue<C>>,
+    ) -> Self {
+        Self { template, visitor, handles, queue }
     }
 
+    /// Waits for every thread to finish. Fails if a thread can't be joined or, when the
+    /// pipeline was set to fail fast, with the error of the card that stopped it.
     pub fn join(self) -> Result<(T, V)> {
         let visitor = self.visitor;
 
-        let mut handles = self.handles.into_iter().enumerate();
-
-        let (i, handle) = handles.next().expect("at least 1 join handle should exist");
-        let base_result = handle.join().map_err(|_| Error::thread_join(i))?;
-
-        for (i, handle) in handles {
-            let _ = handle.join().map_err(|_| Error::thread_join(i))?;
-        }
+        // every thread is joined before reporting errors, so that none is left detached
+        let results: Vec<_> = self
+            .handles
+            .into_iter()
+            .enumerate()
+            .map(|(i, handle)| handle.join().map_err(|e| Error::thread_join(i, e)))
+            .collect();
+        // the reader may have finished before a card failed, so the failure is taken from
+        // the queue first
+        let result = match self.queue.failure() {
+            Ok(Some(e)) => Err(e),
+            Ok(None) => results.into_iter().try_for_each(|joined| joined.and_then(|r| r)),
+            Err(e) => Err(e),
+        };
 
+        // threads only read the template, which can't poison its lock
         let template = Arc::into_inner(self.template)
             .expect("all handles should have been joined")
             .into_inner()
-            .map_err(|e| Error::read_lock("template", e))?;
-        visitor.on_finish(&template, 0, &base_result);
+            .unwrap_or_else(PoisonError::into_inner);
+        visitor.on_finish(&template, 0, &result);
 
-        Ok((template, visitor))
+        result.map(|_| (template, visitor))
     }
 }
 
 struct CardQueue<C: Card> {
     queue: Mutex<CardQueueState<C>>,
-    capacity: usize,
+    /// Bounds of the capacity, which is only adjusted if they differ.
+    bounds: (usize, usize),
+    max_in_flight: usize,
     cond: Condvar,
 }
 
 struct CardQueueState<C: Card> {
     queue: VecDeque<(usize, C)>,
+    capacity: usize,
+    in_flight: usize,
+    workers: usize,
     done: bool,
+    /// Whether any card was queued yet, as workers find the queue empty until then only
+    /// because the source is still being opened.
+    started: bool,
+    /// Error of the card that stopped a pipeline set to fail fast.
+    failure: Option<Error>,
 }
 
 impl<C: Card> CardQueueState<C> {
-    fn new(capacity: usize) -> Self {
-        Self { queue: VecDeque::with_capacity(capacity), done: false }
+    fn new(capacity: usize, workers: usize) -> Self {
+        Self {
+            queue: VecDeque::with_capacity(capacity),
+            capacity,
+            in_flight: 0,
+            workers,
+            done: false,
+            started: false,
+            failure: None,
+        }
     }
 }
 
 impl<C: Card> CardQueue<C> {
-    fn new(capacity: usize) -> Self {
+    fn new(
+        capacity: usize,
+        bounds: (usize, usize),
+        max_in_flight: usize,
+        workers: usize,
+    ) -> Self {
+        let capacity = capacity.clamp(bounds.0, bounds.1);
         Self {
-            queue: Mutex::new(CardQueueState::new(capacity)),
-            capacity,
+            queue: Mutex::new(CardQueueState::new(capacity, workers)),
+            bounds,
+            max_in_flight,
             cond: Condvar::new(),
         }
     }
 
-    fn push(&self, index: usize, card: C) -> Result<()> {
-        let state = lock!("card queue" self.queue);
+    /// Pushes a card, waiting while the queue is full or too many cards are being processed.
+    /// Returns the number of queued cards and cards being processed after the push, or an
+    /// error if there are no workers left to process it, or the error that stopped the
+    /// pipeline if it failed fast.
+    ///
+    /// Finding the queue full means workers take cards slower than they are read, so the
+    /// capacity is decreased, if adaptive.
+    fn push(&self, index: usize, card: C) -> Result<(usize, usize)> {
+        let mut state = lock!("card queue" self.queue);
+        if state.queue.len() >= state.capacity && state.capacity > self.bounds.0 {
+            state.capacity -= 1;
+        }
         let mut state = self
             .cond
-            .wait_while(state, |s| s.queue.len() >= self.capacity)
+            .wait_while(state, |s| {
+                s.workers > 0
+                    && s.failure.is_none()
+                    && (s.queue.len() >= s.capacity
+                        || s.queue.len() + s.in_flight >= self.max_in_flight)
+            })
             .map_err(|e| Error::mutex_lock("card queue", e))?;
+        if let Some(e) = &state.failure {
+            return Err(e.clone());
+        }
+        if state.workers == 0 {
+            return Err(Error::no_workers());
+        }
         state.queue.push_back((index, card));
-        self.cond.notify_one();
-        Ok(())
+        state.started = true;
+        self.cond.notify_all();
+        Ok((state.queue.len(), state.in_flight))
     }
 
+    /// Pops a card, which counts as being processed until `finish` is called.
+    ///
+    /// Finding the queue empty means workers are starved, so the capacity is doubled, if
+    /// adaptive, which also wakes the reader if it was waiting for room in the queue. Workers
+    /// waiting for the first card are not starved, so the capacity is kept until then.
     fn pop(&self) -> Result<Option<(usize, C)>> {
-        let state = lock!("card queue" self.queue);
+        let mut state = lock!("card queue" self.queue);
+        let starved = state.queue.is_empty() && state.started && !state.done;
+        if starved && state.capacity < self.bounds.1 {
+            state.capacity = (state.capacity * 2).min(self.bounds.1);
+            self.cond.notify_all();
+        }
         let mut state = self
             .cond
             .wait_while(state, |s| s.queue.is_empty() && !s.done)
             .map_err(|e| Error::mutex_lock("card queue", e))?;
         let card = state.queue.pop_front();
+        if card.is_some() {
+            state.in_flight += 1;
+        }
         self.cond.notify_all();
         Ok(card)
     }
 
+    fn finish(&self) -> Result<()> {
+        let mut state = lock!("card queue" self.queue);
+        state.in_flight -= 1;
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    /// Signals that a worker stopped, releasing the card it was processing, if any.
+    fn leave(&self, busy: bool) -> Result<()> {
+        let mut state = lock!("card queue" self.queue);
+        state.workers -= 1;
+        if busy {
+            state.in_flight -= 1;
+        }
+        self.cond.notify_all();
+        Ok(())
+    }
+
     fn done(&self) -> Result<()> {
         let mut state = lock!("card queue" self.queue);
         state.done = true;
         self.cond.notify_all();
         Ok(())
     }
+
+    /// Stops the pipeline because a card failed, dropping the cards still queued so that
+    /// workers stop once done with the ones they hold. Only the first failure is kept.
+    fn fail(&self, error: Error) -> Result<()> {
+        let mut state = lock!("card queue" self.queue);
+        if state.failure.is_none() {
+            state.failure = Some(error);
+        }
+        state.done = true;
+        state.queue.clear();
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    fn failure(&self) -> Result<Option<Error>> {
+        Ok(lock!("card queue" self.queue).failure.clone())
+    }
 }
 
 struct Worker<'a, C: Card, T: Template<C>, V: Visitor<C, T>> {
@@ -237,30 +449,240 @@ struct Worker<'a, C: Card, T: Template<C>, V: Visitor<C, T>> {
     pub template: &'a T,
     pub img_backend: &'a ImgBackend,
     pub visitor: &'a V,
+    pub busy: Cell<bool>,
+    pub fail_fast: bool,
 }
 
 impl<'a, C: Card + Send, T: Template<C>, V: Visitor<C, T>> Worker<'a, C, T, V> {
     fn run(&self) -> Result<()> {
         let ctx = RenderContext {
             img_map: self.template.resources(),
+            options: self.template.render_options(),
             font_map: self.template.fonts(),
             backend: self.img_backend,
+            warnings: Default::default(),
+            layer_times: Default::default(),
+            outputs: Default::default(),
         };
+        // a decoder that can't be created fails for every card, so it stops the worker
         let decoder = self.template.decoder()?;
         while let Some((i, card)) = self.queue.pop()? {
+            self.busy.set(true);
             self.visitor.on_iter_start(self.template, self.id, i, &card);
-            match self.process(&decoder, &card, &ctx) {
-                Ok(()) => self.visitor.on_iter_ok(self.template, self.id, i, card),
+            let start = Instant::now();
+            let result = process_card(|| self.process(&decoder, &card, &ctx));
+            let layer_times = ctx.layer_times.take();
+            self.visitor
+                .on_iter_times(self.template, self.id, i, &card, start.elapsed(), &layer_times);
+            for warning in ctx.warnings.take() {
+                self.visitor.on_iter_warn(self.template, self.id, i, &card, &warning);
+            }
+            let outputs = ctx.outputs.take();
+            match result {
+                Ok(Processed::Rendered(path)) => {
+                    for (output, size) in outputs {
+                        self.visitor.on_output(self.template, self.id, i, &card, &output, size);
+                    }
+                    self.visitor.on_iter_ok(self.template, self.id, i, card, path)
+                }
+                Ok(Processed::Skipped(path)) => {
+                    self.visitor.on_iter_skip(self.template, self.id, i, card, path)
+                }
+                Err(e) if self.fail_fast => {
+                    let id = self.template.identify(&card);
+                    self.visitor.on_iter_err(self.template, self.id, i, card, e.clone());
+                    self.queue.fail(Error::fail_fast(id, e))?;
+                }
                 Err(e) => self.visitor.on_iter_err(self.template, self.id, i, card, e),
             }
+            self.queue.finish()?;
+            self.busy.set(false);
         }
         Ok(())
     }
 
-    fn process(&self, decoder: &T::Decoder, card: &C, ctx: &RenderContext) -> Result<()> {
+    fn process(&self, decoder: &T::Decoder, card: &C, ctx: &RenderContext) -> Result<Processed> {
+        if let Some(path) = self.template.rendered(card) {
+            return Ok(Processed::Skipped(path));
+        }
         let layers = decoder.decode(card)?;
-        let img = layers.render(ctx)?;
-        self.template.output(card, &img, &ctx.backend)?;
+        self.template.render(card, layers, ctx).map(Processed::Rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{DataSource, Value};
+    use crate::image::{Color, ImageMap};
+    use crate::layer::LayerStack;
+    use crate::text::FontMap;
+
+    use libvips::VipsImage;
+    use serde::Deserialize;
+    use std::path::PathBuf;
+
+    fn write(lock: &RwLock<i32>, value: i32) -> Result<()> {
+        *lock!(write "value" lock) = value;
         Ok(())
     }
+
+    fn read(lock: &RwLock<i32>) -> Result<i32> {
+        Ok(*lock!(read "value" lock))
+    }
+
+    #[test]
+    fn write_lock_is_exclusive() {
+        let lock = RwLock::new(0);
+        let exclusive = || -> Result<bool> {
+            let _guard = lock!(write "value" lock);
+            Ok(lock.try_read().is_err())
+        };
+        assert!(exclusive().unwrap());
+        write(&lock, 1).unwrap();
+        assert_eq!(read(&lock).unwrap(), 1);
+    }
+
+    #[test]
+    fn poisoned_locks_fail() {
+        let lock = Arc::new(RwLock::new(0));
+        let mutex = Arc::new(Mutex::new(0));
+        let (poisoned_lock, poisoned_mutex) = (lock.clone(), mutex.clone());
+        let _ = thread::spawn(move || {
+            let _write = poisoned_lock.write().unwrap();
+            let _lock = poisoned_mutex.lock().unwrap();
+            panic!("poisoning the locks");
+        })
+        .join();
+
+        let err = write(&lock, 1).unwrap_err();
+        assert!(matches!(err, Error::WriteLock { variable: "value", .. }), "{err:?}");
+        let err = read(&lock).unwrap_err();
+        assert!(matches!(err, Error::ReadLock { variable: "value", .. }), "{err:?}");
+        let lock_mutex = || -> Result<i32> { Ok(*lock!("value" mutex)) };
+        let err = lock_mutex().unwrap_err();
+        assert!(matches!(err, Error::MutexLock { variable: "value", .. }), "{err:?}");
+    }
+
+    #[derive(Deserialize)]
+    struct NumberCard(i64);
+
+    impl Card for NumberCard {
+        fn get(&self, _field: &str) -> Value {
+            Value::Int(self.0)
+        }
+    }
+
+    struct Numbers(i64);
+
+    impl DataSource<NumberCard> for Numbers {
+        fn read(
+            &mut self,
+            _filter: Option<Predicate>,
+        ) -> Result<Box<dyn Iterator<Item = Result<NumberCard>> + '_>> {
+            Ok(Box::new((0..self.0).map(|i| Ok(NumberCard(i)))))
+        }
+    }
+
+    /// Fails to decode a single card, and renders nothing for the others.
+    #[derive(Debug)]
+    struct FailingTemplate {
+        fails: i64,
+        img_map: ImageMap,
+        font_map: FontMap,
+    }
+
+    struct FailingDecoder(i64);
+
+    impl Decoder<NumberCard> for FailingDecoder {
+        fn decode(&self, card: &NumberCard) -> Result<LayerStack<'_>> {
+            if card.0 == self.0 {
+                return Err(Error::unknown());
+            }
+            Ok(LayerStack::new(Vec::new()))
+        }
+    }
+
+    impl Template<NumberCard> for FailingTemplate {
+        type SourceKey = i64;
+        type Decoder = FailingDecoder;
+
+        fn source(&self, key: i64) -> Result<Box<dyn DataSource<NumberCard>>> {
+            Ok(Box::new(Numbers(key)))
+        }
+
+        fn decoder(&self) -> Result<FailingDecoder> {
+            Ok(FailingDecoder(self.fails))
+        }
+
+        fn resources(&self) -> &ImageMap {
+            &self.img_map
+        }
+
+        fn fonts(&self) -> &FontMap {
+            &self.font_map
+        }
+
+        fn output(&self, _: &NumberCard, _: &VipsImage, _: &ImgBackend) -> Result<PathBuf> {
+            unreachable!("cards are not rendered")
+        }
+
+        fn render(&self, card: &NumberCard, _: LayerStack, _: &RenderContext) -> Result<PathBuf> {
+            Ok(PathBuf::from(card.id()))
+        }
+    }
+
+    /// Worker that finished, along with the error it finished with, if any.
+    type Finished = (usize, Option<Error>);
+
+    /// Records the cards that failed and the error each thread finished with, if any.
+    #[derive(Debug, Clone, Default)]
+    struct Recorder {
+        failed: Arc<Mutex<Vec<usize>>>,
+        finished: Arc<Mutex<Vec<Finished>>>,
+    }
+
+    impl<T: Template<NumberCard>> Visitor<NumberCard, T> for Recorder {
+        fn on_iter_err(&self, _: &T, _: usize, i: usize, _: NumberCard, _: Error) {
+            self.failed.lock().unwrap().push(i);
+        }
+
+        fn on_finish(&self, _: &T, worker: usize, result: &Result<()>) {
+            self.finished.lock().unwrap().push((worker, result.clone().err()));
+        }
+    }
+
+    #[test]
+    fn fail_fast_reports_the_failure_when_finishing() {
+        let template = FailingTemplate {
+            fails: 3,
+            img_map: ImageMap {
+                assets_folder: PathBuf::from("assets"),
+                artwork_folder: PathBuf::from("artwork"),
+                extensions: vec![String::from("png")],
+                placeholder: None,
+                card_size: (10, 10),
+                background: Color::WHITE,
+            },
+            font_map: FontMap::new().unwrap(),
+        };
+        let recorder = Recorder::default();
+        let opt = ParallelismOptions::new(NonZero::new(2).unwrap());
+        let err = Pipeline::new(template, recorder.clone())
+            .with_fail_fast(true)
+            .run_parallel(100, None, opt)
+            .unwrap()
+            .join()
+            .unwrap_err();
+
+        let expected = Error::fail_fast("3", Error::unknown());
+        assert_eq!(err, expected);
+        assert_eq!(*recorder.failed.lock().unwrap(), [3]);
+        let finished = recorder.finished.lock().unwrap();
+        let mut workers: Vec<_> = finished.iter().map(|(worker, _)| *worker).collect();
+        workers.sort();
+        assert_eq!(workers, (0..=opt.n_workers()).collect::<Vec<_>>());
+        // the last to finish is the pipeline itself, with the failure that stopped it
+        assert_eq!(finished.last(), Some(&(0, Some(expected))));
+    }
 }
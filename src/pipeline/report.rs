@@ -0,0 +1,89 @@
+use crate::data::Card;
+use crate::error::{Error, Result};
+use crate::pipeline::Visitor;
+use crate::template::Template;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// Time spent on a card, keyed by its index in a [`ReportVisitor`].
+#[derive(Debug, Clone)]
+struct CardReport {
+    id: String,
+    total: Duration,
+    layers: Vec<(usize, &'static str, Duration)>,
+}
+
+/// Collects the time spent on each card and each of its layers, to find which are slow.
+///
+/// Clones share what is collected, so that each worker of a parallel pipeline can hold its
+/// own. Once the pipeline is done, the report is written with [`ReportVisitor::write_csv`].
+#[derive(Debug, Clone, Default)]
+pub struct ReportVisitor {
+    cards: Arc<Mutex<BTreeMap<usize, CardReport>>>,
+}
+
+impl ReportVisitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the report as CSV, with a row for each card followed by a row for each of its
+    /// layers, in the order the cards were read. Card rows have an empty layer position and
+    /// the kind `card`. Times are in milliseconds.
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(path).map_err(Error::io_error)?;
+        let mut w = BufWriter::new(file);
+        self.write_csv_to(&mut w)
+            .and_then(|_| w.flush())
+            .map_err(Error::io_error)
+    }
+
+    fn write_csv_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+        let cards = self.cards.lock().unwrap_or_else(PoisonError::into_inner);
+        writeln!(w, "index,card,layer,kind,ms")?;
+        for (i, card) in cards.iter() {
+            let id = csv_field(&card.id);
+            writeln!(w, "{i},{id},,card,{:.3}", millis(card.total))?;
+            for (layer, kind, time) in &card.layers {
+                writeln!(w, "{i},{id},{layer},{kind},{:.3}", millis(*time))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C: Card, T: Template<C>> Visitor<C, T> for ReportVisitor {
+    fn on_iter_times(
+        &self,
+        template: &T,
+        _worker: usize,
+        i: usize,
+        card: &C,
+        total: Duration,
+        layers: &[(usize, &'static str, Duration)],
+    ) {
+        let report = CardReport { id: template.identify(card), total, layers: layers.to_vec() };
+        self.cards
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(i, report);
+    }
+}
+
+fn millis(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
@@ -1,11 +1,13 @@
 use crate::data::{Card, Predicate};
 use crate::decode::Decoder;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::image::ImgBackend;
 use crate::layer::RenderContext;
-use crate::pipeline::{Pipeline, Visitor};
+use crate::pipeline::{process_card, Pipeline, Processed, Visitor};
 use crate::template::Template;
 
+use std::time::Instant;
+
 impl<C, T, V> Pipeline<C, T, V>
 where
     C: Card,
@@ -13,11 +15,55 @@ where
     V: Visitor<C, T>,
 {
     pub fn run(self, source_key: T::SourceKey, filter: Option<Predicate>) -> (T, V) {
+        let (template, visitor, _) = self.run_with(source_key, filter, false);
+        (template, visitor)
+    }
+
+    /// Like `run`, but creates a new decoder for each card, so that changes made to the
+    /// decoder while running, e.g. edits to a script, are used by the next card.
+    pub fn run_reloading(self, source_key: T::SourceKey, filter: Option<Predicate>) -> (T, V) {
+        let (template, visitor, _) = self.run_with(source_key, filter, true);
+        (template, visitor)
+    }
+
+    /// Like `run`, but fails with the error of the card that stopped the pipeline, when set
+    /// to [fail fast](Self::set_fail_fast). Other errors are only reported to the visitor.
+    pub fn try_run(self, source_key: T::SourceKey, filter: Option<Predicate>) -> Result<(T, V)> {
+        match self.run_with(source_key, filter, false) {
+            (_, _, Some(e)) => Err(e),
+            (template, visitor, None) => Ok((template, visitor)),
+        }
+    }
+
+    /// Like `run_reloading`, but fails the same as [`try_run`](Self::try_run).
+    pub fn try_run_reloading(
+        self,
+        source_key: T::SourceKey,
+        filter: Option<Predicate>,
+    ) -> Result<(T, V)> {
+        match self.run_with(source_key, filter, true) {
+            (_, _, Some(e)) => Err(e),
+            (template, visitor, None) => Ok((template, visitor)),
+        }
+    }
+
+    /// Runs the pipeline, returning the error that stopped it when set to fail fast.
+    fn run_with(
+        self,
+        source_key: T::SourceKey,
+        filter: Option<Predicate>,
+        reload: bool,
+    ) -> (T, V, Option<Error>) {
         let template = self.template;
         let visitor = self.visitor;
-        let result = Self::run_internal(&template, &visitor, source_key, filter);
+        let result =
+            Self::run_internal(&template, &visitor, source_key, filter, reload, self.fail_fast);
         visitor.on_finish(&template, 0, &result);
-        (template, visitor)
+        let failure = match result {
+            Err(e @ Error::FailFast { .. }) => Some(e),
+            _ => None,
+        };
+        (template, visitor, failure)
     }
 
     fn run_internal(
@@ -25,15 +71,30 @@ where
         visitor: &V,
         source_key: T::SourceKey,
         filter: Option<Predicate>,
+        reload: bool,
+        fail_fast: bool,
     ) -> Result<()> {
         visitor.on_start(&template, 0);
         let mut source = template.source(source_key)?;
-        let decoder = template.decoder()?;
+        if let Some(total) = source.count(filter.as_ref()) {
+            visitor.on_total(template, total);
+        }
+        // when reloading, a decoder is created for each card instead
+        let decoder = if reload { None } else { Some(template.decoder()?) };
         let font_map = template.fonts();
         let img_map = template.resources();
-        let backend = ImgBackend::new()?;
-        let ctx = RenderContext { backend: &backend, font_map, img_map };
-        source
+        let options = template.render_options();
+        let backend = ImgBackend::shared()?;
+        let ctx = RenderContext {
+            backend,
+            font_map,
+            img_map,
+            options,
+            warnings: Default::default(),
+            layer_times: Default::default(),
+            outputs: Default::default(),
+        };
+        let result = source
             .read(filter)?
             .filter(|card_res| visitor.on_read(template, card_res))
             .enumerate()
@@ -44,20 +105,57 @@ where
                     None
                 }
             })
-            .for_each(|(i, card)| {
+            .try_for_each(|(i, card)| {
                 visitor.on_iter_start(template, 0, i, &card);
-                match Self::process(&template, &decoder, &card, &ctx) {
-                    Ok(()) => visitor.on_iter_ok(template, 0, i, card),
-                    Err(e) => visitor.on_iter_err(template, 0, i, card, e),
+                let start = Instant::now();
+                let result = process_card(|| match &decoder {
+                    Some(decoder) => Self::process(template, decoder, &card, &ctx),
+                    None => template
+                        .decoder()
+                        .and_then(|decoder| Self::process(template, &decoder, &card, &ctx)),
+                });
+                let layer_times = ctx.layer_times.take();
+                visitor.on_iter_times(template, 0, i, &card, start.elapsed(), &layer_times);
+                for warning in ctx.warnings.take() {
+                    visitor.on_iter_warn(template, 0, i, &card, &warning);
+                }
+                let outputs = ctx.outputs.take();
+                match result {
+                    Ok(Processed::Rendered(path)) => {
+                        for (output, size) in outputs {
+                            visitor.on_output(template, 0, i, &card, &output, size);
+                        }
+                        visitor.on_iter_ok(template, 0, i, card, path);
+                        Ok(())
+                    }
+                    Ok(Processed::Skipped(path)) => {
+                        visitor.on_iter_skip(template, 0, i, card, path);
+                        Ok(())
+                    }
+                    Err(e) if fail_fast => {
+                        let id = template.identify(&card);
+                        visitor.on_iter_err(template, 0, i, card, e.clone());
+                        Err(Error::fail_fast(id, e))
+                    }
+                    Err(e) => {
+                        visitor.on_iter_err(template, 0, i, card, e);
+                        Ok(())
+                    }
                 }
             });
-        Ok(())
+        result
     }
 
-    fn process(template: &T, decoder: &T::Decoder, card: &C, ctx: &RenderContext) -> Result<()> {
+    fn process(
+        template: &T,
+        decoder: &T::Decoder,
+        card: &C,
+        ctx: &RenderContext,
+    ) -> Result<Processed> {
+        if let Some(path) = template.rendered(card) {
+            return Ok(Processed::Skipped(path));
+        }
         let layers = decoder.decode(card)?;
-        let img = layers.render(ctx)?;
-        template.output(card, &img, &ctx.backend)?;
-        Ok(())
+        template.render(card, layers, ctx).map(Processed::Rendered)
     }
 }
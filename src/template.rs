@@ -1,10 +1,13 @@
-use crate::data::{Card, DataSource};
+use crate::data::{identify, Card, DataSource};
 use crate::decode::Decoder;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::image::{ImageMap, ImgBackend};
+use crate::layer::{LayerStack, RenderContext, RenderOptions};
 use crate::text::FontMap;
 
 use libvips::VipsImage;
+use std::path::PathBuf;
+use std::sync::OnceLock;
 
 pub trait Template<C: Card> {
     type SourceKey;
@@ -15,9 +18,79 @@ pub trait Template<C: Card> {
     }
 
     fn source(&self, key: Self::SourceKey) -> Result<Box<dyn DataSource<C>>>;
-    fn identify(&self, card: &C) -> String;
+
+    /// Pattern used to identify cards, e.g. `{set}-{number}`, where each `{field}` is
+    /// replaced with the value of that field. Without one, cards are identified by
+    /// [`Card::id`].
+    fn identity(&self) -> Option<&str> {
+        None
+    }
+
+    fn identify(&self, card: &C) -> String {
+        match self.identity() {
+            Some(pattern) => identify(pattern, card),
+            None => card.id(),
+        }
+    }
+
     fn decoder(&self) -> Result<Self::Decoder>;
     fn resources(&self) -> &ImageMap;
     fn fonts(&self) -> &FontMap;
-    fn output(&self, card: &C, img: &VipsImage, ib: &ImgBackend) -> Result<()>;
+
+    /// Settings applied to every card rendered, such as print margins. By default, none is set.
+    fn render_options(&self) -> &RenderOptions {
+        static DEFAULT: OnceLock<RenderOptions> = OnceLock::new();
+        DEFAULT.get_or_init(RenderOptions::default)
+    }
+
+    /// Writes the rendered card image, returning the path it was written to.
+    fn output(&self, card: &C, img: &VipsImage, ib: &ImgBackend) -> Result<PathBuf>;
+
+    /// Returns the path of an output rendered earlier for a card, if it is still up to date,
+    /// so that the card is skipped and reported through [`Visitor::on_iter_skip`]. By
+    /// default, every card is rendered.
+    fn rendered(&self, _card: &C) -> Option<PathBuf> {
+        None
+    }
+
+    /// Renders the decoded layers of a card and writes the result, returning the path it was
+    /// written to. By default, renders a raster image and writes it with `output`, but
+    /// templates can override it to render to other formats.
+    ///
+    /// Each file written is pushed to `ctx.outputs` along with the size of the image written,
+    /// so that visitors are told of it through [`Visitor::on_output`].
+    ///
+    /// [`Visitor::on_output`]: crate::pipeline::Visitor::on_output
+    /// [`Visitor::on_iter_skip`]: crate::pipeline::Visitor::on_iter_skip
+    fn render(&self, card: &C, layers: LayerStack, ctx: &RenderContext) -> Result<PathBuf> {
+        let img = layers.render_with(ctx, self.dump_folder(card, ctx).as_deref())?;
+        let path = self.output(card, &img, ctx.backend)?;
+        ctx.outputs.push((path.clone(), (img.get_width(), img.get_height())));
+        Ok(path)
+    }
+
+    /// Decodes a card, e.g. the first one of a source, and checks that the images and fonts
+    /// referred to by its layers exist, so that problems are found before a long run. Returns
+    /// every problem found, along with the position and kind of the layer it was found in, or
+    /// an error if the card can't be decoded at all.
+    fn validate(&self, card: &C) -> Result<Vec<(usize, &'static str, Error)>> {
+        let ctx = RenderContext {
+            backend: ImgBackend::shared()?,
+            font_map: self.fonts(),
+            img_map: self.resources(),
+            options: self.render_options(),
+            warnings: Default::default(),
+            layer_times: Default::default(),
+            outputs: Default::default(),
+        };
+        let decoder = self.decoder()?;
+        let layers = decoder.decode(card)?;
+        Ok(layers.validate(&ctx))
+    }
+
+    /// Folder where a card is written after each layer, if `dump_layers` is set.
+    fn dump_folder(&self, card: &C, ctx: &RenderContext) -> Option<PathBuf> {
+        let dir = ctx.options.dump_layers.as_ref()?;
+        Some(dir.join(self.identify(card)))
+    }
 }
@@ -3,8 +3,11 @@
 pub mod attr;
 mod font;
 mod markup;
+mod number;
 mod parser;
 
+pub(crate) use font::FONT_FILES_ADDED;
 pub use font::{FontMap, FontPath};
 pub use markup::Markup;
+pub use number::NumberFormat;
 pub use parser::{escape, unescape};
@@ -1,7 +1,7 @@
 //! Text attribute values and conversions.
 
 use crate::error::{Error, Result};
-use crate::image::{Color, ImageMap, ImgBackend, Origin};
+use crate::image::{Color, ImgBackend, Origin, TextResources};
 use crate::text::FontMap;
 
 use libvips::VipsImage;
@@ -29,14 +29,13 @@ impl ITagAttr {
     pub fn vec_to_pango(
         attrs: Vec<ITagAttr>,
         ib: &ImgBackend,
-        im: &ImageMap,
-        fm: &FontMap,
+        res: TextResources,
         ctx: &pango::Context,
     ) -> Result<(pango::AttrList, Vec<Option<VipsImage>>)> {
         let mut attr_list = pango::AttrList::new();
         let mut images = Vec::new();
         for attr in attrs.into_iter() {
-            attr.to_pango(ib, im, fm, ctx, &mut attr_list, &mut images)?;
+            attr.to_pango(ib, res, ctx, &mut attr_list, &mut images)?;
         }
         Ok((attr_list, images))
     }
@@ -44,16 +43,18 @@ impl ITagAttr {
     pub fn to_pango(
         self,
         ib: &ImgBackend,
-        im: &ImageMap,
-        fm: &FontMap,
+        res: TextResources,
         ctx: &pango::Context,
         attr_list: &mut pango::AttrList,
         images: &mut Vec<Option<VipsImage>>,
     ) -> Result<()> {
         match self.value {
-            TagAttr::Span(a) => a.to_pango(fm, attr_list, self.start_index, self.end_index)?,
+            TagAttr::Span(a) => {
+                a.to_pango(res.font_map, attr_list, self.start_index, self.end_index)?
+            }
             TagAttr::Img(a) => {
-                let img = a.to_pango(ib, im, fm, ctx, attr_list, self.start_index, self.end_index);
+                let (i, j) = (self.start_index, self.end_index);
+                let img = a.to_pango(ib, res, ctx, attr_list, i, j);
                 images.push(img);
             }
         }
@@ -263,6 +264,7 @@ struct_attr! {
     #[derive(Debug, Clone, Default)]
     pub struct ImgAttr {
         "src"     => src: String,
+        "artwork" => artwork: String,
         "width"   => width: i32,
         "height"  => height: i32,
         "scale"   => scale: Scale,
@@ -300,6 +302,20 @@ impl ImgAttr {
         }
     }
 
+    /// Creates the attributes of an inline image, loaded from `src` in the assets folder,
+    /// which may omit the extension, e.g. `mana` for `mana.png`. In markup, images can also
+    /// be loaded from the artwork folder with `artwork` instead of `src`, e.g.
+    /// `<img artwork="42"/>`, found like the artwork of a layer.
+    pub fn with_src(src: impl Into<String>) -> Self {
+        Self { src: Some(src.into()), ..Self::default() }
+    }
+
+    /// Creates the attributes of an icon, an inline image that takes the color of the text
+    /// around it, loaded from `src` in the assets folder.
+    pub fn icon(src: impl Into<String>) -> Self {
+        Self { src: Some(src.into()), ..Self::new_inherit() }
+    }
+
     #[must_use]
     pub fn configured(
         mut self,
@@ -322,18 +338,22 @@ impl ImgAttr {
     pub fn to_pango(
         self,
         ib: &ImgBackend,
-        im: &ImageMap,
-        fm: &FontMap,
+        res: TextResources,
         ctx: &pango::Context,
         attrs: &mut pango::AttrList,
         i: u32,
         j: u32,
     ) -> Option<VipsImage> {
-        let fp = im.asset_path(self.src.as_ref()?);
+        let fp = match (&self.src, &self.artwork) {
+            (Some(src), _) => res.img_map.find_asset(src),
+            (None, Some(key)) => res.img_map.artwork_path(key).ok()?,
+            (None, None) => return None,
+        };
         let fp = &fp.to_string_lossy();
         let img = ib.open(fp).ok()?;
+        let img = if res.premultiplied_alpha { ib.unpremultiply(&img).ok()? } else { img };
         let img = rotate_img(ib, img, self.gravity.unwrap_or(Gravity::South))?;
-        let metrics = get_metrics(fm, ctx, self.font.as_ref()?, self.size?)?;
+        let metrics = get_metrics(res.font_map, ctx, self.font.as_ref()?, self.size?)?;
         let img = resize_img(ib, &img, &metrics, self.width, self.height, self.scale)?;
         let img = recolor_img(ib, img, self.color, self.alpha)?;
         push_img_rect(attrs, i, j, &img, &metrics);
@@ -417,6 +437,17 @@ fn push_img_rect(
     attrs.insert(indexed!(pango::AttrShape::new(&rect, &rect); at i, j));
 }
 
+/// Position in pixels of the top left corner of an inline image whose shape starts at a byte
+/// index of a layout.
+///
+/// The position of an index is at its leading edge, which in right-to-left text is its right
+/// side, so the image is placed from whichever edge is visually on the left.
+pub(crate) fn shape_position(layout: &pango::Layout, index: u32) -> (i32, i32) {
+    let rect = layout.index_to_pos(index as i32);
+    let x = rect.x().min(rect.x() + rect.width());
+    (x / pango::SCALE, rect.y() / pango::SCALE)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Points(pub i32);
 
@@ -654,18 +685,101 @@ impl FromStr for Segment {
 #[derive(Debug, Clone)]
 pub enum LayoutAttr<'a> {
     Alignment(Alignment),
+    Antialias(Antialias),
     AutoDir(bool),
     Dpi(f64),
     Direction(Direction),
     Gravity(Gravity),
     GravityHint(GravityHint),
+    HintMetrics(HintMetrics),
+    HintStyle(HintStyle),
     Indent(f64),
     Justify(bool),
+    JustifyLastLine(bool),
     Language(&'a str),
+    LetterSpacing(i32),
+    LineHeight(f64),
     LineSpacing(f64),
     Spacing(f64),
+    SubpixelOrder(SubpixelOrder),
     Width(i32),
     Wrap(WrapMode),
+    /// Maximum height in pixels, past which lines are dropped and the last one is ellipsized.
+    Height(i32),
+}
+
+macro_rules! into_cairo {
+    (
+        $(#[$outer:meta])*
+        $vis:vis enum $Enum:ident {
+            $( $Variant:ident ),*
+        }
+    ) => {
+        $(#[$outer])*
+        $vis enum $Enum {
+            $( $Variant ),*
+        }
+
+        impl From<$Enum> for cairo::$Enum {
+            fn from(value: $Enum) -> Self {
+                match value {
+                    $( $Enum::$Variant => Self::$Variant ),*
+                }
+            }
+        }
+    };
+}
+
+into_cairo! {
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "cli", derive(Deserialize, Serialize))]
+    #[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
+    pub enum Antialias {
+        Default,
+        None,
+        Gray,
+        Subpixel,
+        Fast,
+        Good,
+        Best
+    }
+}
+
+into_cairo! {
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "cli", derive(Deserialize, Serialize))]
+    #[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
+    pub enum HintStyle {
+        Default,
+        None,
+        Slight,
+        Medium,
+        Full
+    }
+}
+
+into_cairo! {
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "cli", derive(Deserialize, Serialize))]
+    #[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
+    pub enum HintMetrics {
+        Default,
+        Off,
+        On
+    }
+}
+
+into_cairo! {
+    #[derive(Debug, Copy, Clone)]
+    #[cfg_attr(feature = "cli", derive(Deserialize, Serialize))]
+    #[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
+    pub enum SubpixelOrder {
+        Default,
+        Rgb,
+        Bgr,
+        Vrgb,
+        Vbgr
+    }
 }
 
 into_pango! {
@@ -705,22 +819,135 @@ into_pango! {
     }
 }
 
+/// What to do with text taller than the maximum height of its layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "cli", serde(rename_all = "kebab-case"))]
+pub enum Overflow {
+    /// Cuts off what is below the maximum height.
+    Clip,
+    /// Scales the text down, keeping its proportions, until it fits.
+    #[default]
+    Shrink,
+    /// Drops the lines that don't fit, ending the last line kept with an ellipsis. This needs
+    /// the text to be wrapped at a width, otherwise it is clipped instead.
+    Ellipsize,
+}
+
 impl<'a> LayoutAttr<'a> {
-    pub fn configure(&self, ctx: &pango::Context, layout: &pango::Layout) {
+    pub fn configure(
+        &self,
+        ctx: &pango::Context,
+        layout: &pango::Layout,
+        opt: &mut cairo::FontOptions,
+    ) {
         match self {
             Self::Dpi(x) => pangocairo::functions::context_set_resolution(&ctx, *x),
             Self::Direction(x) => ctx.set_base_dir((*x).into()),
             Self::Gravity(x) => ctx.set_base_gravity((*x).into()),
             Self::GravityHint(x) => ctx.set_gravity_hint((*x).into()),
             Self::Language(x) => ctx.set_language(Some(&pango::Language::from_string(x))),
+            Self::Antialias(x) => opt.set_antialias((*x).into()),
+            Self::HintMetrics(x) => opt.set_hint_metrics((*x).into()),
+            Self::HintStyle(x) => opt.set_hint_style((*x).into()),
+            Self::SubpixelOrder(x) => opt.set_subpixel_order((*x).into()),
             Self::Alignment(x) => layout.set_alignment((*x).into()),
             Self::AutoDir(x) => layout.set_auto_dir(*x),
             Self::Indent(x) => layout.set_indent((x * pango::SCALE as f64) as i32),
             Self::Justify(x) => layout.set_justify(*x),
+            Self::JustifyLastLine(x) => layout.set_justify_last_line(*x),
             Self::LineSpacing(x) => layout.set_line_spacing(*x as f32),
             Self::Spacing(x) => layout.set_spacing((x * pango::SCALE as f64) as i32),
             Self::Width(x) => layout.set_width(x * pango::SCALE),
             Self::Wrap(x) => layout.set_wrap((*x).into()),
+            Self::Height(x) => {
+                layout.set_height(x * pango::SCALE);
+                layout.set_ellipsize(pango::EllipsizeMode::End);
+            }
+            Self::LetterSpacing(_) | Self::LineHeight(_) => {}
         }
     }
+
+    /// Attribute applied over the whole text, which markup spans may override.
+    pub fn base_attr(&self) -> Option<pango::Attribute> {
+        match self {
+            Self::LetterSpacing(x) => Some(pango::AttrInt::new_letter_spacing(*x).into()),
+            Self::LineHeight(x) => Some(pango::AttrFloat::new_line_height(*x).into()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pango::prelude::FontMapExt;
+
+    /// A layout of two inline images of 10 by 10 pixels, which are laid out by the size of
+    /// their shapes regardless of the fonts installed.
+    fn images(dir: pango::Direction) -> (pango::Layout, [u32; 2]) {
+        let ctx = pangocairo::FontMap::new().create_context();
+        ctx.set_base_dir(dir);
+        let layout = pango::Layout::new(&ctx);
+        layout.set_auto_dir(false);
+        let text = "\u{FFFC}\u{FFFC}";
+        let len = '\u{FFFC}'.len_utf8() as u32;
+        let size = 10 * pango::SCALE;
+        let rect = pango::Rectangle::new(0, -size, size, size);
+        let attrs = pango::AttrList::new();
+        for i in [0, len] {
+            let j = i + len;
+            attrs.insert(indexed!(pango::AttrShape::new(&rect, &rect); at i, j));
+        }
+        layout.set_text(text);
+        layout.set_attributes(Some(&attrs));
+        (layout, [0, len])
+    }
+
+    #[test]
+    fn shapes_are_placed_from_their_left_edge() {
+        let (layout, [a, b]) = images(pango::Direction::Ltr);
+        assert_eq!(shape_position(&layout, a), (0, 0));
+        assert_eq!(shape_position(&layout, b), (10, 0));
+
+        // right to left, the first image is drawn rightmost
+        let (layout, [a, b]) = images(pango::Direction::Rtl);
+        assert_eq!(shape_position(&layout, a), (10, 0));
+        assert_eq!(shape_position(&layout, b), (0, 0));
+    }
+
+    /// Left and right edges, in pango units, of the character at a byte index.
+    fn edges(layout: &pango::Layout, index: usize) -> (i32, i32) {
+        let rect = layout.index_to_pos(index as i32);
+        let (a, b) = (rect.x(), rect.x() + rect.width());
+        (a.min(b), a.max(b))
+    }
+
+    #[test]
+    fn shapes_in_right_to_left_runs_are_placed_visually() {
+        let ctx = pangocairo::FontMap::new().create_context();
+        ctx.set_base_dir(pango::Direction::Ltr);
+        let layout = pango::Layout::new(&ctx);
+        layout.set_auto_dir(false);
+        // the image is between hebrew letters, so it flows with them from right to left
+        // even though the paragraph is left to right, and is drawn as `דג [image] בא`
+        let text = "\u{5D0}\u{5D1}\u{FFFC}\u{5D2}\u{5D3}";
+        let i = text.find('\u{FFFC}').unwrap();
+        let j = i + '\u{FFFC}'.len_utf8();
+        let size = 10 * pango::SCALE;
+        let rect = pango::Rectangle::new(0, -size, size, size);
+        let attrs = pango::AttrList::new();
+        let (start, end) = (i as u32, j as u32);
+        attrs.insert(indexed!(pango::AttrShape::new(&rect, &rect); at start, end));
+        layout.set_text(text);
+        layout.set_attributes(Some(&attrs));
+
+        let (x, _) = shape_position(&layout, start);
+        // the letter after the image is to its left, and the one before it is to its right
+        let (_, after) = edges(&layout, j);
+        let (before, _) = edges(&layout, text.find('\u{5D1}').unwrap());
+        assert!(after > 0, "{after}");
+        assert_eq!(x, after / pango::SCALE);
+        assert!(before >= (x + 10) * pango::SCALE, "{before} {x}");
+    }
 }
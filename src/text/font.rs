@@ -4,9 +4,17 @@ use crate::error::{Error, Result};
 
 use fontconfig::{Fontconfig, Pattern};
 use fontconfig_sys::fontconfig as sys;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{collections::HashMap, ffi::CString};
 
+/// Number of font files added to fontconfig so far, so that font maps cached before a font
+/// was added can be replaced with ones that see it.
+pub(crate) static FONT_FILES_ADDED: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Debug, Clone)]
 pub enum FontPath {
     Path(PathBuf),
@@ -16,6 +24,7 @@ pub enum FontPath {
 pub struct FontMap {
     fc: Fontconfig,
     loaded: HashMap<String, String>,
+    default: Option<String>,
 }
 
 impl std::fmt::Debug for FontMap {
@@ -29,9 +38,35 @@ impl FontMap {
         Ok(Self {
             fc: fontconfig::Fontconfig::new().ok_or(Error::FontMapInit)?,
             loaded: HashMap::new(),
+            default: None,
         })
     }
 
+    /// Sets the key of the font used when none is given, which must already be loaded.
+    pub fn set_default(&mut self, key: String) -> Result<()> {
+        if !self.loaded.contains_key(&key) {
+            return Err(Error::font_missing(key));
+        }
+        self.default = Some(key);
+        Ok(())
+    }
+
+    /// Fonts loaded so far, each key mapped to the name fontconfig resolved it to, which may
+    /// be a fallback when a family is not installed, e.g. to find why text uses a wrong face.
+    pub fn loaded(&self) -> &HashMap<String, String> {
+        &self.loaded
+    }
+
+    /// Key of the font used when none is given: the one set with `set_default`, the only
+    /// font loaded if there is a single one, or else `default`.
+    pub fn default_key(&self) -> &str {
+        match (&self.default, self.loaded.len()) {
+            (Some(key), _) => key,
+            (None, 1) => self.loaded.keys().next().unwrap(),
+            (None, _) => "default",
+        }
+    }
+
     pub fn get(&self, key: &str) -> Option<&str> {
         self.loaded.get(key).map(|s| s.as_str())
     }
@@ -104,6 +139,7 @@ impl FontMap {
         if status == 0 {
             Err(Error::font_file_load(&key, &path))
         } else {
+            FONT_FILES_ADDED.fetch_add(1, Ordering::Release);
             let name = pat
                 .font_match()
                 .name()
@@ -115,6 +151,41 @@ impl FontMap {
         }
     }
 
+    /// Loads a font from the contents of a font file, e.g. one embedded in the program with
+    /// `include_bytes!`. Fontconfig only reads fonts from files, so the contents are written
+    /// to the temporary folder first, named after their hash so that they're written once.
+    ///
+    /// As anyone can write to the temporary folder, a file found there is only used if it
+    /// holds the same contents. Otherwise, the font is loaded from a copy of this process.
+    pub fn load_font_from_bytes(&mut self, key: String, bytes: &[u8]) -> Result<()> {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let path = std::env::temp_dir().join(format!("cartomata-font-{:016x}", hasher.finish()));
+        if fs::read(&path).is_ok_and(|found| found == bytes) {
+            return self.load_font_from_file(key, path);
+        }
+        // written under another name first, so that no other process sees it half written
+        let own = path.with_extension(std::process::id().to_string());
+        Self::write_new(&own, bytes).map_err(Error::io_error)?;
+        if path.exists() {
+            self.load_font_from_file(key, own)
+        } else {
+            fs::rename(&own, &path).map_err(Error::io_error)?;
+            self.load_font_from_file(key, path)
+        }
+    }
+
+    /// Writes a file that must not exist yet, so that a link left at its path can't redirect
+    /// the write elsewhere.
+    fn write_new(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+        // left behind by an earlier process with the same id
+        if path.symlink_metadata().is_ok() {
+            fs::remove_file(path)?;
+        }
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(path)?;
+        file.write_all(bytes)
+    }
+
     fn load_pattern_from_file<'s>(&'s self, c_fp: &CString) -> Option<Pattern<'s>> {
         unsafe {
             let set = sys::FcFontSetCreate();
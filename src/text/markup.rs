@@ -1,6 +1,8 @@
 use crate::error::Result;
 use crate::image::Color;
-use crate::text::attr::{Gravity, ITagAttr, ImgAttr, Points, Scale, SpanAttr, TagAttr};
+use crate::text::attr::{
+    BaselineShift, Gravity, ITagAttr, ImgAttr, Points, Scale, SpanAttr, TagAttr,
+};
 use crate::text::parser::TextParser;
 
 #[derive(Debug, Clone)]
@@ -16,6 +18,70 @@ impl Markup {
         TextParser::new(markup).parse()
     }
 
+    /// Creates a markup with only the given text, which is not parsed, so it needs no escaping.
+    pub fn plain(text: impl Into<String>) -> Self {
+        Self::root(vec![Self::text(text)])
+    }
+
+    /// Creates a markup with a sequence of elements.
+    ///
+    /// # Example
+    /// ```
+    /// use cartomata::image::Color;
+    /// use cartomata::text::attr::{ImgAttr, SpanAttr};
+    /// use cartomata::text::Markup;
+    ///
+    /// let markup = Markup::root(vec![
+    ///     Markup::text("Deals 3 "),
+    ///     Markup::img(ImgAttr::icon("fire.png")),
+    ///     Markup::text(" damage to "),
+    ///     Markup::span(vec![SpanAttr::Color(Color::BLACK)], vec![Markup::text("<all>")]),
+    /// ]);
+    /// ```
+    pub fn root(children: Vec<Markup>) -> Self {
+        Self::Root(children)
+    }
+
+    /// Creates a text element, which is not parsed, so it needs no escaping.
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::Text(text.into())
+    }
+
+    /// Creates a span with attributes applied over its elements.
+    pub fn span(attrs: Vec<SpanAttr>, children: Vec<Markup>) -> Self {
+        Self::SpanTag(attrs, children)
+    }
+
+    /// Creates a span raised above the baseline and made smaller, e.g. for `m²`. Written in
+    /// markup as `<sup/2>`, which is the same as
+    /// `<span baseline-shift="superscript" scale="small"/2>`, and also takes span attributes.
+    ///
+    /// # Example
+    /// ```
+    /// use cartomata::text::Markup;
+    ///
+    /// assert!(Markup::from_string("10 m<sup/2>, H<sub/2>O<sup color=\"#ff0000\"/*>").is_ok());
+    /// ```
+    pub fn sup(children: Vec<Markup>) -> Self {
+        Self::script(BaselineShift::Superscript, children)
+    }
+
+    /// Creates a span lowered below the baseline and made smaller, e.g. for `H₂O`, like
+    /// [`Markup::sup`]. Written in markup as `<sub/2>`.
+    pub fn sub(children: Vec<Markup>) -> Self {
+        Self::script(BaselineShift::Subscript, children)
+    }
+
+    fn script(shift: BaselineShift, children: Vec<Markup>) -> Self {
+        let scale = Scale(pango::SCALE_SMALL);
+        Self::SpanTag(vec![SpanAttr::BaselineShift(shift), SpanAttr::Scale(scale)], children)
+    }
+
+    /// Creates an inline image.
+    pub fn img(attr: ImgAttr) -> Self {
+        Self::ImgTag(attr)
+    }
+
     pub fn push_attr(&mut self, key: &str, value: &str) -> Result<()> {
         match self {
             Self::SpanTag(attrs, _) => attrs.push(SpanAttr::from_key_value(key, value)?),
@@ -0,0 +1,94 @@
+//! Formatting of numbers for display, with grouped digits.
+
+use itertools::Itertools;
+use serde::Deserialize;
+
+/// Options to format numbers for display, such as `1,000,000` or `1.000.000,50`, without
+/// depending on the system locale.
+///
+/// # Example
+/// ```
+/// use cartomata::text::NumberFormat;
+///
+/// let format = NumberFormat::default();
+/// assert_eq!(format.format_int(1000000), "1,000,000");
+/// assert_eq!(format.format_int(-999), "-999");
+/// assert_eq!(format.format_float(1234.5), "1,234.5");
+///
+/// let format = NumberFormat {
+///     separator: String::from("."),
+///     decimal_point: String::from(","),
+///     decimals: Some(2),
+///     ..Default::default()
+/// };
+/// assert_eq!(format.format_int(1000000), "1.000.000,00");
+/// assert_eq!(format.format_float(-1234.567), "-1.234,57");
+/// assert_eq!(format.format_float(-0.001), "0,00");
+///
+/// let format = NumberFormat { separator: String::from(" "), grouping: 4, ..Default::default() };
+/// assert_eq!(format.format_int(123456789), "1 2345 6789");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct NumberFormat {
+    /// Placed between groups of digits of the integer part. Defaults to `,`.
+    pub separator: String,
+    /// Placed between the integer and the fractional part. Defaults to `.`.
+    pub decimal_point: String,
+    /// Number of digits in each group, or `0` to not group them. Defaults to `3`.
+    pub grouping: usize,
+    /// Number of decimal places numbers are rounded to. By default, integers have none and
+    /// other numbers have as many as needed.
+    pub decimals: Option<usize>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            separator: String::from(","),
+            decimal_point: String::from("."),
+            grouping: 3,
+            decimals: None,
+        }
+    }
+}
+
+impl NumberFormat {
+    pub fn format_int(&self, n: i64) -> String {
+        let frac = "0".repeat(self.decimals.unwrap_or(0));
+        self.join(n < 0, &n.unsigned_abs().to_string(), &frac)
+    }
+
+    /// Formats a number, which is written as is if not finite.
+    pub fn format_float(&self, n: f64) -> String {
+        if !n.is_finite() {
+            return n.to_string();
+        }
+        let digits = match self.decimals {
+            Some(decimals) => format!("{:.*}", decimals, n.abs()),
+            None => n.abs().to_string(),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((&digits, ""));
+        // numbers rounded to zero are written without a sign
+        let negative = n < 0.0 && digits.chars().any(|c| c.is_ascii_digit() && c != '0');
+        self.join(negative, int, frac)
+    }
+
+    fn join(&self, negative: bool, int: &str, frac: &str) -> String {
+        let mut s = String::from(if negative { "-" } else { "" });
+        let digits: Vec<char> = int.chars().collect();
+        match self.grouping {
+            0 => s.push_str(int),
+            grouping => {
+                let groups = digits.rchunks(grouping).rev();
+                let mut groups = groups.map(|group| group.iter().collect::<String>());
+                s.push_str(&groups.join(&self.separator));
+            }
+        }
+        if !frac.is_empty() {
+            s.push_str(&self.decimal_point);
+            s.push_str(frac);
+        }
+        s
+    }
+}
@@ -27,6 +27,10 @@ enum Token {
     TypeImg,
     #[token("icon")]
     TypeIcon,
+    #[token("sup")]
+    TypeSup,
+    #[token("sub")]
+    TypeSub,
     #[regex("[a-z][a-z0-9-]*")]
     Key,
     #[token("=")]
@@ -57,6 +61,8 @@ impl fmt::Display for Token {
             Self::TypeSpan => write!(f, "`span`"),
             Self::TypeImg => write!(f, "`img`"),
             Self::TypeIcon => write!(f, "`icon`"),
+            Self::TypeSup => write!(f, "`sup`"),
+            Self::TypeSub => write!(f, "`sub`"),
             Self::Key => write!(f, "a key"),
             Self::Eq => write!(f, "="),
             Self::Value => write!(f, "a value"),
@@ -67,27 +73,116 @@ impl fmt::Display for Token {
     }
 }
 
+/// Escapes text, e.g. from card data, so that it can be included in markup without being
+/// parsed: `<` and `>` are escaped with a backslash, and `&` and `\` are replaced with
+/// character references, so that they don't start escapes themselves.
+///
+/// # Example
+/// ```
+/// use cartomata::text::{escape, unescape, Markup};
+///
+/// let name = "<Unnamed>";
+/// let markup = format!("<span color=\"#ff0000\"/{}>", escape(name));
+/// assert_eq!(markup, r##"<span color="#ff0000"/\<Unnamed\>>"##);
+/// assert!(Markup::from_string(&markup).is_ok());
+/// assert_eq!(unescape(&escape(name)), name);
+///
+/// let text = r"Salt &amp; \u2605";
+/// assert_eq!(unescape(&escape(text)), text);
+/// ```
 pub fn escape(text: &str) -> String {
-    let re = Regex::new(r"([<>])").unwrap();
-    re.replace_all(text, r"\$1").to_string()
+    let re = Regex::new(r"[<>&\\]").unwrap();
+    re.replace_all(text, |captures: &regex::Captures| match &captures[0] {
+        "&" => "&amp;",
+        "\\" => "&#92;",
+        "<" => "\\<",
+        _ => "\\>",
+    })
+    .to_string()
 }
 
+/// Reverts [`escape`], and replaces the escapes that markup text may contain:
+///
+/// - `\<` and `\>`, for `<` and `>`;
+/// - `\uXXXX` and `\u{X...}`, for the unicode character with that hexadecimal code point;
+/// - `&#N;` and `&#xX;`, for the unicode character with that decimal or hexadecimal code point;
+/// - named entities, such as `&amp;`, `&lt;`, `&gt;`, `&quot;`, `&apos;`, `&nbsp;`,
+///   `&bull;`, `&middot;`, `&times;`, `&ndash;`, `&mdash;`, `&hellip;`, `&deg;`,
+///   `&plusmn;`, `&copy;`, `&reg;`, `&trade;`, `&star;` (☆) and `&starf;` (★).
+///
+/// Anything else, such as an unknown entity, is kept as it is.
+///
+/// # Example
+/// ```
+/// use cartomata::text::unescape;
+///
+/// let text = r"\<3 \u2605 \u{1F525} &#x2606; &mdash; &unknown;";
+/// assert_eq!(unescape(text), "<3 ★ 🔥 ☆ — &unknown;");
+/// ```
 pub fn unescape(text: &str) -> String {
-    let re = Regex::new(r"\\([<>])").unwrap();
-    re.replace_all(text, r"$1").to_string()
+    let re = Regex::new(
+        r"\\([<>])|\\u\{([0-9a-fA-F]{1,6})\}|\\u([0-9a-fA-F]{4})|&#([0-9]{1,7});|&#[xX]([0-9a-fA-F]{1,6});|&([a-zA-Z]+);",
+    )
+    .unwrap();
+    re.replace_all(text, |captures: &regex::Captures| {
+        let code = |i: usize, radix: u32| {
+            let digits = captures.get(i)?.as_str();
+            let c = char::from_u32(u32::from_str_radix(digits, radix).ok()?)?;
+            Some(c.to_string())
+        };
+        let replaced = match captures.get(1) {
+            Some(c) => Some(c.as_str().to_string()),
+            None => code(2, 16)
+                .or_else(|| code(3, 16))
+                .or_else(|| code(4, 10))
+                .or_else(|| code(5, 16))
+                .or_else(|| {
+                    let name = captures.get(6)?.as_str();
+                    let (_, c) = ENTITIES.iter().find(|(entity, _)| *entity == name)?;
+                    Some(c.to_string())
+                }),
+        };
+        replaced.unwrap_or_else(|| captures[0].to_string())
+    })
+    .to_string()
 }
 
+/// Named character references accepted by [`unescape`].
+const ENTITIES: &[(&str, &str)] = &[
+    ("amp", "&"),
+    ("lt", "<"),
+    ("gt", ">"),
+    ("quot", "\""),
+    ("apos", "'"),
+    ("nbsp", "\u{a0}"),
+    ("bull", "•"),
+    ("middot", "·"),
+    ("times", "×"),
+    ("ndash", "–"),
+    ("mdash", "—"),
+    ("hellip", "…"),
+    ("deg", "°"),
+    ("plusmn", "±"),
+    ("copy", "©"),
+    ("reg", "®"),
+    ("trade", "™"),
+    ("star", "☆"),
+    ("starf", "★"),
+];
+
+/// Unescapes an attribute value, removing its quotes. Besides `\"`, values accept the same
+/// escapes as text.
 fn unescape_val(text: &str) -> String {
     let len = text.len();
     let re = Regex::new(r#"\\""#).unwrap();
-    re.replace_all(&text[1..len - 1], "\"").to_string()
+    unescape(&re.replace_all(&text[1..len - 1], "\""))
 }
 
 /// Parses text into text fragments that can be later turned
 /// into a text layout.
 // Grammar:
 // (Markup) M → ϵ | text M | < T M
-// (Tag)    T → span A / M > | img A / > | icon A / >
+// (Tag)    T → span A / M > | sup A / M > | sub A / M > | img A / > | icon A / >
 // (Attrs)  A → ϵ | key = value A
 pub struct TextParser<'src> {
     text_lexer: Lexer<'src, TextToken>,
@@ -229,6 +324,21 @@ impl<'src> TextParser<'src> {
                         Symbol::Token(Token::TypeSpan),
                     ]);
                 }
+                (Symbol::T, Some(tag @ (Token::TypeSup | Token::TypeSub))) => {
+                    // T → sup A / M > | sub A / M >
+                    elems.push(match tag {
+                        Token::TypeSup => Markup::sup(Vec::new()),
+                        _ => Markup::sub(Vec::new()),
+                    });
+                    stack.extend([
+                        Symbol::M,
+                        Symbol::Token(Token::TagClose),
+                        Symbol::M,
+                        Symbol::Token(Token::TagSep),
+                        Symbol::A,
+                        Symbol::Token(tag),
+                    ]);
+                }
                 (Symbol::T, Some(Token::TypeImg)) => {
                     // T → img A / >
                     elems.push(Markup::ImgTag(ImgAttr::new()));